@@ -3,8 +3,6 @@ extends GDPlayer
 @onready var input_manager: GDInputManager = $"../GDInputManager"
 @onready var sprite: AnimatedSprite2D = $AnimatedSprite2D
 
-@export var boat_speed = 500.0;
-
 var orientation := 0
 
 const ANIMATION_FRAMES = [
@@ -29,10 +27,9 @@ func _physics_process(_delta: float) -> void:
 			"move_down",
 		)
 		
-		velocity = direction * boat_speed
-		move_and_slide()
-		
 		input_manager.add_direction_input(direction)
+		
+		predict(input_manager)
 	else:
 		direction = replicated_velocity
 	
@@ -47,5 +44,5 @@ func _physics_process(_delta: float) -> void:
 	var animation = ANIMATION_FRAMES[clampf(orientation / 45.0, 0, 7)];
 	sprite.play(state + "_" + animation)
 
-func _on_boat_deserialize(snap1: Array[int], snap2: Array[int], alpha: float) -> void:
-	call_deferred("deserialize_bytes", snap1, snap2, alpha)
+func _on_boat_deserialize(state: Dictionary) -> void:
+	call_deferred("apply_state", state)
@@ -0,0 +1,137 @@
+﻿//! Measures the server delta snapshot path and counts heap allocations per snapshot.
+//!
+//! Run with `cargo bench -p common --bench snapshot`.
+
+use common::bit_writer::BitWriter;
+use common::player_state::{PLAYER_TYPE_ID, PlayerState};
+use common::replicated_node::ReplicatedNode;
+use common::snapshot::{DeltaSnapshot, Snapshot};
+use common::stream_reader::StreamReader;
+use common::stream_writer::StreamWriter;
+use glm::Vec2;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::borrow::Cow;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+const ITERATIONS: usize = 10_000;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn node(net_id: u32, x: f32) -> ReplicatedNode<'static> {
+    let state = PlayerState {
+        position: Vec2::new(x, 500.0 - net_id as f32),
+        velocity: Vec2::new(120.0, -35.5),
+        owner_id: net_id,
+    };
+    let mut bit_writer = BitWriter::new();
+    bit_writer.write_serializable(state);
+    ReplicatedNode {
+        net_id,
+        type_id: PLAYER_TYPE_ID,
+        data: Cow::Owned(bit_writer.into_bytes()),
+    }
+}
+
+/// A baseline of `count` nodes and the snapshot a few frames later, where half
+/// of them moved, one left and one joined. Nodes are sorted by `net_id`, as
+/// in the server's history.
+fn snapshots(count: u32) -> (Snapshot<'static>, Snapshot<'static>) {
+    let baseline = Snapshot {
+        frame: 40,
+        nodes: (0..count).map(|i| node(i, i as f32 * 10.0)).collect(),
+    };
+    let current = Snapshot {
+        frame: 42,
+        nodes: (1..=count)
+            .map(|i| node(i, i as f32 * 10.0 + (i % 2) as f32 * 25.0))
+            .collect(),
+    };
+    (baseline, current)
+}
+
+/// The path used by the server: one long-lived writer, the delta written
+/// straight from the packed states.
+fn write_in_place(stream_writer: &mut StreamWriter, current: &Snapshot, baseline: &Snapshot) {
+    stream_writer.clear();
+    DeltaSnapshot::write_from(stream_writer, current, Some(baseline), Some(7), Some(2));
+}
+
+/// Building a `DeltaSnapshot` first, which owns a byte buffer per changed node.
+fn write_owned(stream_writer: &mut StreamWriter, current: &Snapshot, baseline: &Snapshot) {
+    stream_writer.clear();
+    let mut delta = DeltaSnapshot::encode(current, Some(baseline));
+    delta.last_input = Some(7);
+    delta.input_lead = Some(2);
+    stream_writer.write_serializable(delta);
+}
+
+type Write = fn(&mut StreamWriter, &Snapshot, &Snapshot);
+
+fn measure(name: &str, current: &Snapshot, baseline: &Snapshot, write: Write) -> usize {
+    let mut stream_writer = StreamWriter::new();
+    // Lets the reused buffers reach their final capacity.
+    write(&mut stream_writer, current, baseline);
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        write(&mut stream_writer, black_box(current), black_box(baseline));
+        black_box(stream_writer.get_data());
+    }
+    let elapsed = start.elapsed();
+    let allocations = (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / ITERATIONS;
+
+    println!(
+        "{name:>8} {:>4} nodes: {:>8.0} ns/snapshot, {allocations:>4} allocations/snapshot, {} bytes",
+        current.nodes.len(),
+        elapsed.as_nanos() as f64 / ITERATIONS as f64,
+        stream_writer.get_data().len()
+    );
+    allocations
+}
+
+fn main() {
+    for count in [1, 16, 256] {
+        let (baseline, current) = snapshots(count);
+
+        let in_place = measure("in place", &current, &baseline, write_in_place);
+        let owned = measure("owned", &current, &baseline, write_owned);
+        assert_eq!(in_place, 0, "delta snapshot path allocated");
+
+        let mut stream_writer = StreamWriter::new();
+        write_in_place(&mut stream_writer, &current, &baseline);
+        let in_place_bytes = stream_writer.get_data().to_vec();
+        write_owned(&mut stream_writer, &current, &baseline);
+        assert_eq!(in_place_bytes, stream_writer.get_data());
+
+        let delta: DeltaSnapshot = StreamReader::new(&in_place_bytes)
+            .try_read_serializable()
+            .unwrap();
+        let rebuilt = delta.apply(Some(&baseline)).unwrap();
+        assert_eq!(rebuilt.nodes.len(), count as usize);
+        assert!(owned > in_place);
+    }
+}
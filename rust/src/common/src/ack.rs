@@ -0,0 +1,191 @@
+﻿use crate::stream_reader::Deserializable;
+use crate::stream_writer::Serializable;
+use std::collections::VecDeque;
+
+/// Received packets acknowledged by `ack_bits` besides `ack` itself.
+pub const ACK_BITS: u16 = 32;
+
+/// Sent packets still waiting for an acknowledgement past this are counted lost
+/// without waiting for the peer, which has likely gone away.
+const MAX_PENDING: usize = 1024;
+
+/// Sequencing part of the `MessageHeader`.
+///
+/// `sequence` numbers the packets sent on a connection, `ack` is the newest
+/// sequence received from the peer and bit `n` of `ack_bits` is set when
+/// `ack - n - 1` was received too.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serializable, Deserializable)]
+pub struct AckHeader {
+    pub sequence: u16,
+    pub ack: u16,
+    pub ack_bits: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketStatus {
+    Delivered,
+    Lost,
+}
+
+/// Whether `s1` is newer than `s2`, allowing for wrap around.
+pub fn sequence_greater_than(s1: u16, s2: u16) -> bool {
+    (s1 > s2 && s1 - s2 <= 32768) || (s1 < s2 && s2 - s1 > 32768)
+}
+
+/// Sequence numbers and acknowledgements of one connection.
+///
+/// Every sequenced packet sent takes its `AckHeader` from `send`, every one
+/// received goes through `receive`, which reports what became of the packets
+/// sent so far. Packets only acknowledged once applied go through
+/// `receive_unacknowledged` and `acknowledge` instead. A packet is lost once the peer acknowledges packets more than
+/// `ACK_BITS` newer without it.
+pub struct AckTracker {
+    local_sequence: u16,
+    remote_sequence: u16,
+    received_bits: u32,
+    pending: VecDeque<u16>,
+    pub delivered_packets: u32,
+    pub lost_packets: u32,
+    /// Received packets dropped because they were already seen or are too old
+    /// to be acknowledged.
+    pub duplicate_packets: u32,
+}
+
+impl Default for AckTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AckTracker {
+    pub fn new() -> Self {
+        Self {
+            local_sequence: 0,
+            // Nothing received yet: acknowledges a sequence no packet will
+            // carry before the window has moved far past it.
+            remote_sequence: u16::MAX,
+            received_bits: 0,
+            pending: VecDeque::new(),
+            delivered_packets: 0,
+            lost_packets: 0,
+            duplicate_packets: 0,
+        }
+    }
+
+    /// Numbers a packet about to be sent and acknowledges what was received.
+    pub fn send(&mut self) -> AckHeader {
+        let header = AckHeader {
+            sequence: self.local_sequence,
+            ack: self.remote_sequence,
+            ack_bits: self.received_bits,
+        };
+
+        if self.pending.len() >= MAX_PENDING {
+            self.pending.pop_front();
+            self.lost_packets += 1;
+        }
+        self.pending.push_back(self.local_sequence);
+        self.local_sequence = self.local_sequence.wrapping_add(1);
+        header
+    }
+
+    /// Records a received packet and calls `on_status` for each sent packet now
+    /// known to be delivered or lost. Returns false when the packet is a
+    /// duplicate or too old and should be dropped.
+    pub fn receive(
+        &mut self,
+        header: &AckHeader,
+        on_status: impl FnMut(u16, PacketStatus),
+    ) -> bool {
+        let fresh = self.receive_unacknowledged(header, on_status);
+        if fresh {
+            self.acknowledge(header.sequence);
+        }
+        fresh
+    }
+
+    /// Like `receive`, but leaves the packet to `acknowledge` once handled, so
+    /// that the peer counts it lost when it could not be.
+    pub fn receive_unacknowledged(
+        &mut self,
+        header: &AckHeader,
+        mut on_status: impl FnMut(u16, PacketStatus),
+    ) -> bool {
+        let ack = header.ack;
+        let ack_bits = header.ack_bits;
+        let mut delivered = 0;
+        let mut lost = 0;
+
+        self.pending.retain(|&sequence| {
+            let status = if sequence == ack {
+                PacketStatus::Delivered
+            } else if sequence_greater_than(sequence, ack) {
+                return true;
+            } else {
+                let distance = ack.wrapping_sub(sequence);
+                if distance > ACK_BITS {
+                    PacketStatus::Lost
+                } else if ack_bits & (1 << (distance - 1)) != 0 {
+                    PacketStatus::Delivered
+                } else {
+                    return true;
+                }
+            };
+
+            match status {
+                PacketStatus::Delivered => delivered += 1,
+                PacketStatus::Lost => lost += 1,
+            }
+            on_status(sequence, status);
+            false
+        });
+        self.delivered_packets += delivered;
+        self.lost_packets += lost;
+
+        let fresh = self.is_fresh(header.sequence);
+        if !fresh {
+            self.duplicate_packets += 1;
+        }
+        fresh
+    }
+
+    /// Acknowledges `sequence` in the next packets sent.
+    pub fn acknowledge(&mut self, sequence: u16) {
+        if !self.is_fresh(sequence) {
+            return;
+        }
+        if sequence_greater_than(sequence, self.remote_sequence) {
+            let shift = sequence.wrapping_sub(self.remote_sequence) as u32;
+            self.received_bits = if shift <= ACK_BITS as u32 {
+                (((self.received_bits as u64) << shift) | (1 << (shift - 1))) as u32
+            } else {
+                0
+            };
+            self.remote_sequence = sequence;
+        } else {
+            let distance = self.remote_sequence.wrapping_sub(sequence);
+            self.received_bits |= 1 << (distance - 1);
+        }
+    }
+
+    /// Whether `sequence` was neither acknowledged yet nor is too old to be.
+    fn is_fresh(&self, sequence: u16) -> bool {
+        if sequence == self.remote_sequence {
+            return false;
+        }
+        if sequence_greater_than(sequence, self.remote_sequence) {
+            return true;
+        }
+        let distance = self.remote_sequence.wrapping_sub(sequence);
+        distance <= ACK_BITS && self.received_bits & (1 << (distance - 1)) == 0
+    }
+
+    /// Share of the resolved packets that were lost.
+    pub fn packet_loss(&self) -> f32 {
+        let resolved = self.delivered_packets + self.lost_packets;
+        if resolved == 0 {
+            return 0.0;
+        }
+        self.lost_packets as f32 / resolved as f32
+    }
+}
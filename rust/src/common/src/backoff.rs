@@ -0,0 +1,49 @@
+﻿use std::time::{Duration, Instant};
+
+/// Spaces out the attempts at something that keeps failing: the delay starts
+/// at `initial` and doubles with each failure, up to `max`.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            failures: 0,
+            retry_at: None,
+        }
+    }
+
+    /// Records a failure at `now` and returns how long to wait before the next attempt.
+    pub fn fail(&mut self, now: Instant) -> Duration {
+        let delay = self
+            .initial
+            .saturating_mul(1 << self.failures.min(31))
+            .min(self.max);
+        self.failures = self.failures.saturating_add(1);
+        self.retry_at = Some(now + delay);
+        delay
+    }
+
+    /// Whether the delay since the last failure is over.
+    pub fn is_ready(&self, now: Instant) -> bool {
+        self.retry_at.is_none_or(|retry_at| now >= retry_at)
+    }
+
+    /// Failures since the last success.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Records a success, the next failure waits `initial` again.
+    pub fn reset(&mut self) {
+        self.failures = 0;
+        self.retry_at = None;
+    }
+}
@@ -0,0 +1,176 @@
+﻿use crate::bit_writer::bits_required;
+use crate::decode_error::DecodeError;
+use crate::quantization::{FixedPoint, decompress_unit_vec2, f16_to_f32, u16_to_angle};
+use glm::Vec2;
+
+pub trait BitDeserializable: Sized {
+    fn try_deserialize_bits(reader: &mut BitReader<'_>) -> Result<Self, DecodeError>;
+}
+
+/// Reads values written by a `BitWriter`.
+///
+/// Offsets and sizes reported in `DecodeError` are counted in bits.
+pub struct BitReader<'a> {
+    buffer: &'a [u8],
+    bit_cursor: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self {
+            buffer,
+            bit_cursor: 0,
+        }
+    }
+
+    /// Reads `bits` bits (in `0..=32`) into the low bits of the result.
+    pub fn try_read_bits(&mut self, bits: u32) -> Result<u32, DecodeError> {
+        debug_assert!(bits <= 32);
+        let available = self.bits_remaining();
+        if available < bits as usize {
+            return Err(DecodeError::unexpected_end(
+                self.bit_cursor,
+                bits as usize,
+                available,
+            ));
+        }
+
+        let mut value = 0u64;
+        let mut read = 0;
+        while read < bits {
+            let byte = self.buffer[self.bit_cursor / 8] as u64;
+            let bit_offset = (self.bit_cursor % 8) as u32;
+            let count = (8 - bit_offset).min(bits - read);
+            let chunk = (byte >> bit_offset) & ((1 << count) - 1);
+            value |= chunk << read;
+            read += count;
+            self.bit_cursor += count as usize;
+        }
+
+        Ok(value as u32)
+    }
+
+    pub fn try_read_bool(&mut self) -> Result<bool, DecodeError> {
+        Ok(self.try_read_bits(1)? != 0)
+    }
+
+    pub fn try_read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.try_read_bits(8)? as u8)
+    }
+
+    pub fn try_read_u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(self.try_read_bits(16)? as u16)
+    }
+
+    pub fn try_read_u32(&mut self) -> Result<u32, DecodeError> {
+        self.try_read_bits(32)
+    }
+
+    pub fn try_read_f32(&mut self) -> Result<f32, DecodeError> {
+        Ok(f32::from_bits(self.try_read_bits(32)?))
+    }
+
+    pub fn try_read_ranged_u32(&mut self, min: u32, max: u32) -> Result<u32, DecodeError> {
+        let offset = self.bit_cursor;
+        let value = self.try_read_bits(bits_required(max - min))?;
+        if value > max - min {
+            return Err(DecodeError::invalid_value(offset, value as usize));
+        }
+        Ok(min + value)
+    }
+
+    pub fn try_read_ranged_i32(&mut self, min: i32, max: i32) -> Result<i32, DecodeError> {
+        let offset = self.bit_cursor;
+        let range = max.wrapping_sub(min) as u32;
+        let value = self.try_read_bits(bits_required(range))?;
+        if value > range {
+            return Err(DecodeError::invalid_value(offset, value as usize));
+        }
+        Ok(min.wrapping_add(value as i32))
+    }
+
+    pub fn try_read_fixed_point(&mut self, format: &FixedPoint) -> Result<f32, DecodeError> {
+        Ok(format.dequantize(self.try_read_bits(format.bits())?))
+    }
+
+    pub fn try_read_fixed_point_vec2(&mut self, format: &FixedPoint) -> Result<Vec2, DecodeError> {
+        let x = self.try_read_fixed_point(format)?;
+        let y = self.try_read_fixed_point(format)?;
+        Ok(Vec2::new(x, y))
+    }
+
+    pub fn try_read_half_f32(&mut self) -> Result<f32, DecodeError> {
+        Ok(f16_to_f32(self.try_read_u16()?))
+    }
+
+    pub fn try_read_half_vec2(&mut self) -> Result<Vec2, DecodeError> {
+        let x = self.try_read_half_f32()?;
+        let y = self.try_read_half_f32()?;
+        Ok(Vec2::new(x, y))
+    }
+
+    pub fn try_read_angle(&mut self) -> Result<f32, DecodeError> {
+        Ok(u16_to_angle(self.try_read_u16()?))
+    }
+
+    pub fn try_read_unit_vec2(&mut self) -> Result<Vec2, DecodeError> {
+        Ok(decompress_unit_vec2(self.try_read_u16()?))
+    }
+
+    /// Skips the padding up to the next byte boundary.
+    pub fn align(&mut self) {
+        self.bit_cursor = self.bit_cursor.div_ceil(8) * 8;
+    }
+
+    pub fn try_read_serializable<T: BitDeserializable>(&mut self) -> Result<T, DecodeError> {
+        T::try_deserialize_bits(self)
+    }
+
+    pub fn try_read_serializable_vec<T: BitDeserializable>(
+        &mut self,
+        max_len: u32,
+    ) -> Result<Vec<T>, DecodeError> {
+        let len = self.try_read_ranged_u32(0, max_len)?;
+        let mut vec = Vec::with_capacity(len as usize);
+
+        for _ in 0..len {
+            vec.push(self.try_read_serializable()?);
+        }
+
+        Ok(vec)
+    }
+
+    pub fn bits_remaining(&self) -> usize {
+        self.buffer.len() * 8 - self.bit_cursor
+    }
+}
+
+impl BitDeserializable for bool {
+    fn try_deserialize_bits(reader: &mut BitReader<'_>) -> Result<Self, DecodeError> {
+        reader.try_read_bool()
+    }
+}
+
+impl BitDeserializable for u8 {
+    fn try_deserialize_bits(reader: &mut BitReader<'_>) -> Result<Self, DecodeError> {
+        reader.try_read_u8()
+    }
+}
+
+impl BitDeserializable for u16 {
+    fn try_deserialize_bits(reader: &mut BitReader<'_>) -> Result<Self, DecodeError> {
+        reader.try_read_u16()
+    }
+}
+
+impl BitDeserializable for u32 {
+    fn try_deserialize_bits(reader: &mut BitReader<'_>) -> Result<Self, DecodeError> {
+        reader.try_read_u32()
+    }
+}
+
+impl BitDeserializable for f32 {
+    fn try_deserialize_bits(reader: &mut BitReader<'_>) -> Result<Self, DecodeError> {
+        reader.try_read_f32()
+    }
+}
@@ -0,0 +1,182 @@
+﻿use crate::quantization::{FixedPoint, angle_to_u16, compress_unit_vec2, f32_to_f16};
+use glm::Vec2;
+
+pub trait BitSerializable {
+    fn serialize_bits(&self, writer: &mut BitWriter);
+}
+
+/// Number of bits needed to store any value in `0..=range`.
+pub fn bits_required(range: u32) -> u32 {
+    u32::BITS - range.leading_zeros()
+}
+
+/// Writes values with bit granularity, least significant bit first.
+///
+/// The last byte is zero padded when the writer is turned into bytes, so a
+/// `BitReader` reading the same sequence of calls gets the same values back.
+pub struct BitWriter {
+    buffer: Vec<u8>,
+    scratch: u64,
+    scratch_bits: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::with_buffer(vec![])
+    }
+
+    /// Writes into `buffer` after clearing it, reusing its capacity.
+    pub fn with_buffer(mut buffer: Vec<u8>) -> Self {
+        buffer.clear();
+        Self {
+            buffer,
+            scratch: 0,
+            scratch_bits: 0,
+        }
+    }
+
+    /// Writes the `bits` low bits of `value`. `bits` must be in `0..=32`.
+    pub fn write_bits(&mut self, value: u32, bits: u32) {
+        debug_assert!(bits <= 32);
+        if bits == 0 {
+            return;
+        }
+
+        let mask = (1u64 << bits) - 1;
+        self.scratch |= (value as u64 & mask) << self.scratch_bits;
+        self.scratch_bits += bits;
+
+        while self.scratch_bits >= 8 {
+            self.buffer.push(self.scratch as u8);
+            self.scratch >>= 8;
+            self.scratch_bits -= 8;
+        }
+    }
+
+    pub fn write_bool(&mut self, data: bool) {
+        self.write_bits(data as u32, 1);
+    }
+
+    pub fn write_u8(&mut self, data: u8) {
+        self.write_bits(data as u32, 8);
+    }
+
+    pub fn write_u16(&mut self, data: u16) {
+        self.write_bits(data as u32, 16);
+    }
+
+    pub fn write_u32(&mut self, data: u32) {
+        self.write_bits(data, 32);
+    }
+
+    pub fn write_f32(&mut self, data: f32) {
+        self.write_bits(data.to_bits(), 32);
+    }
+
+    /// Writes `data` clamped to `min..=max` using only the bits that range needs.
+    pub fn write_ranged_u32(&mut self, data: u32, min: u32, max: u32) {
+        debug_assert!(min <= max);
+        let value = data.clamp(min, max) - min;
+        self.write_bits(value, bits_required(max - min));
+    }
+
+    pub fn write_ranged_i32(&mut self, data: i32, min: i32, max: i32) {
+        debug_assert!(min <= max);
+        let value = data.clamp(min, max).wrapping_sub(min) as u32;
+        self.write_bits(value, bits_required(max.wrapping_sub(min) as u32));
+    }
+
+    pub fn write_fixed_point(&mut self, data: f32, format: &FixedPoint) {
+        self.write_bits(format.quantize(data), format.bits());
+    }
+
+    pub fn write_fixed_point_vec2(&mut self, data: Vec2, format: &FixedPoint) {
+        self.write_fixed_point(data.x, format);
+        self.write_fixed_point(data.y, format);
+    }
+
+    pub fn write_half_f32(&mut self, data: f32) {
+        self.write_u16(f32_to_f16(data));
+    }
+
+    pub fn write_half_vec2(&mut self, data: Vec2) {
+        self.write_half_f32(data.x);
+        self.write_half_f32(data.y);
+    }
+
+    pub fn write_angle(&mut self, angle: f32) {
+        self.write_u16(angle_to_u16(angle));
+    }
+
+    pub fn write_unit_vec2(&mut self, data: Vec2) {
+        self.write_u16(compress_unit_vec2(data));
+    }
+
+    /// Pads with zero bits up to the next byte boundary.
+    pub fn align(&mut self) {
+        let padding = (8 - self.scratch_bits % 8) % 8;
+        self.write_bits(0, padding);
+    }
+
+    pub fn bits_written(&self) -> usize {
+        self.buffer.len() * 8 + self.scratch_bits as usize
+    }
+
+    pub fn write_serializable<T: BitSerializable>(&mut self, data: T) {
+        data.serialize_bits(self);
+    }
+
+    pub fn write_serializable_ref<T: BitSerializable>(&mut self, data: &T) {
+        data.serialize_bits(self);
+    }
+
+    /// Writes a length in `0..=max_len` followed by the items.
+    pub fn write_serializable_slice<T: BitSerializable>(&mut self, data: &[T], max_len: u32) {
+        let len = (data.len() as u32).min(max_len);
+        self.write_ranged_u32(len, 0, max_len);
+        for serializable in &data[..len as usize] {
+            serializable.serialize_bits(self);
+        }
+    }
+
+    pub fn into_bytes(mut self) -> Vec<u8> {
+        self.align();
+        self.buffer
+    }
+}
+
+impl Default for BitWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BitSerializable for bool {
+    fn serialize_bits(&self, writer: &mut BitWriter) {
+        writer.write_bool(*self);
+    }
+}
+
+impl BitSerializable for u8 {
+    fn serialize_bits(&self, writer: &mut BitWriter) {
+        writer.write_u8(*self);
+    }
+}
+
+impl BitSerializable for u16 {
+    fn serialize_bits(&self, writer: &mut BitWriter) {
+        writer.write_u16(*self);
+    }
+}
+
+impl BitSerializable for u32 {
+    fn serialize_bits(&self, writer: &mut BitWriter) {
+        writer.write_u32(*self);
+    }
+}
+
+impl BitSerializable for f32 {
+    fn serialize_bits(&self, writer: &mut BitWriter) {
+        writer.write_f32(*self);
+    }
+}
@@ -0,0 +1,63 @@
+﻿use crate::sha256::{HmacSha256, constant_time_eq};
+use crate::stream_reader::Deserializable;
+use crate::stream_writer::Serializable;
+use std::time::Duration;
+
+/// Bytes of the HMAC kept in a `Cookie`.
+pub const COOKIE_MAC_SIZE: usize = 16;
+
+/// A cookie older than this is refused and the client is challenged again.
+pub const COOKIE_LIFETIME: Duration = Duration::from_secs(10);
+
+/// The client pads its `Helo` and `Hsk` to at least this size. The server never answers
+/// them with more bytes than it received, so spoofed requests cannot make it
+/// send a victim more traffic than the attacker sent.
+pub const HANDSHAKE_PACKET_SIZE: usize = 128;
+
+/// Body of the server's `Helo`, echoed by the client's `Hsk` after its
+/// `ProtocolInfo`. Proves the client receives packets at its address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serializable, Deserializable)]
+pub struct Cookie {
+    /// Seconds since the Unix epoch when the server issued it.
+    pub timestamp: u64,
+    pub mac: [u8; COOKIE_MAC_SIZE],
+}
+
+/// Issues and checks the cookies of the connection challenge.
+///
+/// A cookie is an HMAC of the client address and the time it was issued,
+/// keyed by a secret only the server knows, so nothing is stored per client
+/// until a `Hsk` brings back a valid one.
+pub struct CookieIssuer {
+    key: [u8; 32],
+}
+
+impl CookieIssuer {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key }
+    }
+
+    /// Cookie for `addr`, `now` being seconds since the Unix epoch.
+    pub fn issue(&self, addr: &str, now: u64) -> Cookie {
+        Cookie {
+            timestamp: now,
+            mac: self.mac(addr, now),
+        }
+    }
+
+    /// Whether `cookie` was issued by this server to `addr` and has not expired.
+    pub fn verify(&self, addr: &str, cookie: &Cookie, now: u64) -> bool {
+        let fresh = cookie.timestamp <= now && now - cookie.timestamp <= COOKIE_LIFETIME.as_secs();
+        fresh && constant_time_eq(&cookie.mac, &self.mac(addr, cookie.timestamp))
+    }
+
+    fn mac(&self, addr: &str, timestamp: u64) -> [u8; COOKIE_MAC_SIZE] {
+        let mut hmac = HmacSha256::new(&self.key);
+        hmac.update(&timestamp.to_le_bytes());
+        hmac.update(addr.as_bytes());
+
+        let mut mac = [0; COOKIE_MAC_SIZE];
+        mac.copy_from_slice(&hmac.finalize()[..COOKIE_MAC_SIZE]);
+        mac
+    }
+}
@@ -0,0 +1,216 @@
+﻿use crate::ping_request::PingResponse;
+use std::collections::VecDeque;
+
+/// Ping samples kept, the newest ones.
+pub const CLOCK_SAMPLES: usize = 16;
+
+/// Samples needed before the estimate is trusted enough to ping less often.
+pub const MIN_CLOCK_SAMPLES: usize = 4;
+
+/// Share of its rate the served clock speeds up or slows down by to catch up
+/// with the estimate, so frames keep flowing evenly after a correction.
+pub const MAX_SLEW_RATE: f64 = 0.05;
+
+/// Seconds past which the served clock jumps forward to the estimate, or
+/// stops until the estimate catches up with it, instead of slewing.
+pub const MAX_SLEW_ERROR: f64 = 0.25;
+
+/// Drift between the clocks fitted from the samples is trusted once they
+/// span this many seconds.
+const MIN_DRIFT_SPAN: f64 = 4.0;
+
+/// Bound of the drift fitted, well above that of real clocks so a jump of the
+/// offset is not taken for a fast clock.
+pub const MAX_DRIFT: f64 = 0.001;
+
+/// What one `PingResponse` tells about the server's clocks. Times are in
+/// seconds, on the client's clock unless said otherwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockSample {
+    /// Halfway through the round trip, when the server is assumed to have
+    /// answered.
+    pub local_time: f64,
+    pub rtt: f64,
+    /// Server wall clock minus the client's.
+    pub offset: f64,
+    /// Time the server simulated, its frame times the frame duration, minus
+    /// the client's clock.
+    pub frame_offset: f64,
+}
+
+impl ClockSample {
+    /// Sample of `response` received at `received_at`, in milliseconds on the
+    /// clock of `time_client_request`. None when the clock went back since.
+    pub fn new(response: &PingResponse, received_at: u64, frequency: f64) -> Option<Self> {
+        let sent_at = response.time_client_request;
+        if received_at < sent_at {
+            return None;
+        }
+
+        let local_time = (sent_at + received_at) as f64 / 2000.0;
+        Some(Self {
+            local_time,
+            rtt: (received_at - sent_at) as f64 / 1000.0,
+            offset: response.time_server_response as f64 / 1000.0 - local_time,
+            frame_offset: response.server_frame as f64 / frequency - local_time,
+        })
+    }
+}
+
+/// Server time as a line over the client's clock.
+#[derive(Debug, Clone, Copy)]
+struct Estimate {
+    local_time: f64,
+    server_time: f64,
+    /// Server seconds per client second, minus one.
+    drift: f64,
+}
+
+impl Estimate {
+    fn at(&self, local_time: f64) -> f64 {
+        self.server_time + (local_time - self.local_time) * (1.0 + self.drift)
+    }
+}
+
+/// Estimate of the server's clock from the answers to pings, NTP style.
+///
+/// Each `PingResponse` gives the server frame and wall clock halfway through
+/// the round trip. Samples delayed more than the median of the last
+/// `CLOCK_SAMPLES` are dropped, since queues only ever add delay and skew the
+/// halfway guess, and a line fitted through the rest gives the offset and the
+/// drift of the server. The time served does not follow new estimates at
+/// once but slews toward them, never going back, so frames derived from it
+/// step one by one.
+#[derive(Debug)]
+pub struct ClockSync {
+    frequency: f64,
+    samples: VecDeque<ClockSample>,
+    estimate: Option<Estimate>,
+    offset: f64,
+    rtt: f64,
+    /// Last time served, with the client time it was served at.
+    served: Option<(f64, f64)>,
+}
+
+impl ClockSync {
+    /// Clock of a server running `frequency` frames per second.
+    pub fn new(frequency: f64) -> Self {
+        Self {
+            frequency,
+            samples: VecDeque::new(),
+            estimate: None,
+            offset: 0.0,
+            rtt: 0.0,
+            served: None,
+        }
+    }
+
+    /// Takes `response`, received at `received_at` in milliseconds on the
+    /// clock of `time_client_request`, and returns its sample.
+    pub fn add_sample(&mut self, response: &PingResponse, received_at: u64) -> Option<ClockSample> {
+        let sample = ClockSample::new(response, received_at, self.frequency)?;
+        if self.samples.len() >= CLOCK_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+        self.update_estimate();
+        Some(sample)
+    }
+
+    pub fn is_synchronized(&self) -> bool {
+        self.samples.len() >= MIN_CLOCK_SAMPLES
+    }
+
+    /// Seconds the server wall clock is ahead of the client's.
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    /// Round trip time of the samples kept, in seconds.
+    pub fn rtt(&self) -> f64 {
+        self.rtt
+    }
+
+    /// Seconds drifted by the server per second of the client, positive when
+    /// its clock runs faster.
+    pub fn drift(&self) -> f64 {
+        self.estimate.map_or(0.0, |estimate| estimate.drift)
+    }
+
+    /// Seconds simulated by the server at `now`, in milliseconds on the clock
+    /// of the pings. None before the first sample.
+    pub fn server_time(&mut self, now: u64) -> Option<f64> {
+        let estimate = self.estimate?;
+        let now = now as f64 / 1000.0;
+        let target = estimate.at(now);
+
+        let Some((served_at, served)) = self.served else {
+            self.served = Some((now, target));
+            return Some(target);
+        };
+        if now <= served_at {
+            return Some(served);
+        }
+
+        let elapsed = now - served_at;
+        let predicted = served + elapsed * (1.0 + estimate.drift);
+        let error = target - predicted;
+        let time = if error > MAX_SLEW_ERROR {
+            target
+        } else if error < -MAX_SLEW_ERROR {
+            served.max(target)
+        } else {
+            let slew = elapsed * MAX_SLEW_RATE;
+            (predicted + error.clamp(-slew, slew)).max(served)
+        };
+        self.served = Some((now, time));
+        Some(time)
+    }
+
+    /// Frame the server is running at `now`, see `server_time`.
+    pub fn server_frame(&mut self, now: u64) -> Option<u32> {
+        let frequency = self.frequency;
+        self.server_time(now)
+            .map(|time| (time * frequency).max(0.0) as u32)
+    }
+
+    fn update_estimate(&mut self) {
+        let mut rtts: Vec<f64> = self.samples.iter().map(|sample| sample.rtt).collect();
+        rtts.sort_by(f64::total_cmp);
+        let median_rtt = rtts[(rtts.len() - 1) / 2];
+        let kept: Vec<&ClockSample> = self
+            .samples
+            .iter()
+            .filter(|sample| sample.rtt <= median_rtt)
+            .collect();
+
+        let count = kept.len() as f64;
+        let mean = |value: fn(&ClockSample) -> f64| {
+            kept.iter().map(|sample| value(sample)).sum::<f64>() / count
+        };
+        let local_time = mean(|sample| sample.local_time);
+        let frame_offset = mean(|sample| sample.frame_offset);
+        self.offset = mean(|sample| sample.offset);
+        self.rtt = mean(|sample| sample.rtt);
+
+        let span = kept.last().unwrap().local_time - kept[0].local_time;
+        let drift = if span >= MIN_DRIFT_SPAN {
+            let (covariance, variance) =
+                kept.iter()
+                    .fold((0.0, 0.0), |(covariance, variance), sample| {
+                        let dx = sample.local_time - local_time;
+                        let dy = sample.frame_offset - frame_offset;
+                        (covariance + dx * dy, variance + dx * dx)
+                    });
+            (covariance / variance).clamp(-MAX_DRIFT, MAX_DRIFT)
+        } else {
+            0.0
+        };
+
+        self.estimate = Some(Estimate {
+            local_time,
+            server_time: local_time + frame_offset,
+            drift,
+        });
+    }
+}
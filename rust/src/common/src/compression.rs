@@ -0,0 +1,327 @@
+﻿use crate::decode_error::DecodeError;
+use crate::fragment::{FRAGMENT_SIZE, MAX_FRAGMENTS};
+use crate::message_header::{MessageHeader, MessageType};
+use crate::stream_reader::StreamReader;
+use crate::stream_writer::StreamWriter;
+use std::borrow::Cow;
+
+/// Largest message a compressed body may expand to, the most fragments can carry.
+pub const MAX_DECOMPRESSED_SIZE: usize = MAX_FRAGMENTS * FRAGMENT_SIZE;
+
+const MIN_MATCH: usize = 4;
+const HASH_LOG: u32 = 12;
+const MAX_OFFSET: usize = u16::MAX as usize;
+/// The block ends with at least this many literals.
+const LAST_LITERALS: usize = 5;
+/// No match starts this close to the end of the block.
+const MATCH_FIND_LIMIT: usize = 12;
+
+/// Compression achieved on the messages of one type.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionStats {
+    pub messages: u32,
+    /// Messages sent compressed, the others did not get smaller.
+    pub compressed_messages: u32,
+    pub original_bytes: u64,
+    pub sent_bytes: u64,
+}
+
+impl CompressionStats {
+    /// Bytes sent per byte of original message, 1 when nothing was sent.
+    pub fn ratio(&self) -> f32 {
+        if self.original_bytes == 0 {
+            return 1.0;
+        }
+        self.sent_bytes as f32 / self.original_bytes as f32
+    }
+}
+
+/// Compression stage of a connection that negotiated it during the handshake.
+///
+/// The body of each sequenced message, everything after its `MessageHeader`,
+/// is compressed before the packet is sealed and sent compressed only when it
+/// shrinks, with `MessageHeader::COMPRESSED` set. Messages exchanged before the
+/// handshake completes are never compressed.
+#[derive(Default)]
+pub struct Compressor {
+    buffer: Vec<u8>,
+    block: Vec<u8>,
+    stats: Vec<(MessageType, CompressionStats)>,
+}
+
+impl Compressor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the packet to seal and send for `packet`, a message starting
+    /// with a `MessageHeader`: its compressed form when smaller, else `packet`.
+    pub fn compress<'a>(&'a mut self, packet: &'a mut [u8]) -> &'a mut [u8] {
+        let Ok(message_type) = StreamReader::new(packet)
+            .try_read_serializable::<MessageHeader>()
+            .map(|header| header.message_type)
+        else {
+            return packet;
+        };
+        if !message_type.is_sequenced() {
+            return packet;
+        }
+
+        let body = &packet[MessageHeader::SIZE..];
+        compress_block(body, &mut self.block);
+
+        // The body size goes first, for the receiver to check what it rebuilds.
+        let mut size_prefix = [0; 5];
+        let mut stream_writer = StreamWriter::from_slice(&mut size_prefix);
+        stream_writer.write_var_u32(body.len() as u32);
+        let size = MessageHeader::SIZE + stream_writer.get_data().len() + self.block.len();
+        let compressed = size < packet.len();
+
+        let stats = self.stats_mut(message_type);
+        stats.messages += 1;
+        stats.original_bytes += packet.len() as u64;
+        if !compressed {
+            stats.sent_bytes += packet.len() as u64;
+            return packet;
+        }
+        stats.compressed_messages += 1;
+        stats.sent_bytes += size as u64;
+
+        self.buffer.clear();
+        self.buffer
+            .extend_from_slice(&packet[..MessageHeader::SIZE]);
+        self.buffer.extend_from_slice(stream_writer.get_data());
+        self.buffer.extend_from_slice(&self.block);
+        let flags = MessageHeader::flags(&self.buffer) | MessageHeader::COMPRESSED;
+        MessageHeader::write_flags(&mut self.buffer, flags);
+        &mut self.buffer
+    }
+
+    pub fn stats(&self) -> &[(MessageType, CompressionStats)] {
+        &self.stats
+    }
+
+    pub fn stats_for(&self, message_type: MessageType) -> CompressionStats {
+        self.stats
+            .iter()
+            .find(|(stats_type, _)| *stats_type == message_type)
+            .map(|(_, stats)| *stats)
+            .unwrap_or_default()
+    }
+
+    fn stats_mut(&mut self, message_type: MessageType) -> &mut CompressionStats {
+        let index = match self
+            .stats
+            .iter()
+            .position(|(stats_type, _)| *stats_type == message_type)
+        {
+            Some(index) => index,
+            None => {
+                self.stats.push((message_type, CompressionStats::default()));
+                self.stats.len() - 1
+            }
+        };
+        &mut self.stats[index].1
+    }
+}
+
+/// Undoes `Compressor::compress` on a received message, which comes back as
+/// is when it was not compressed.
+pub fn decompress(message: &[u8]) -> Result<Cow<'_, [u8]>, DecodeError> {
+    if MessageHeader::flags(message) & MessageHeader::COMPRESSED == 0 {
+        return Ok(Cow::Borrowed(message));
+    }
+    if message.len() < MessageHeader::SIZE {
+        return Err(DecodeError::unexpected_end(
+            0,
+            MessageHeader::SIZE,
+            message.len(),
+        ));
+    }
+
+    let mut stream_reader = StreamReader::new(&message[MessageHeader::SIZE..]);
+    let offset = MessageHeader::SIZE;
+    let size = stream_reader.try_read_var_u32()? as usize;
+    if size > MAX_DECOMPRESSED_SIZE {
+        return Err(DecodeError::length_too_large(
+            offset,
+            MAX_DECOMPRESSED_SIZE,
+            size,
+        ));
+    }
+
+    let mut decompressed = Vec::with_capacity(MessageHeader::SIZE + size);
+    decompressed.extend_from_slice(&message[..MessageHeader::SIZE]);
+    let flags = MessageHeader::flags(&decompressed) & !MessageHeader::COMPRESSED;
+    MessageHeader::write_flags(&mut decompressed, flags);
+
+    let block_offset = offset + stream_reader.get_cursor();
+    decompress_block(&message[block_offset..], size, &mut decompressed).map_err(|e| {
+        DecodeError {
+            offset: e.offset + block_offset,
+            ..e
+        }
+    })?;
+    Ok(Cow::Owned(decompressed))
+}
+
+fn hash(sequence: u32) -> usize {
+    (sequence.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize
+}
+
+fn read_u32(input: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([input[at], input[at + 1], input[at + 2], input[at + 3]])
+}
+
+/// Writes a length past the 15 a token nibble holds, as 255s and a remainder.
+fn write_length(out: &mut Vec<u8>, mut length: usize) {
+    while length >= 255 {
+        out.push(255);
+        length -= 255;
+    }
+    out.push(length as u8);
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], offset_and_match: Option<(u16, usize)>) {
+    let literal_nibble = literals.len().min(15) as u8;
+    let match_length = offset_and_match.map_or(0, |(_, length)| length - MIN_MATCH);
+    let match_nibble = match_length.min(15) as u8;
+    out.push(literal_nibble << 4 | match_nibble);
+    if literals.len() >= 15 {
+        write_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+
+    if let Some((offset, _)) = offset_and_match {
+        out.extend_from_slice(&offset.to_le_bytes());
+        if match_length >= 15 {
+            write_length(out, match_length - 15);
+        }
+    }
+}
+
+/// Compresses `input` into `out` as an LZ4 block: sequences of literals
+/// followed by a copy of earlier output.
+pub fn compress_block(input: &[u8], out: &mut Vec<u8>) {
+    out.clear();
+    let mut table = [0u32; 1 << HASH_LOG];
+    let mut anchor = 0;
+    let mut i = 0;
+
+    if input.len() > MATCH_FIND_LIMIT {
+        let match_limit = input.len() - MATCH_FIND_LIMIT;
+        let end_limit = input.len() - LAST_LITERALS;
+        while i < match_limit {
+            let sequence = read_u32(input, i);
+            let slot = &mut table[hash(sequence)];
+            // Positions are stored plus one, zero marks an empty slot.
+            let candidate = *slot as usize;
+            *slot = i as u32 + 1;
+
+            if candidate == 0 || i - (candidate - 1) > MAX_OFFSET {
+                i += 1;
+                continue;
+            }
+            let candidate = candidate - 1;
+            if read_u32(input, candidate) != sequence {
+                i += 1;
+                continue;
+            }
+
+            let mut length = MIN_MATCH;
+            while i + length < end_limit && input[candidate + length] == input[i + length] {
+                length += 1;
+            }
+            write_sequence(
+                out,
+                &input[anchor..i],
+                Some(((i - candidate) as u16, length)),
+            );
+            i += length;
+            anchor = i;
+        }
+    }
+
+    write_sequence(out, &input[anchor..], None);
+}
+
+fn read_length(input: &[u8], cursor: &mut usize, nibble: u8) -> Result<usize, DecodeError> {
+    let mut length = nibble as usize;
+    if nibble < 15 {
+        return Ok(length);
+    }
+    loop {
+        let byte = *input
+            .get(*cursor)
+            .ok_or(DecodeError::unexpected_end(*cursor, 1, 0))?;
+        *cursor += 1;
+        length += byte as usize;
+        if byte != 255 {
+            return Ok(length);
+        }
+    }
+}
+
+/// Appends to `out` the `size` bytes an LZ4 block decompresses to.
+pub fn decompress_block(input: &[u8], size: usize, out: &mut Vec<u8>) -> Result<(), DecodeError> {
+    let start = out.len();
+    let mut cursor = 0;
+
+    loop {
+        let token = *input
+            .get(cursor)
+            .ok_or(DecodeError::unexpected_end(cursor, 1, 0))?;
+        cursor += 1;
+
+        let literals = read_length(input, &mut cursor, token >> 4)?;
+        if literals > input.len() - cursor {
+            return Err(DecodeError::unexpected_end(
+                cursor,
+                literals,
+                input.len() - cursor,
+            ));
+        }
+        if out.len() - start + literals > size {
+            return Err(DecodeError::length_too_large(
+                cursor,
+                size,
+                out.len() - start + literals,
+            ));
+        }
+        out.extend_from_slice(&input[cursor..cursor + literals]);
+        cursor += literals;
+
+        // The last sequence has no match.
+        if cursor == input.len() {
+            break;
+        }
+
+        if input.len() - cursor < 2 {
+            return Err(DecodeError::unexpected_end(cursor, 2, input.len() - cursor));
+        }
+        let offset = u16::from_le_bytes([input[cursor], input[cursor + 1]]) as usize;
+        if offset == 0 || offset > out.len() - start {
+            return Err(DecodeError::invalid_value(cursor, offset));
+        }
+        cursor += 2;
+
+        let length = read_length(input, &mut cursor, token & 0x0f)? + MIN_MATCH;
+        if out.len() - start + length > size {
+            return Err(DecodeError::length_too_large(
+                cursor,
+                size,
+                out.len() - start + length,
+            ));
+        }
+        // Byte by byte, the copy may overlap what it writes.
+        let from = out.len() - offset;
+        for index in from..from + length {
+            out.push(out[index]);
+        }
+    }
+
+    if out.len() - start != size {
+        return Err(DecodeError::unexpected_end(cursor, size, out.len() - start));
+    }
+    Ok(())
+}
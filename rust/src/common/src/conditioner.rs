@@ -0,0 +1,395 @@
+﻿use crate::session::random_bytes;
+use crate::transport::Transport;
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Extra delay of a reordered packet, letting the ones sent after it through
+/// first.
+pub const REORDER_DELAY: Duration = Duration::from_millis(50);
+
+/// Longest a packet waits for the bandwidth, more is dropped like by a router
+/// with a full buffer.
+pub const MAX_QUEUE_DELAY: Duration = Duration::from_secs(1);
+
+/// Largest datagram read from the wrapped transport.
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+/// Flags read by `NetworkConditions::from_args`.
+pub const CONDITIONS_USAGE: &str = "\
+Network conditions, applied to each direction:
+  --latency <ms>               delay added to every packet
+  --jitter <ms>                spread of the delay around the latency
+  --jitter-distribution <uniform|normal>
+  --loss <percent>             packets lost
+  --burst-loss <percent>       packets lost right after a lost one
+  --duplicate <percent>        packets delivered twice
+  --reorder <percent>          packets held back behind the next ones
+  --bandwidth <kbit/s>         rate packets go through, unlimited when 0";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JitterDistribution {
+    /// Delays spread evenly up to `jitter` around the latency.
+    #[default]
+    Uniform,
+    /// Delays normally distributed around the latency, `jitter` being the
+    /// standard deviation.
+    Normal,
+}
+
+/// Bad network a `ConditionedTransport` reproduces in each direction.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NetworkConditions {
+    /// Delay added to every packet.
+    pub latency: Duration,
+    /// Spread of the delay around `latency`, never below no delay.
+    pub jitter: Duration,
+    pub jitter_distribution: JitterDistribution,
+    /// Chance for a packet to be lost, from 0 to 1.
+    pub loss: f64,
+    /// Chance for the packet after a lost one to be lost too, making losses
+    /// come in bursts. At 0 losses are independent.
+    pub burst_loss: f64,
+    /// Chance for a packet to be delivered twice.
+    pub duplication: f64,
+    /// Chance for a packet to be delayed by `REORDER_DELAY` more, when the
+    /// others keep the order they were sent in.
+    pub reorder: f64,
+    /// Bytes per second, unlimited when 0.
+    pub bandwidth: u64,
+}
+
+impl NetworkConditions {
+    /// Whether packets go through untouched.
+    pub fn is_ideal(&self) -> bool {
+        self.latency.is_zero()
+            && self.jitter.is_zero()
+            && self.loss == 0.0
+            && self.duplication == 0.0
+            && self.reorder == 0.0
+            && self.bandwidth == 0
+    }
+
+    /// Reads the flags of `CONDITIONS_USAGE`, the ones not given are ideal.
+    pub fn from_args<I, S>(args: I) -> Result<Self, ConditionsError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut conditions = Self::default();
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let flag = flag.as_ref();
+            let value = args
+                .next()
+                .ok_or_else(|| ConditionsError::MissingValue(flag.to_string()))?;
+            let value = value.as_ref();
+            let invalid = || ConditionsError::InvalidValue {
+                flag: flag.to_string(),
+                value: value.to_string(),
+            };
+
+            match flag {
+                "--latency" => conditions.latency = parse_millis(value).ok_or_else(invalid)?,
+                "--jitter" => conditions.jitter = parse_millis(value).ok_or_else(invalid)?,
+                "--jitter-distribution" => {
+                    conditions.jitter_distribution = match value {
+                        "uniform" => JitterDistribution::Uniform,
+                        "normal" => JitterDistribution::Normal,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "--loss" => conditions.loss = parse_percent(value).ok_or_else(invalid)?,
+                "--burst-loss" => {
+                    conditions.burst_loss = parse_percent(value).ok_or_else(invalid)?
+                }
+                "--duplicate" => {
+                    conditions.duplication = parse_percent(value).ok_or_else(invalid)?
+                }
+                "--reorder" => conditions.reorder = parse_percent(value).ok_or_else(invalid)?,
+                "--bandwidth" => {
+                    let kbits = value.parse::<u64>().map_err(|_| invalid())?;
+                    conditions.bandwidth = kbits * 1000 / 8;
+                }
+                _ => return Err(ConditionsError::UnknownFlag(flag.to_string())),
+            }
+        }
+        Ok(conditions)
+    }
+}
+
+/// Same flags as `from_args`, separated by whitespace.
+impl FromStr for NetworkConditions {
+    type Err = ConditionsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_args(s.split_whitespace())
+    }
+}
+
+fn parse_millis(value: &str) -> Option<Duration> {
+    let millis = value.parse::<f64>().ok()?;
+    (millis.is_finite() && millis >= 0.0).then(|| Duration::from_secs_f64(millis / 1000.0))
+}
+
+fn parse_percent(value: &str) -> Option<f64> {
+    let percent = value.parse::<f64>().ok()?;
+    (0.0..=100.0).contains(&percent).then_some(percent / 100.0)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConditionsError {
+    UnknownFlag(String),
+    MissingValue(String),
+    InvalidValue { flag: String, value: String },
+}
+
+impl Display for ConditionsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConditionsError::UnknownFlag(flag) => write!(f, "unknown flag {}", flag),
+            ConditionsError::MissingValue(flag) => write!(f, "no value after {}", flag),
+            ConditionsError::InvalidValue { flag, value } => {
+                write!(f, "invalid value {} for {}", value, flag)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConditionsError {}
+
+/// Wraps a transport to put its packets through `NetworkConditions`, so the
+/// client and the server can be tried on a bad network locally.
+///
+/// Sent packets are held until their delivery time and go out on the next call
+/// to `send_to` or `recv_from`, received ones are returned by `recv_from` once
+/// due. Delays are rounded up to how often the transport is polled.
+pub struct ConditionedTransport<T> {
+    inner: T,
+    state: Mutex<ConditionerState>,
+}
+
+struct ConditionerState {
+    outgoing: Link,
+    incoming: Link,
+    buffer: Vec<u8>,
+}
+
+impl<T: Transport> ConditionedTransport<T> {
+    pub fn new(inner: T, conditions: NetworkConditions) -> Self {
+        Self::with_seed(inner, conditions, u64::from_le_bytes(random_bytes()))
+    }
+
+    /// Same as `new`, drawing the same losses and delays for the same seed.
+    pub fn with_seed(inner: T, conditions: NetworkConditions, seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        Self {
+            inner,
+            state: Mutex::new(ConditionerState {
+                outgoing: Link::new(conditions, Rng::new(rng.next_u64())),
+                incoming: Link::new(conditions, Rng::new(rng.next_u64())),
+                buffer: Vec::new(),
+            }),
+        }
+    }
+
+    /// `send_to` at `now`.
+    pub fn send_to_at(&self, addr: &str, packet: &[u8], now: Instant) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.outgoing.schedule(addr, packet, now);
+        self.flush(&mut state, now);
+        Ok(())
+    }
+
+    /// `recv_from` at `now`.
+    pub fn recv_from_at(&self, buf: &mut [u8], now: Instant) -> Option<(usize, String)> {
+        let mut state = self.state.lock().unwrap();
+        self.flush(&mut state, now);
+
+        let state = &mut *state;
+        state.buffer.resize(MAX_DATAGRAM_SIZE, 0);
+        while let Some((size, from)) = self.inner.recv_from(&mut state.buffer) {
+            state.incoming.schedule(&from, &state.buffer[..size], now);
+        }
+
+        let delayed = state.incoming.pop_due(now)?;
+        // Truncated to the buffer, like a datagram larger than it.
+        let size = delayed.packet.len().min(buf.len());
+        buf[..size].copy_from_slice(&delayed.packet[..size]);
+        Some((size, delayed.addr))
+    }
+
+    /// Sends the held packets now due. Errors are dropped, the packet being
+    /// lost like any other.
+    fn flush(&self, state: &mut ConditionerState, now: Instant) {
+        while let Some(delayed) = state.outgoing.pop_due(now) {
+            let _ = self.inner.send_to(&delayed.addr, &delayed.packet);
+        }
+    }
+}
+
+impl<T: Transport> Transport for ConditionedTransport<T> {
+    fn send_to(&self, addr: &str, packet: &[u8]) -> io::Result<()> {
+        self.send_to_at(addr, packet, Instant::now())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> Option<(usize, String)> {
+        self.recv_from_at(buf, Instant::now())
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        self.inner.local_addr()
+    }
+}
+
+struct Delayed {
+    deliver_at: Instant,
+    /// Destination of a sent packet, source of a received one.
+    addr: String,
+    packet: Vec<u8>,
+}
+
+/// One direction of a `ConditionedTransport`.
+struct Link {
+    conditions: NetworkConditions,
+    rng: Rng,
+    last_lost: bool,
+    /// When the packets accepted so far are through the bandwidth.
+    busy_until: Option<Instant>,
+    /// Delivery time of the last packet not reordered, the next ones are not
+    /// delivered before it.
+    last_delivery: Option<Instant>,
+    /// Held packets by delivery time.
+    queue: VecDeque<Delayed>,
+}
+
+impl Link {
+    fn new(conditions: NetworkConditions, rng: Rng) -> Self {
+        Self {
+            conditions,
+            rng,
+            last_lost: false,
+            busy_until: None,
+            last_delivery: None,
+            queue: VecDeque::new(),
+        }
+    }
+
+    fn schedule(&mut self, addr: &str, packet: &[u8], now: Instant) {
+        let loss = if self.last_lost && self.conditions.burst_loss > 0.0 {
+            self.conditions.burst_loss
+        } else {
+            self.conditions.loss
+        };
+        self.last_lost = self.rng.chance(loss);
+        if self.last_lost {
+            return;
+        }
+
+        let copies = if self.rng.chance(self.conditions.duplication) {
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let Some(sent_at) = self.transmit(packet.len(), now) else {
+                return;
+            };
+            let mut deliver_at = sent_at + self.delay();
+            if self.rng.chance(self.conditions.reorder) {
+                deliver_at += REORDER_DELAY;
+            } else {
+                if let Some(last_delivery) = self.last_delivery {
+                    deliver_at = deliver_at.max(last_delivery);
+                }
+                self.last_delivery = Some(deliver_at);
+            }
+
+            let index = self
+                .queue
+                .partition_point(|delayed| delayed.deliver_at <= deliver_at);
+            self.queue.insert(
+                index,
+                Delayed {
+                    deliver_at,
+                    addr: addr.to_string(),
+                    packet: packet.to_vec(),
+                },
+            );
+        }
+    }
+
+    /// When a packet of `size` bytes is through the bandwidth, None when it
+    /// would wait longer than `MAX_QUEUE_DELAY`.
+    fn transmit(&mut self, size: usize, now: Instant) -> Option<Instant> {
+        if self.conditions.bandwidth == 0 {
+            return Some(now);
+        }
+
+        let start = self
+            .busy_until
+            .map_or(now, |busy_until| busy_until.max(now));
+        if start - now > MAX_QUEUE_DELAY {
+            return None;
+        }
+        let end = start + Duration::from_secs_f64(size as f64 / self.conditions.bandwidth as f64);
+        self.busy_until = Some(end);
+        Some(end)
+    }
+
+    fn delay(&mut self) -> Duration {
+        let jitter = self.conditions.jitter.as_secs_f64();
+        let offset = match self.conditions.jitter_distribution {
+            JitterDistribution::Uniform => (self.rng.next_f64() * 2.0 - 1.0) * jitter,
+            JitterDistribution::Normal => self.rng.normal() * jitter,
+        };
+        Duration::from_secs_f64((self.conditions.latency.as_secs_f64() + offset).max(0.0))
+    }
+
+    fn pop_due(&mut self, now: Instant) -> Option<Delayed> {
+        if self.queue.front()?.deliver_at <= now {
+            self.queue.pop_front()
+        } else {
+            None
+        }
+    }
+}
+
+/// xorshift64*, enough to draw losses and delays.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Zero would stay zero.
+        Self(seed | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        self.next_f64() < probability
+    }
+
+    /// Standard normal, by the Box-Muller transform.
+    fn normal(&mut self) -> f64 {
+        let u = 1.0 - self.next_f64();
+        let v = self.next_f64();
+        (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+    }
+}
@@ -0,0 +1,221 @@
+﻿use crate::decode_error::DecodeError;
+use crate::protocol::PROTOCOL_VERSION;
+use crate::session::{KEY_SIZE, random_bytes};
+use crate::stream_reader::{Deserializable, StreamReader};
+use crate::stream_writer::{Serializable, StreamWriter};
+use chacha20poly1305::ChaCha20Poly1305;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+/// A token is refused by the server once this old.
+pub const CONNECT_TOKEN_EXPIRY: Duration = Duration::from_secs(30);
+
+/// Environment variable holding the key shared by the token service and the
+/// server, as 64 hexadecimal digits.
+pub const TOKEN_KEY_VAR: &str = "RUSTY_TOKEN_KEY";
+
+/// Key used when `TOKEN_KEY_VAR` is not set, for local runs only.
+pub const DEV_TOKEN_KEY: [u8; KEY_SIZE] = *b"RustyGodot development token key";
+
+/// Part of a connect token only the server can read.
+#[derive(Debug, Clone, PartialEq, Eq, Serializable, Deserializable)]
+pub struct PrivateConnectToken {
+    pub client_id: u32,
+    pub key: [u8; KEY_SIZE],
+}
+
+/// `PrivateConnectToken` encrypted with the key of the token service, carried
+/// by the client's `Hsk` after its `Cookie`.
+#[derive(Debug, Clone, PartialEq, Eq, Serializable, Deserializable)]
+pub struct SealedConnectToken {
+    /// Seconds since the Unix epoch past which the token is refused.
+    pub expire_timestamp: u64,
+    pub nonce: [u8; 12],
+    pub data: Vec<u8>,
+}
+
+/// Handed to a client by the token service. The client keeps `key` and sends
+/// `sealed` to the server, which finds the same key and the client id inside.
+#[derive(Debug, Clone, PartialEq, Eq, Serializable, Deserializable)]
+pub struct ConnectToken {
+    pub client_id: u32,
+    pub key: [u8; KEY_SIZE],
+    pub sealed: SealedConnectToken,
+}
+
+impl ConnectToken {
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.sealed.expire_timestamp
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    Expired,
+    /// Not sealed with this key, or altered since.
+    Forged,
+    Malformed(DecodeError),
+    /// Already used from another address.
+    Replayed,
+}
+
+impl Display for TokenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenError::Expired => write!(f, "connect token expired"),
+            TokenError::Forged => write!(f, "connect token not issued by the token service"),
+            TokenError::Malformed(e) => write!(f, "malformed connect token: {}", e),
+            TokenError::Replayed => write!(f, "connect token already used from another address"),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+/// Key shared by the token service, which issues connect tokens, and the
+/// server, which opens them.
+pub struct TokenKey {
+    cipher: ChaCha20Poly1305,
+}
+
+impl TokenKey {
+    pub fn new(key: &[u8; KEY_SIZE]) -> Self {
+        Self {
+            cipher: ChaCha20Poly1305::new(key.into()),
+        }
+    }
+
+    /// Reads `TOKEN_KEY_VAR`, None when it is not set or not a valid key.
+    pub fn from_env() -> Option<Self> {
+        let hex = std::env::var(TOKEN_KEY_VAR).ok()?;
+        if hex.len() != KEY_SIZE * 2 {
+            return None;
+        }
+
+        let mut key = [0; KEY_SIZE];
+        for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks_exact(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()?;
+        }
+        Some(Self::new(&key))
+    }
+
+    /// Token for `client_id` with a fresh key, `now` being seconds since the
+    /// Unix epoch.
+    pub fn issue(&self, client_id: u32, now: u64) -> ConnectToken {
+        let private = PrivateConnectToken {
+            client_id,
+            key: random_bytes(),
+        };
+        let expire_timestamp = now + CONNECT_TOKEN_EXPIRY.as_secs();
+        let nonce = random_bytes();
+
+        let mut stream_writer = StreamWriter::new();
+        stream_writer.write_serializable(private.clone());
+        let data = self
+            .cipher
+            .encrypt(
+                &nonce.into(),
+                Payload {
+                    msg: stream_writer.get_data(),
+                    aad: &associated_data(expire_timestamp),
+                },
+            )
+            .expect("Connect token too large to encrypt");
+
+        ConnectToken {
+            client_id,
+            key: private.key,
+            sealed: SealedConnectToken {
+                expire_timestamp,
+                nonce,
+                data,
+            },
+        }
+    }
+
+    pub fn open(
+        &self,
+        sealed: &SealedConnectToken,
+        now: u64,
+    ) -> Result<PrivateConnectToken, TokenError> {
+        if now >= sealed.expire_timestamp {
+            return Err(TokenError::Expired);
+        }
+
+        let data = self
+            .cipher
+            .decrypt(
+                &sealed.nonce.into(),
+                Payload {
+                    msg: &sealed.data,
+                    aad: &associated_data(sealed.expire_timestamp),
+                },
+            )
+            .map_err(|_| TokenError::Forged)?;
+        StreamReader::new(&data)
+            .try_read_serializable()
+            .map_err(TokenError::Malformed)
+    }
+}
+
+/// Connect tokens the server accepted, each bound to the address that used
+/// it first until it expires, like the token entries of netcode.io. Someone
+/// sniffing a token cannot connect with it from elsewhere.
+pub struct UsedTokens<A> {
+    /// Address and expiry by token nonce, unique to each token issued.
+    tokens: HashMap<[u8; 12], (A, u64)>,
+}
+
+impl<A: Eq> UsedTokens<A> {
+    pub fn new() -> Self {
+        Self {
+            tokens: HashMap::new(),
+        }
+    }
+
+    /// Records the use of `sealed`, already opened, from `addr` at `now`.
+    /// Fails when it was used from another address before.
+    pub fn register(
+        &mut self,
+        sealed: &SealedConnectToken,
+        addr: A,
+        now: u64,
+    ) -> Result<(), TokenError> {
+        self.tokens
+            .retain(|_, (_, expire_timestamp)| now < *expire_timestamp);
+
+        match self.tokens.get(&sealed.nonce) {
+            Some((first, _)) if *first != addr => Err(TokenError::Replayed),
+            Some(_) => Ok(()),
+            None => {
+                self.tokens
+                    .insert(sealed.nonce, (addr, sealed.expire_timestamp));
+                Ok(())
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+}
+
+impl<A: Eq> Default for UsedTokens<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Binds the token to its expiry, and to the protocol it was issued for.
+fn associated_data(expire_timestamp: u64) -> [u8; 10] {
+    let mut data = [0; 10];
+    data[..2].copy_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    data[2..].copy_from_slice(&expire_timestamp.to_le_bytes());
+    data
+}
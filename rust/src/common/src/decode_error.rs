@@ -0,0 +1,106 @@
+﻿use std::fmt::{Display, Formatter};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DecodeErrorKind {
+    /// The buffer ended before the value could be read.
+    UnexpectedEnd,
+    /// A length prefix is larger than the reader accepts.
+    LengthTooLarge,
+    /// A discriminant does not match any known variant.
+    InvalidValue,
+    /// An encrypted message was forged, tampered with or not meant for us.
+    Unauthenticated,
+    /// An encrypted message was received before.
+    Replayed,
+}
+
+/// Error returned by the fallible `try_read_*` methods of the stream reader.
+///
+/// `offset` is the cursor position where decoding failed. The meaning of
+/// `expected` and `actual` depends on the kind: byte counts for
+/// `UnexpectedEnd`, maximum and received length for `LengthTooLarge`, the
+/// raw value read for `InvalidValue` and the packet counter for `Replayed`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DecodeError {
+    pub kind: DecodeErrorKind,
+    pub offset: usize,
+    pub expected: usize,
+    pub actual: usize,
+}
+
+impl DecodeError {
+    pub fn unexpected_end(offset: usize, expected: usize, actual: usize) -> Self {
+        Self {
+            kind: DecodeErrorKind::UnexpectedEnd,
+            offset,
+            expected,
+            actual,
+        }
+    }
+
+    pub fn length_too_large(offset: usize, max: usize, length: usize) -> Self {
+        Self {
+            kind: DecodeErrorKind::LengthTooLarge,
+            offset,
+            expected: max,
+            actual: length,
+        }
+    }
+
+    pub fn invalid_value(offset: usize, value: usize) -> Self {
+        Self {
+            kind: DecodeErrorKind::InvalidValue,
+            offset,
+            expected: 0,
+            actual: value,
+        }
+    }
+
+    pub fn unauthenticated(offset: usize) -> Self {
+        Self {
+            kind: DecodeErrorKind::Unauthenticated,
+            offset,
+            expected: 0,
+            actual: 0,
+        }
+    }
+
+    pub fn replayed(offset: usize, counter: u64) -> Self {
+        Self {
+            kind: DecodeErrorKind::Replayed,
+            offset,
+            expected: 0,
+            actual: counter as usize,
+        }
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            DecodeErrorKind::UnexpectedEnd => write!(
+                f,
+                "unexpected end of buffer at offset {}: expected {} bytes, {} available",
+                self.offset, self.expected, self.actual
+            ),
+            DecodeErrorKind::LengthTooLarge => write!(
+                f,
+                "length {} at offset {} exceeds maximum of {}",
+                self.actual, self.offset, self.expected
+            ),
+            DecodeErrorKind::InvalidValue => {
+                write!(f, "invalid value {} at offset {}", self.actual, self.offset)
+            }
+            DecodeErrorKind::Unauthenticated => {
+                write!(f, "message at offset {} failed authentication", self.offset)
+            }
+            DecodeErrorKind::Replayed => write!(
+                f,
+                "packet counter {} at offset {} was already received",
+                self.actual, self.offset
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
@@ -0,0 +1,79 @@
+﻿use crate::bit_reader::BitReader;
+use crate::bit_writer::BitWriter;
+use crate::decode_error::DecodeError;
+use crate::property::NodeSchema;
+
+/// Packs the fields of `data` that differ from `baseline`, both laid out as
+/// `schema`, after a bit per field telling which. Returns `None` when nothing
+/// changed.
+///
+/// Fields are compared as sent, so a value moving less than its quantization
+/// step counts as unchanged.
+pub fn encode_delta(schema: &NodeSchema, baseline: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+    if !fields_changed(schema, baseline, data) {
+        return None;
+    }
+
+    let mut writer = BitWriter::new();
+    write_delta(schema, baseline, data, &mut writer);
+    Some(writer.into_bytes())
+}
+
+/// Whether `encode_delta` has anything to send for `data` against `baseline`.
+pub fn fields_changed(schema: &NodeSchema, baseline: &[u8], data: &[u8]) -> bool {
+    fields(schema, baseline, data).any(|(_, old, new)| old != new)
+}
+
+/// Writes what `encode_delta` returns to `writer`, even when nothing changed.
+pub fn write_delta(schema: &NodeSchema, baseline: &[u8], data: &[u8], writer: &mut BitWriter) {
+    for (_, old, new) in fields(schema, baseline, data) {
+        writer.write_bool(old != new);
+    }
+    for (bits, old, new) in fields(schema, baseline, data) {
+        if old != new {
+            writer.write_bits(new, bits);
+        }
+    }
+}
+
+/// Size, baseline value and new value of each field.
+fn fields<'d>(
+    schema: &'d NodeSchema,
+    baseline: &'d [u8],
+    data: &'d [u8],
+) -> impl Iterator<Item = (u32, u32, u32)> + 'd {
+    let mut baseline_reader = BitReader::new(baseline);
+    let mut reader = BitReader::new(data);
+    schema.field_bits().map(move |bits| {
+        // Both come from the sender's own packing, which writes every field.
+        let old = baseline_reader.try_read_bits(bits).unwrap_or_default();
+        let new = reader.try_read_bits(bits).unwrap_or_default();
+        (bits, old, new)
+    })
+}
+
+/// Rebuilds the packed state written by `encode_delta` from `baseline`.
+pub fn decode_delta(
+    schema: &NodeSchema,
+    baseline: &[u8],
+    delta: &[u8],
+) -> Result<Vec<u8>, DecodeError> {
+    let mut reader = BitReader::new(delta);
+    let mut changed = Vec::new();
+    for _ in schema.field_bits() {
+        changed.push(reader.try_read_bool()?);
+    }
+
+    let mut baseline_reader = BitReader::new(baseline);
+    let mut writer = BitWriter::new();
+    for (bits, changed) in schema.field_bits().zip(changed) {
+        let old = baseline_reader.try_read_bits(bits)?;
+        let value = if changed {
+            reader.try_read_bits(bits)?
+        } else {
+            old
+        };
+        writer.write_bits(value, bits);
+    }
+    Ok(writer.into_bytes())
+}
@@ -0,0 +1,217 @@
+﻿use crate::decode_error::DecodeError;
+use crate::message_header::{DataType, MessageHeader, MessageType};
+use crate::stream_reader::{Deserializable, StreamReader};
+use crate::stream_writer::{BufferOverflow, Serializable, StreamWriter};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{Duration, Instant};
+
+/// Largest datagram either side sends, small enough to avoid IP fragmentation.
+pub const MAX_PACKET_SIZE: usize = 1200;
+
+/// Bytes of a message carried by each fragment but the last, what is left once
+/// the `MessageHeader` and `FragmentHeader` (4 bytes) are written.
+pub const FRAGMENT_SIZE: usize = MAX_PACKET_SIZE - MessageHeader::SIZE - 4;
+
+pub const MAX_FRAGMENTS: usize = 64;
+
+/// Incomplete messages are discarded after this long.
+pub const REASSEMBLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Follows the `MessageHeader` of a `MessageType::Fragment` packet, the rest of
+/// the packet is the fragment data.
+#[derive(Debug, Clone, Copy, Serializable, Deserializable)]
+pub struct FragmentHeader {
+    pub group_id: u16,
+    pub index: u8,
+    pub count: u8,
+}
+
+/// Splits messages larger than `MAX_PACKET_SIZE` into fragments.
+#[derive(Default)]
+pub struct Fragmenter {
+    next_group_id: AtomicU16,
+}
+
+impl Fragmenter {
+    /// Calls `send` with each packet to put on the wire for `message`, a sealed
+    /// packet starting with a `MessageHeader`.
+    pub fn send(&self, message: &[u8], mut send: impl FnMut(&[u8])) -> Result<(), BufferOverflow> {
+        if message.len() <= MAX_PACKET_SIZE {
+            send(message);
+            return Ok(());
+        }
+
+        let count = message.len().div_ceil(FRAGMENT_SIZE);
+        if count > MAX_FRAGMENTS {
+            return Err(BufferOverflow {
+                capacity: MAX_FRAGMENTS * FRAGMENT_SIZE,
+                required: message.len(),
+            });
+        }
+
+        let group_id = self.next_group_id.fetch_add(1, Ordering::Relaxed);
+        for (index, data) in message.chunks(FRAGMENT_SIZE).enumerate() {
+            let mut packet = [0; MAX_PACKET_SIZE];
+            let mut stream_writer = StreamWriter::from_slice(&mut packet);
+            stream_writer
+                .write_serializable(MessageHeader::init(MessageType::Fragment, DataType::None));
+            stream_writer.write_serializable(FragmentHeader {
+                group_id,
+                index: index as u8,
+                count: count as u8,
+            });
+            stream_writer.write_bytes(data);
+            debug_assert!(stream_writer.overflow().is_none());
+
+            MessageHeader::seal(stream_writer.get_data_mut());
+            send(stream_writer.get_data());
+        }
+
+        Ok(())
+    }
+}
+
+struct FragmentGroup {
+    buffer: Vec<u8>,
+    received: Vec<bool>,
+    remaining: usize,
+    started: Instant,
+}
+
+/// Rebuilds fragmented messages, per sender `A`.
+pub struct Reassembler<A> {
+    groups: HashMap<(A, u16), FragmentGroup>,
+    max_groups: usize,
+    max_groups_per_sender: usize,
+    /// Messages dropped because they timed out or too many were in flight.
+    pub discarded_groups: u32,
+}
+
+impl<A: Eq + Hash + Clone> Reassembler<A> {
+    pub fn new(max_groups: usize) -> Self {
+        Self::with_sender_limit(max_groups, max_groups)
+    }
+
+    /// Keeps at most `max_groups_per_sender` of the `max_groups` for one
+    /// sender, so a single one cannot push out the messages of the others.
+    pub fn with_sender_limit(max_groups: usize, max_groups_per_sender: usize) -> Self {
+        Self {
+            groups: HashMap::new(),
+            max_groups,
+            max_groups_per_sender,
+            discarded_groups: 0,
+        }
+    }
+
+    /// Returns the message carried by `packet`, a verified datagram: the packet
+    /// itself when it is not a fragment, the rebuilt message once the last
+    /// fragment of its group arrives, `None` otherwise.
+    pub fn receive<'p>(
+        &mut self,
+        from: A,
+        packet: &'p [u8],
+        now: Instant,
+    ) -> Result<Option<Cow<'p, [u8]>>, DecodeError> {
+        let mut stream_reader = StreamReader::new(packet);
+        let message_header: MessageHeader = stream_reader.try_read_serializable()?;
+        if message_header.message_type != MessageType::Fragment {
+            return Ok(Some(Cow::Borrowed(packet)));
+        }
+
+        let offset = stream_reader.get_cursor();
+        let fragment: FragmentHeader = stream_reader.try_read_serializable()?;
+        let count = fragment.count as usize;
+        let index = fragment.index as usize;
+        if count == 0 || count > MAX_FRAGMENTS {
+            return Err(DecodeError::invalid_value(offset + 3, count));
+        }
+        if index >= count {
+            return Err(DecodeError::invalid_value(offset + 2, index));
+        }
+
+        // Every fragment but the last is full, which gives each one its place.
+        let data = stream_reader.get_rest_buffer();
+        let data_offset = stream_reader.get_cursor();
+        if data.len() > FRAGMENT_SIZE {
+            return Err(DecodeError::length_too_large(
+                data_offset,
+                FRAGMENT_SIZE,
+                data.len(),
+            ));
+        }
+        if index + 1 < count && data.len() < FRAGMENT_SIZE {
+            return Err(DecodeError::unexpected_end(
+                data_offset,
+                FRAGMENT_SIZE,
+                data.len(),
+            ));
+        }
+
+        let key = (from, fragment.group_id);
+        if !self.groups.contains_key(&key) {
+            let sender_groups = self.groups.keys().filter(|(a, _)| *a == key.0).count();
+            if sender_groups >= self.max_groups_per_sender {
+                self.discard_oldest(|a| *a == key.0);
+            } else if self.groups.len() >= self.max_groups {
+                self.discard_oldest(|_| true);
+            }
+        }
+        let group = self
+            .groups
+            .entry(key.clone())
+            .or_insert_with(|| FragmentGroup {
+                buffer: vec![0; count * FRAGMENT_SIZE],
+                received: vec![false; count],
+                remaining: count,
+                started: now,
+            });
+        if group.received.len() != count {
+            return Err(DecodeError::invalid_value(offset + 3, count));
+        }
+        if group.received[index] {
+            return Ok(None);
+        }
+
+        let start = index * FRAGMENT_SIZE;
+        group.buffer[start..start + data.len()].copy_from_slice(data);
+        group.received[index] = true;
+        group.remaining -= 1;
+        if index + 1 == count {
+            group.buffer.truncate(start + data.len());
+        }
+
+        if group.remaining > 0 {
+            return Ok(None);
+        }
+        Ok(self
+            .groups
+            .remove(&key)
+            .map(|group| Cow::Owned(group.buffer)))
+    }
+
+    /// Drops the groups started more than `REASSEMBLY_TIMEOUT` before `now`.
+    pub fn expire(&mut self, now: Instant) {
+        let before = self.groups.len();
+        self.groups
+            .retain(|_, group| now.duration_since(group.started) < REASSEMBLY_TIMEOUT);
+        self.discarded_groups += (before - self.groups.len()) as u32;
+    }
+
+    /// Drops the oldest group of the senders matching `sender`.
+    fn discard_oldest(&mut self, sender: impl Fn(&A) -> bool) {
+        let oldest = self
+            .groups
+            .iter()
+            .filter(|((from, _), _)| sender(from))
+            .min_by_key(|(_, group)| group.started)
+            .map(|(key, _)| key.clone());
+
+        if let Some(oldest) = oldest {
+            self.groups.remove(&oldest);
+            self.discarded_groups += 1;
+        }
+    }
+}
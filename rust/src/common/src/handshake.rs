@@ -1,24 +1,96 @@
-﻿use crate::stream_reader::{Deserializable, StreamReader};
-use crate::stream_writer::{Serializable, StreamWriter};
+﻿use crate::protocol::{PROTOCOL_VERSION, SCHEMA_HASH};
+use crate::session::PUBLIC_KEY_SIZE;
+use crate::stream_reader::Deserializable;
+use crate::stream_writer::Serializable;
 
-#[derive(Debug)]
-pub struct Handshake {
-    pub client_id: u32,
-    pub server_frequency: f64,
+/// Identifies the wire format of a build. It starts the body of the client's
+/// `Helo` and `Hsk` and of the server's `Hsk`, the server's `Rejection` carries
+/// its own.
+///
+/// Its layout, like `MessageHeader` and `Rejection`, must stay the same across
+/// versions so mismatched builds can still tell each other apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serializable, Deserializable)]
+pub struct ProtocolInfo {
+    pub version: u16,
+    pub schema_hash: u64,
 }
 
-impl Serializable for Handshake {
-    fn serialize(&self, stream: &mut StreamWriter) {
-        stream.write_u32(self.client_id);
-        stream.write_f64(self.server_frequency);
+impl ProtocolInfo {
+    pub fn current() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            schema_hash: SCHEMA_HASH,
+        }
     }
+
+    /// Why `self`, received from a peer, cannot talk to this build.
+    pub fn check(&self) -> Result<(), RejectReason> {
+        if self.version != PROTOCOL_VERSION {
+            Err(RejectReason::ProtocolVersion)
+        } else if self.schema_hash != SCHEMA_HASH {
+            Err(RejectReason::SchemaHash)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serializable, Deserializable)]
+pub enum RejectReason {
+    ProtocolVersion = 0,
+    SchemaHash = 1,
+}
+
+/// Sent by the server instead of `Helo`/`Hsk` when the client is refused.
+#[derive(Debug, Clone, Serializable, Deserializable)]
+pub struct Rejection {
+    pub reason: RejectReason,
+    pub server: ProtocolInfo,
+    pub message: String,
 }
 
-impl Deserializable for Handshake {
-    fn deserialize(stream_reader: &mut StreamReader) -> Self {
+impl Rejection {
+    pub fn new(reason: RejectReason, client: &ProtocolInfo) -> Self {
+        let server = ProtocolInfo::current();
+        let message = match reason {
+            RejectReason::ProtocolVersion => format!(
+                "Client protocol version {} does not match server version {}",
+                client.version, server.version
+            ),
+            RejectReason::SchemaHash => format!(
+                "Client build {:016x} does not match server build {:016x}",
+                client.schema_hash, server.schema_hash
+            ),
+        };
+
         Self {
-            client_id: stream_reader.read_u32(),
-            server_frequency: stream_reader.read_f64(),
+            reason,
+            server,
+            message,
         }
     }
 }
+
+/// Public key of one side of the `session::KeyExchange`. Follows the
+/// `SealedConnectToken` of the client's `Hsk` and the `ProtocolInfo` of the
+/// server's.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serializable, Deserializable)]
+pub struct KeyShare {
+    pub public_key: [u8; PUBLIC_KEY_SIZE],
+}
+
+/// Follows the `KeyShare` of the client's `Hsk`, with the options it asks for.
+#[derive(Debug, Clone, Copy, Serializable, Deserializable)]
+pub struct HandshakeRequest {
+    pub compression: bool,
+}
+
+/// Follows the `KeyShare` of the server's `Hsk`, encrypted by the new session.
+/// `compression` is set when both sides asked for it, sequenced messages may
+/// then be compressed.
+#[derive(Debug, Serializable, Deserializable)]
+pub struct Handshake {
+    pub client_id: u32,
+    pub server_frequency: f64,
+    pub compression: bool,
+}
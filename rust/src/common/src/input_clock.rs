@@ -0,0 +1,138 @@
+﻿use crate::input_packet::InputPacket;
+use glm::Vec2;
+
+/// Frames ahead of the server the inputs of a client should arrive, enough
+/// for a little jitter not to make them late.
+pub const TARGET_INPUT_LEAD: f64 = 2.0;
+
+/// Share of its rate the input clock speeds up or slows down by at most.
+pub const MAX_DILATION: f64 = 0.05;
+
+/// Frames behind the target past which the clock jumps ahead instead of
+/// speeding up.
+pub const MAX_LEAD_ERROR: f64 = 6.0;
+
+/// Dilation per frame of error between the lead and the target.
+const DILATION_GAIN: f64 = 0.02;
+
+/// Weight of each lead reported in the smoothed one.
+const LEAD_SMOOTHING: f64 = 0.1;
+
+/// Frame counter the client samples its inputs on, ahead of the server by the
+/// time its inputs take to arrive.
+///
+/// The server reports in each snapshot how many frames ahead of their frame
+/// the client's inputs arrive. The clock runs slightly faster while that lead
+/// is below `TARGET_INPUT_LEAD` and slightly slower above it, so inputs keep
+/// arriving just before the frame that needs them as latency changes, without
+/// the sequence ever stepping back.
+#[derive(Debug)]
+pub struct InputClock {
+    frequency: f64,
+    frame: Option<f64>,
+    lead: Option<f64>,
+    dilation: f64,
+    rtt: f64,
+    /// Seconds reports are ignored for after a jump, the ones sent before it
+    /// still on their way.
+    settling: f64,
+}
+
+impl InputClock {
+    /// Clock of a server running `frequency` frames per second.
+    pub fn new(frequency: f64) -> Self {
+        Self {
+            frequency,
+            frame: None,
+            lead: None,
+            dilation: 0.0,
+            rtt: 0.0,
+            settling: 0.0,
+        }
+    }
+
+    /// Moves on by `delta` seconds, `server_time` and `rtt` being the current
+    /// estimates of the server's clock.
+    pub fn advance(&mut self, delta: f64, server_time: f64, rtt: f64) {
+        self.rtt = rtt;
+        self.settling -= delta;
+        match self.frame.as_mut() {
+            Some(frame) => *frame += delta * self.frequency * (1.0 + self.dilation),
+            // An input is sent once its frame is over, and travels half the
+            // round trip.
+            None => {
+                self.frame =
+                    Some((server_time + rtt / 2.0) * self.frequency + 1.0 + TARGET_INPUT_LEAD)
+            }
+        }
+    }
+
+    /// Takes the lead of the inputs reported by the server.
+    pub fn report_lead(&mut self, lead: i16) {
+        if self.settling > 0.0 {
+            return;
+        }
+        let lead = match self.lead {
+            Some(smoothed) => smoothed + (lead as f64 - smoothed) * LEAD_SMOOTHING,
+            None => lead as f64,
+        };
+        let error = lead - TARGET_INPUT_LEAD;
+
+        if error < -MAX_LEAD_ERROR {
+            if let Some(frame) = self.frame.as_mut() {
+                *frame -= error;
+            }
+            self.lead = None;
+            self.dilation = 0.0;
+            self.settling = self.rtt + 1.0 / self.frequency;
+            return;
+        }
+        self.lead = Some(lead);
+        self.dilation = (-error * DILATION_GAIN).clamp(-MAX_DILATION, MAX_DILATION);
+    }
+
+    /// Sequence of the input being sampled, None until the server's clock is
+    /// known.
+    pub fn sequence(&self) -> Option<u32> {
+        self.frame.map(|frame| frame.max(0.0) as u32)
+    }
+
+    /// Share of its rate the clock runs faster, negative when slower.
+    pub fn dilation(&self) -> f64 {
+        self.dilation
+    }
+
+    /// Smoothed lead of the inputs reported.
+    pub fn lead(&self) -> Option<f64> {
+        self.lead
+    }
+}
+
+/// Input of the frame the input clock is on, made of the directions of every
+/// rendered frame that falls in it.
+///
+/// An input is complete once the clock moves past its frame. It is then sent
+/// and predicted as is, once, like the server applies it on that frame.
+#[derive(Debug, Default)]
+pub struct InputSampler {
+    current: Option<InputPacket>,
+}
+
+impl InputSampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `direction` to the input of `sequence`, and returns the input of
+    /// the previous sequence when the clock just moved past it.
+    pub fn sample(&mut self, sequence: u32, direction: Vec2) -> Option<InputPacket> {
+        let complete = self.current.take_if(|input| input.sequence != sequence);
+        let input = self.current.get_or_insert_with(|| {
+            let mut input = InputPacket::new();
+            input.sequence = sequence;
+            input
+        });
+        input.add_direction(direction);
+        complete
+    }
+}
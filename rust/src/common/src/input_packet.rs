@@ -1,22 +1,29 @@
-﻿use crate::stream_reader::{Deserializable, StreamReader};
-use crate::stream_writer::{Serializable, StreamWriter};
+﻿use crate::bit_reader::{BitDeserializable, BitReader};
+use crate::bit_writer::{BitSerializable, BitWriter};
+use crate::decode_error::DecodeError;
+use crate::stream_reader::Deserializable;
+use crate::stream_writer::Serializable;
 use glm::Vec2;
 
-#[derive(Debug)]
+#[derive(Debug, Serializable, Deserializable)]
 pub struct InputBuffer {
     pub client_id: u32,
     pub node_id: u32,
     pub packets: Vec<InputPacket>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Serializable, Deserializable)]
 pub struct InputPacket {
+    #[stream(varint)]
     pub sequence: u32,
     pub keys: u8, // bitfield : bit0=haut, bit1=bas, bit2=gauche, bit3=droite
     pub aim_x: f32,
     pub aim_y: f32,
 }
 
+/// Number of bits of `InputPacket::keys` actually used by `Input`.
+pub const INPUT_COUNT: u32 = 4;
+
 #[derive(Debug)]
 pub enum Input {
     Up = 0,
@@ -39,6 +46,22 @@ impl InputPacket {
         self.keys = self.keys | 1u8 << (input as u8);
     }
 
+    /// Sets the keys pressed to go along `direction`, in screen coordinates.
+    pub fn add_direction(&mut self, direction: Vec2) {
+        if direction.y > 0.0 {
+            self.add_input(Input::Up)
+        }
+        if direction.y < 0.0 {
+            self.add_input(Input::Down)
+        }
+        if direction.x > 0.0 {
+            self.add_input(Input::Right)
+        }
+        if direction.x < 0.0 {
+            self.add_input(Input::Left)
+        }
+    }
+
     pub fn read_input(&self, input: Input) -> bool {
         self.keys & 1u8 << (input as u8) != 0
     }
@@ -73,49 +96,27 @@ impl InputPacket {
     }
 }
 
-impl Serializable for InputBuffer {
-    fn serialize(&self, stream: &mut StreamWriter) {
-        stream.write_u32(self.client_id);
-        stream.write_u32(self.node_id);
-        stream.write_serializable_vec(self.packets.clone());
+impl BitSerializable for InputPacket {
+    fn serialize_bits(&self, writer: &mut BitWriter) {
+        writer.write_u32(self.sequence);
+        writer.write_bits(self.keys as u32, INPUT_COUNT);
+        writer.write_f32(self.aim_x);
+        writer.write_f32(self.aim_y);
     }
 }
 
-impl Deserializable for InputBuffer {
-    fn deserialize(stream_reader: &mut StreamReader) -> Self {
-        let client_id = stream_reader.read_u32();
-        let node_id = stream_reader.read_u32();
-        let packets = stream_reader.read_serializable_vec();
+impl BitDeserializable for InputPacket {
+    fn try_deserialize_bits(reader: &mut BitReader<'_>) -> Result<Self, DecodeError> {
+        let sequence = reader.try_read_u32()?;
+        let keys = reader.try_read_bits(INPUT_COUNT)? as u8;
+        let aim_x = reader.try_read_f32()?;
+        let aim_y = reader.try_read_f32()?;
 
-        Self {
-            client_id,
-            node_id,
-            packets,
-        }
-    }
-}
-
-impl Serializable for InputPacket {
-    fn serialize(&self, stream: &mut StreamWriter) {
-        stream.write_u32(self.sequence);
-        stream.write_u8(self.keys);
-        stream.write_f32(self.aim_x);
-        stream.write_f32(self.aim_y);
-    }
-}
-
-impl Deserializable for InputPacket {
-    fn deserialize(stream_reader: &mut StreamReader) -> Self {
-        let sequence = stream_reader.read_u32();
-        let keys = stream_reader.read_u8();
-        let aim_x = stream_reader.read_f32();
-        let aim_y = stream_reader.read_f32();
-
-        Self {
+        Ok(Self {
             sequence,
             keys,
             aim_x,
             aim_y,
-        }
+        })
     }
 }
@@ -0,0 +1,135 @@
+﻿use crate::input_packet::InputPacket;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Frames ahead of the server an input is buffered for at most, further ones
+/// come from a clock gone wrong and are dropped.
+pub const MAX_BUFFERED_INPUTS: u32 = 64;
+
+/// Frames the sequences received are remembered for, longer than the history
+/// of inputs a client repeats in each message.
+const RECEIVED_HISTORY: u32 = 64;
+
+/// What a boat is steered by on a frame its input did not arrive for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingInput {
+    /// The last input again, for up to this many frames in a row, then none.
+    RepeatLast(u32),
+    /// No key pressed.
+    Neutral,
+}
+
+impl Default for MissingInput {
+    fn default() -> Self {
+        // About a tenth of a second at 30 frames per second.
+        MissingInput::RepeatLast(3)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct InputStats {
+    /// Inputs applied on their frame.
+    pub applied: u32,
+    /// Inputs first received after their frame was simulated.
+    pub late: u32,
+    /// Frames simulated without their input.
+    pub missing: u32,
+    /// Inputs received again, mostly the history repeated by the client.
+    pub duplicated: u32,
+}
+
+/// Inputs of one client waiting for the frame their sequence names.
+///
+/// The client sends each input several times to make up for lost packets and
+/// sends them a little ahead, so the server buffers early inputs, keeps the
+/// first copy of each sequence, and takes them out one frame at a time.
+#[derive(Debug, Default)]
+pub struct InputQueue {
+    pending: BTreeMap<u32, InputPacket>,
+    received: BTreeSet<u32>,
+    last_input: Option<InputPacket>,
+    repeated: u32,
+    /// Last frame popped, whether its input arrived or was replaced.
+    simulated: Option<u32>,
+    /// Lead of the newest input received for the frame about to be simulated,
+    /// and for the last one inputs were received on.
+    frame_lead: Option<i64>,
+    lead: Option<i64>,
+    pub stats: InputStats,
+}
+
+impl InputQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Buffers `input`, received while the server is about to simulate
+    /// `frame`.
+    pub fn push(&mut self, input: InputPacket, frame: u32) {
+        let lead = input.sequence as i64 - frame as i64;
+        self.frame_lead = Some(self.frame_lead.map_or(lead, |newest| newest.max(lead)));
+
+        if input.sequence >= frame.saturating_add(MAX_BUFFERED_INPUTS) {
+            return;
+        }
+        if !self.received.insert(input.sequence) {
+            self.stats.duplicated += 1;
+            return;
+        }
+        if input.sequence < frame {
+            self.stats.late += 1;
+            return;
+        }
+        self.pending.insert(input.sequence, input);
+    }
+
+    /// Input to simulate `frame` with, the one received for it or the
+    /// replacement `missing` gives.
+    pub fn pop(&mut self, frame: u32, missing: MissingInput) -> InputPacket {
+        self.received = self
+            .received
+            .split_off(&frame.saturating_sub(RECEIVED_HISTORY));
+        // Only left behind when frames were skipped.
+        self.pending = self.pending.split_off(&frame);
+        if let Some(lead) = self.frame_lead.take() {
+            self.lead = Some(lead);
+        }
+        self.simulated = Some(frame);
+
+        if let Some(input) = self.pending.remove(&frame) {
+            self.stats.applied += 1;
+            self.repeated = 0;
+            self.last_input = Some(input.clone());
+            return input;
+        }
+
+        self.stats.missing += 1;
+        self.repeated += 1;
+        match (missing, &self.last_input) {
+            (MissingInput::RepeatLast(max_frames), Some(last_input))
+                if self.repeated <= max_frames =>
+            {
+                last_input.clone()
+            }
+            _ => InputPacket::new(),
+        }
+    }
+
+    /// Sequence of the last frame simulated, with its input or without. The
+    /// client replays the inputs after it, the ones before are never applied
+    /// even when they arrive.
+    pub fn last_simulated(&self) -> Option<u32> {
+        self.simulated
+    }
+
+    /// Frames the newest input received arrived ahead of the frame it is for,
+    /// negative when late, as of the last frame inputs came in.
+    pub fn lead(&self) -> Option<i16> {
+        self.lead
+            .map(|lead| lead.clamp(i16::MIN as i64, i16::MAX as i64) as i16)
+    }
+
+    /// Inputs received for frames not simulated yet.
+    pub fn buffered(&self) -> usize {
+        self.pending.len()
+    }
+}
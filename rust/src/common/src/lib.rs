@@ -1,8 +1,37 @@
-﻿pub mod input_packet;
+﻿pub mod ack;
+pub mod backoff;
+pub mod bit_reader;
+pub mod bit_writer;
+pub mod challenge;
+pub mod clock_sync;
+pub mod compression;
+pub mod conditioner;
+pub mod connect_token;
+pub mod crc32;
+pub mod decode_error;
+pub mod delta;
+pub mod fragment;
+pub mod input_clock;
+pub mod input_packet;
+pub mod input_queue;
 pub mod message_header;
+pub mod movement;
 pub mod ping_request;
+pub mod player_state;
+pub mod prediction;
+pub mod property;
+pub mod protocol;
+pub mod quantization;
+pub mod reliable;
 pub mod replicated_node;
+pub mod session;
+pub mod sha256;
 pub mod snapshot;
 pub mod stream_reader;
 pub mod stream_writer;
+pub mod transport;
+pub mod varint;
 pub mod handshake;
+
+// Lets the derive macros refer to `::common` from inside this crate too.
+extern crate self as common;
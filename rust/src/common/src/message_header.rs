@@ -1,88 +1,151 @@
-﻿use crate::stream_reader::{Deserializable, StreamReader};
+﻿use crate::ack::AckHeader;
+use crate::crc32::crc32;
+use crate::protocol::PROTOCOL_VERSION;
+use crate::stream_reader::Deserializable;
 use crate::stream_writer::{Serializable, StreamWriter};
+use std::fmt::{Display, Formatter};
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// First bytes of every datagram, anything else on the port is not ours.
+pub const PROTOCOL_MAGIC: u32 = u32::from_le_bytes(*b"RGDT");
+
+/// The checksum covers everything after itself, header included.
+const CHECKSUM_OFFSET: usize = 4;
+const CHECKED_OFFSET: usize = 8;
+const FLAGS_OFFSET: usize = 10;
+const ACKS_OFFSET: usize = 11;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serializable, Deserializable)]
 pub enum MessageType {
     Helo = 0,
     Hsk = 1,
     Ping = 2,
     Data = 3,
     Bye = 4,
+    Reject = 5,
+    Fragment = 6,
+    Reliable = 7,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+impl MessageType {
+    /// Whether the message carries the `AckHeader` of a connection. Messages
+    /// exchanged before the handshake completes and fragments, whose rebuilt
+    /// message is sequenced instead, leave it zeroed.
+    pub fn is_sequenced(self) -> bool {
+        !matches!(
+            self,
+            MessageType::Helo | MessageType::Hsk | MessageType::Reject | MessageType::Fragment
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serializable, Deserializable)]
 pub enum DataType {
     None = 0,
     Input = 1,
     Replication = 2,
 }
 
+/// Starts every datagram. `checksum` is filled by `MessageHeader::seal` once
+/// the whole packet is written.
+#[derive(Serializable, Deserializable)]
 pub struct MessageHeader {
+    pub magic: u32,
+    pub checksum: u32,
     pub message_type: MessageType,
     pub data_type: DataType,
+    /// Set of `MessageHeader::COMPRESSED`, `MessageHeader::ENCRYPTED` and future
+    /// per-message options.
+    pub flags: u8,
+    pub acks: AckHeader,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PacketError {
+    /// The datagram does not start with `PROTOCOL_MAGIC`.
+    UnknownProtocol,
+    /// The checksum does not match, the packet was corrupted or comes from
+    /// another protocol version.
+    BadChecksum,
+}
+
+impl Display for PacketError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PacketError::UnknownProtocol => write!(f, "unknown protocol"),
+            PacketError::BadChecksum => write!(f, "bad checksum"),
+        }
+    }
+}
+
+impl std::error::Error for PacketError {}
+
 impl MessageHeader {
+    /// Bytes taken by the header at the start of every packet.
+    pub const SIZE: usize = 19;
+
+    /// The rest of the message went through `compression::compress`.
+    pub const COMPRESSED: u8 = 1 << 0;
+
+    /// The rest of the message went through `session::Session::encrypt`.
+    pub const ENCRYPTED: u8 = 1 << 1;
+
     pub fn new() -> Self {
-        Self {
-            message_type: MessageType::Helo,
-            data_type: DataType::None,
-        }
+        Self::init(MessageType::Helo, DataType::None)
     }
     pub fn init(message_type: MessageType, data_type: DataType) -> Self {
         Self {
+            magic: PROTOCOL_MAGIC,
+            checksum: 0,
             message_type,
             data_type,
+            flags: 0,
+            acks: AckHeader::default(),
         }
     }
-}
-
-#[derive(Debug)]
-pub struct EnumError;
 
-impl TryFrom<u8> for MessageType {
-    type Error = EnumError;
+    /// Fills the `acks` of a packet that starts with a `MessageHeader`, before
+    /// it is sealed.
+    pub fn write_acks(packet: &mut [u8], acks: AckHeader) {
+        let mut stream_writer = StreamWriter::from_slice(&mut packet[ACKS_OFFSET..Self::SIZE]);
+        stream_writer.write_serializable(acks);
+    }
 
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(MessageType::Helo),
-            1 => Ok(MessageType::Hsk),
-            2 => Ok(MessageType::Ping),
-            3 => Ok(MessageType::Data),
-            4 => Ok(MessageType::Bye),
-            _ => Err(EnumError),
-        }
+    /// Reads the flags of a packet that starts with a `MessageHeader`.
+    pub fn flags(packet: &[u8]) -> u8 {
+        packet.get(FLAGS_OFFSET).copied().unwrap_or(0)
     }
-}
 
-impl TryFrom<u8> for DataType {
-    type Error = EnumError;
+    pub fn write_flags(packet: &mut [u8], flags: u8) {
+        packet[FLAGS_OFFSET] = flags;
+    }
 
-    fn try_from(value: u8) -> Result<Self, Self::Error> {
-        match value {
-            0 => Ok(DataType::None),
-            1 => Ok(DataType::Input),
-            2 => Ok(DataType::Replication),
-            _ => Err(EnumError),
-        }
+    /// Zeroes the checksum, what it is before the packet is sealed.
+    pub fn clear_checksum(packet: &mut [u8]) {
+        packet[CHECKSUM_OFFSET..CHECKED_OFFSET].fill(0);
     }
-}
 
-impl Serializable for MessageHeader {
-    fn serialize(&self, stream: &mut StreamWriter) {
-        stream.write_u8(self.message_type as u8);
-        stream.write_u8(self.data_type as u8);
+    /// Writes the checksum of a packet that starts with a `MessageHeader`.
+    pub fn seal(packet: &mut [u8]) {
+        let checksum = Self::checksum(packet);
+        packet[CHECKSUM_OFFSET..CHECKED_OFFSET].copy_from_slice(&checksum.to_le_bytes());
     }
-}
 
-impl Deserializable for MessageHeader {
-    fn deserialize(stream: &mut StreamReader) -> MessageHeader {
-        let message_type = MessageType::try_from(stream.read_u8()).unwrap();
-        let data_type = DataType::try_from(stream.read_u8()).unwrap();
+    /// Checks the magic and checksum of a received packet before decoding it.
+    pub fn verify(packet: &[u8]) -> Result<(), PacketError> {
+        if packet.len() < CHECKED_OFFSET
+            || packet[..CHECKSUM_OFFSET] != PROTOCOL_MAGIC.to_le_bytes()
+        {
+            return Err(PacketError::UnknownProtocol);
+        }
 
-        MessageHeader {
-            message_type,
-            data_type,
+        let checksum = &packet[CHECKSUM_OFFSET..CHECKED_OFFSET];
+        if checksum != Self::checksum(packet).to_le_bytes() {
+            return Err(PacketError::BadChecksum);
         }
+        Ok(())
+    }
+
+    fn checksum(packet: &[u8]) -> u32 {
+        crc32(PROTOCOL_VERSION as u32, &packet[CHECKED_OFFSET..])
     }
 }
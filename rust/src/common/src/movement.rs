@@ -0,0 +1,63 @@
+﻿use crate::input_packet::{Input, InputPacket};
+use crate::player_state::POSITION_FORMAT;
+use glm::Vec2;
+
+/// Parameters of how a boat moves.
+///
+/// Boats go through each other and through the walls of the map: nothing but
+/// `step` moves them, on the server and in the client's prediction alike,
+/// which would otherwise have to predict the collisions too.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoatConfig {
+    /// Pixels per second along each axis the boat is steered on. Diagonals are
+    /// not normalized.
+    pub speed: f32,
+}
+
+/// Boats of the game, on the server and in the client's prediction.
+pub const BOAT_CONFIG: BoatConfig = BoatConfig { speed: 500.0 };
+
+/// What the movement of a boat depends on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoatState {
+    pub position: Vec2,
+    pub velocity: Vec2,
+}
+
+impl BoatState {
+    pub fn at(position: Vec2) -> Self {
+        Self {
+            position,
+            velocity: Vec2::new(0.0, 0.0),
+        }
+    }
+}
+
+impl BoatConfig {
+    /// Velocity a boat takes from `input`.
+    pub fn velocity(&self, input: &InputPacket) -> Vec2 {
+        let direction = input.read_vector(Input::Right, Input::Left, Input::Up, Input::Down);
+        Vec2::new(direction.x * self.speed, direction.y * self.speed)
+    }
+
+    /// State of a boat `dt` seconds after `state`, steered by `input`. Kept
+    /// within `POSITION_FORMAT`, past which positions could not be replicated
+    /// and the client's prediction would never match the server.
+    ///
+    /// Pure and only made of IEEE operations Rust neither reorders nor fuses,
+    /// so the same state, input and `dt` give the same bits on the server and
+    /// the client. Both step once per input by the server's frame duration,
+    /// `1 / server_frequency` rounded to `f32`.
+    pub fn step(&self, state: BoatState, input: &InputPacket, dt: f32) -> BoatState {
+        let velocity = self.velocity(input);
+        BoatState {
+            position: Vec2::new(
+                (state.position.x + velocity.x * dt)
+                    .clamp(POSITION_FORMAT.min, POSITION_FORMAT.max),
+                (state.position.y + velocity.y * dt)
+                    .clamp(POSITION_FORMAT.min, POSITION_FORMAT.max),
+            ),
+            velocity,
+        }
+    }
+}
@@ -0,0 +1,87 @@
+﻿use crate::bit_reader::{BitDeserializable, BitReader};
+use crate::bit_writer::{BitSerializable, BitWriter};
+use crate::decode_error::DecodeError;
+use crate::property::{Interpolation, NodeSchema, Property, PropertyType, PropertyValue};
+use crate::quantization::FixedPoint;
+use glm::Vec2;
+
+/// World positions in pixels, kept to a twentieth of a pixel.
+pub const POSITION_FORMAT: FixedPoint = FixedPoint::new(-1024.0, 8192.0, 0.05);
+
+/// `ReplicatedNode::type_id` of the boats, also their index in the client's
+/// scene list.
+pub const PLAYER_TYPE_ID: u32 = 0;
+
+/// Velocities are sent as half floats, which keeps them within a quarter of a
+/// pixel per second at the speeds boats reach. They only drive the animation of
+/// other boats, so the newest one is used as is.
+pub const PLAYER_SCHEMA: NodeSchema = NodeSchema {
+    type_id: PLAYER_TYPE_ID,
+    name: "Player",
+    properties: &[
+        Property {
+            name: "position",
+            property_type: PropertyType::FixedPointVec2(POSITION_FORMAT),
+            interpolation: Interpolation::Linear,
+        },
+        Property {
+            name: "velocity",
+            property_type: PropertyType::HalfVec2,
+            interpolation: Interpolation::None,
+        },
+        Property {
+            name: "owner_id",
+            property_type: PropertyType::U32,
+            interpolation: Interpolation::None,
+        },
+    ],
+};
+
+/// Replicated state of a boat, packed into `ReplicatedNode::data` following
+/// `PLAYER_SCHEMA`.
+#[derive(Debug, Clone, Copy)]
+pub struct PlayerState {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub owner_id: u32,
+}
+
+impl PlayerState {
+    pub fn to_values(&self) -> [PropertyValue; 3] {
+        [
+            PropertyValue::Vec2(self.position),
+            PropertyValue::Vec2(self.velocity),
+            PropertyValue::U32(self.owner_id),
+        ]
+    }
+
+    pub fn from_values(values: &[PropertyValue]) -> Option<Self> {
+        let [
+            PropertyValue::Vec2(position),
+            PropertyValue::Vec2(velocity),
+            PropertyValue::U32(owner_id),
+        ] = *values
+        else {
+            return None;
+        };
+
+        Some(Self {
+            position,
+            velocity,
+            owner_id,
+        })
+    }
+}
+
+impl BitSerializable for PlayerState {
+    fn serialize_bits(&self, writer: &mut BitWriter) {
+        PLAYER_SCHEMA.write(&self.to_values(), writer);
+    }
+}
+
+impl BitDeserializable for PlayerState {
+    fn try_deserialize_bits(reader: &mut BitReader<'_>) -> Result<Self, DecodeError> {
+        let values = PLAYER_SCHEMA.try_read(reader)?;
+        Ok(Self::from_values(&values).expect("PLAYER_SCHEMA reads a PlayerState"))
+    }
+}
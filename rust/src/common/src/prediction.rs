@@ -0,0 +1,95 @@
+﻿use crate::input_packet::InputPacket;
+use crate::movement::{BoatConfig, BoatState};
+use std::collections::VecDeque;
+
+/// Inputs kept for replay, about two seconds of frames. Older ones are
+/// forgotten and the next correction takes their place.
+pub const MAX_PENDING_INPUTS: usize = 128;
+
+/// Input the client applied ahead of the server.
+#[derive(Debug, Clone)]
+pub struct PredictedInput {
+    pub input: InputPacket,
+    /// Seconds the input was applied for.
+    pub delta: f32,
+}
+
+/// Client-side prediction of the locally owned boat.
+///
+/// The client moves its boat as soon as it sends an input and keeps the input
+/// until a snapshot includes it. Each newer snapshot rewinds the boat to the
+/// state the server sent and replays the inputs the server had not applied
+/// yet, which lands where the boat already is unless the prediction was wrong.
+#[derive(Debug, Default)]
+pub struct Prediction {
+    pending: VecDeque<PredictedInput>,
+    reconciled_frame: Option<u32>,
+}
+
+impl Prediction {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Steps `state` by `input` for one frame of `delta` seconds, the one the
+    /// server applies it on, and keeps the input to replay it.
+    pub fn apply(
+        &mut self,
+        config: &BoatConfig,
+        state: BoatState,
+        input: InputPacket,
+        delta: f32,
+    ) -> BoatState {
+        let state = config.step(state, &input, delta);
+        if self.pending.len() >= MAX_PENDING_INPUTS {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(PredictedInput { input, delta });
+        state
+    }
+
+    /// Inputs applied and not yet included in a snapshot.
+    pub fn pending(&self) -> impl Iterator<Item = &PredictedInput> {
+        self.pending.iter()
+    }
+
+    /// Forgets the inputs up to `last_input`, included in the snapshot of
+    /// `frame` in which the boat is in `state`, and returns the state it has
+    /// once the remaining ones are replayed. None when a snapshot this recent
+    /// was already reconciled.
+    pub fn reconcile(
+        &mut self,
+        config: &BoatConfig,
+        frame: u32,
+        state: BoatState,
+        last_input: Option<u32>,
+    ) -> Option<BoatState> {
+        if self
+            .reconciled_frame
+            .is_some_and(|reconciled| frame <= reconciled)
+        {
+            return None;
+        }
+        self.reconciled_frame = Some(frame);
+
+        if let Some(last_input) = last_input {
+            while self
+                .pending
+                .front()
+                .is_some_and(|pending| pending.input.sequence <= last_input)
+            {
+                self.pending.pop_front();
+            }
+        }
+
+        Some(self.pending.iter().fold(state, |state, pending| {
+            config.step(state, &pending.input, pending.delta)
+        }))
+    }
+
+    /// Drops everything, when the boat is replaced or the connection restarts.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.reconciled_frame = None;
+    }
+}
@@ -0,0 +1,180 @@
+﻿use crate::bit_reader::BitReader;
+use crate::bit_writer::BitWriter;
+use crate::decode_error::DecodeError;
+use crate::player_state::PLAYER_SCHEMA;
+use crate::quantization::FixedPoint;
+use glm::Vec2;
+
+/// Every replicated node type. Part of `SCHEMA_HASH`, so client and server
+/// builds declaring different properties refuse each other at the handshake.
+pub const NODE_SCHEMAS: &[NodeSchema] = &[PLAYER_SCHEMA];
+
+pub fn node_schema(type_id: u32) -> Option<&'static NodeSchema> {
+    NODE_SCHEMAS.iter().find(|schema| schema.type_id == type_id)
+}
+
+/// How a property is packed, each one matching a `BitWriter` method.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PropertyType {
+    Bool,
+    U32,
+    F32,
+    FixedPoint(FixedPoint),
+    Half,
+    Angle,
+    FixedPointVec2(FixedPoint),
+    HalfVec2,
+    UnitVec2,
+}
+
+/// How the client blends a property between two snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Takes the newest value.
+    None,
+    Linear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PropertyValue {
+    Bool(bool),
+    U32(u32),
+    F32(f32),
+    Vec2(Vec2),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Property {
+    pub name: &'static str,
+    pub property_type: PropertyType,
+    pub interpolation: Interpolation,
+}
+
+/// Ordered properties a node type packs into `ReplicatedNode::data`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeSchema {
+    pub type_id: u32,
+    pub name: &'static str,
+    pub properties: &'static [Property],
+}
+
+impl PropertyType {
+    /// Widths of the fields the property is packed as, one per axis for
+    /// vectors. Delta compression sends or skips each field on its own.
+    pub fn field_bits(&self) -> impl Iterator<Item = u32> {
+        let (bits, count) = match self {
+            PropertyType::Bool => (1, 1),
+            PropertyType::U32 | PropertyType::F32 => (32, 1),
+            PropertyType::FixedPoint(format) => (format.bits(), 1),
+            PropertyType::Half | PropertyType::Angle | PropertyType::UnitVec2 => (16, 1),
+            PropertyType::FixedPointVec2(format) => (format.bits(), 2),
+            PropertyType::HalfVec2 => (16, 2),
+        };
+        std::iter::repeat_n(bits, count)
+    }
+
+    /// # Panics
+    ///
+    /// When `value` is not of the kind this type packs.
+    pub fn write(&self, value: PropertyValue, writer: &mut BitWriter) {
+        match (self, value) {
+            (PropertyType::Bool, PropertyValue::Bool(value)) => writer.write_bool(value),
+            (PropertyType::U32, PropertyValue::U32(value)) => writer.write_u32(value),
+            (PropertyType::F32, PropertyValue::F32(value)) => writer.write_f32(value),
+            (PropertyType::FixedPoint(format), PropertyValue::F32(value)) => {
+                writer.write_fixed_point(value, format)
+            }
+            (PropertyType::Half, PropertyValue::F32(value)) => writer.write_half_f32(value),
+            (PropertyType::Angle, PropertyValue::F32(value)) => writer.write_angle(value),
+            (PropertyType::FixedPointVec2(format), PropertyValue::Vec2(value)) => {
+                writer.write_fixed_point_vec2(value, format)
+            }
+            (PropertyType::HalfVec2, PropertyValue::Vec2(value)) => writer.write_half_vec2(value),
+            (PropertyType::UnitVec2, PropertyValue::Vec2(value)) => writer.write_unit_vec2(value),
+            (property_type, value) => {
+                panic!("{:?} cannot be packed as {:?}", value, property_type)
+            }
+        }
+    }
+
+    pub fn try_read(&self, reader: &mut BitReader) -> Result<PropertyValue, DecodeError> {
+        Ok(match self {
+            PropertyType::Bool => PropertyValue::Bool(reader.try_read_bool()?),
+            PropertyType::U32 => PropertyValue::U32(reader.try_read_u32()?),
+            PropertyType::F32 => PropertyValue::F32(reader.try_read_f32()?),
+            PropertyType::FixedPoint(format) => {
+                PropertyValue::F32(reader.try_read_fixed_point(format)?)
+            }
+            PropertyType::Half => PropertyValue::F32(reader.try_read_half_f32()?),
+            PropertyType::Angle => PropertyValue::F32(reader.try_read_angle()?),
+            PropertyType::FixedPointVec2(format) => {
+                PropertyValue::Vec2(reader.try_read_fixed_point_vec2(format)?)
+            }
+            PropertyType::HalfVec2 => PropertyValue::Vec2(reader.try_read_half_vec2()?),
+            PropertyType::UnitVec2 => PropertyValue::Vec2(reader.try_read_unit_vec2()?),
+        })
+    }
+}
+
+impl Interpolation {
+    pub fn apply(&self, from: PropertyValue, to: PropertyValue, alpha: f32) -> PropertyValue {
+        let lerp = |from: f32, to: f32| from + (to - from) * alpha;
+        match (self, from, to) {
+            (Interpolation::Linear, PropertyValue::F32(from), PropertyValue::F32(to)) => {
+                PropertyValue::F32(lerp(from, to))
+            }
+            (Interpolation::Linear, PropertyValue::Vec2(from), PropertyValue::Vec2(to)) => {
+                PropertyValue::Vec2(Vec2::new(lerp(from.x, to.x), lerp(from.y, to.y)))
+            }
+            _ => to,
+        }
+    }
+}
+
+impl NodeSchema {
+    /// Widths of every packed field, in order.
+    pub fn field_bits(&self) -> impl Iterator<Item = u32> + '_ {
+        self.properties
+            .iter()
+            .flat_map(|property| property.property_type.field_bits())
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.properties
+            .iter()
+            .position(|property| property.name == name)
+    }
+
+    /// Packs one value per property, in order.
+    ///
+    /// # Panics
+    ///
+    /// When `values` does not match the properties.
+    pub fn write(&self, values: &[PropertyValue], writer: &mut BitWriter) {
+        assert_eq!(values.len(), self.properties.len(), "{} values", self.name);
+        for (property, value) in self.properties.iter().zip(values) {
+            property.property_type.write(*value, writer);
+        }
+    }
+
+    pub fn try_read(&self, reader: &mut BitReader) -> Result<Vec<PropertyValue>, DecodeError> {
+        self.properties
+            .iter()
+            .map(|property| property.property_type.try_read(reader))
+            .collect()
+    }
+
+    /// Blends two decoded states property by property.
+    pub fn interpolate(
+        &self,
+        from: &[PropertyValue],
+        to: &[PropertyValue],
+        alpha: f32,
+    ) -> Vec<PropertyValue> {
+        self.properties
+            .iter()
+            .zip(from.iter().zip(to))
+            .map(|(property, (from, to))| property.interpolation.apply(*from, *to, alpha))
+            .collect()
+    }
+}
@@ -0,0 +1,130 @@
+﻿use crate::ack::AckHeader;
+use crate::challenge::Cookie;
+use crate::connect_token::{ConnectToken, PrivateConnectToken, SealedConnectToken};
+use crate::fragment::FragmentHeader;
+use crate::handshake::{
+    Handshake, HandshakeRequest, KeyShare, ProtocolInfo, RejectReason, Rejection,
+};
+use crate::input_packet::{InputBuffer, InputPacket};
+use crate::message_header::{DataType, MessageHeader, MessageType};
+use crate::ping_request::{PingRequest, PingResponse};
+use crate::property::{NODE_SCHEMAS, NodeSchema, PropertyType};
+use crate::quantization::FixedPoint;
+use crate::reliable::{ReliableMessage, ReliablePacket};
+use crate::replicated_node::ReplicatedNode;
+use crate::snapshot::{DeltaSnapshot, Snapshot};
+
+/// Bumped when the meaning of the messages changes in a way `SCHEMA_HASH`
+/// cannot see, e.g. a new encoding for a primitive type.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Hash of the layout of every message sent over the network and of the
+/// properties of every replicated node type, computed at compile time. Builds
+/// that disagree on any field refuse each other during the handshake.
+pub const SCHEMA_HASH: u64 = node_schemas_hash(
+    schema_hash(&[
+        MessageType::SCHEMA,
+        DataType::SCHEMA,
+        MessageHeader::SCHEMA,
+        AckHeader::SCHEMA,
+        FragmentHeader::SCHEMA,
+        ProtocolInfo::SCHEMA,
+        RejectReason::SCHEMA,
+        Rejection::SCHEMA,
+        Cookie::SCHEMA,
+        SealedConnectToken::SCHEMA,
+        PrivateConnectToken::SCHEMA,
+        ConnectToken::SCHEMA,
+        KeyShare::SCHEMA,
+        HandshakeRequest::SCHEMA,
+        Handshake::SCHEMA,
+        ReliablePacket::SCHEMA,
+        ReliableMessage::SCHEMA,
+        PingRequest::SCHEMA,
+        PingResponse::SCHEMA,
+        InputBuffer::SCHEMA,
+        InputPacket::SCHEMA,
+        Snapshot::SCHEMA,
+        DeltaSnapshot::SCHEMA,
+        ReplicatedNode::SCHEMA,
+    ]),
+    NODE_SCHEMAS,
+);
+
+/// Describes how a type is laid out on the wire.
+///
+/// Derived along with `Serializable`; types packed by hand implement it
+/// themselves and must update the description when their layout changes.
+pub trait WireSchema {
+    const SCHEMA: &'static str;
+}
+
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// FNV-1a over the schemas, with a separator between them.
+pub const fn schema_hash(schemas: &[&str]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    let mut i = 0;
+    while i < schemas.len() {
+        hash = hash_bytes(hash, schemas[i].as_bytes());
+        i += 1;
+    }
+
+    hash
+}
+
+/// Continues `hash` over `bytes` and a separator.
+const fn hash_bytes(mut hash: u64, bytes: &[u8]) -> u64 {
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+        i += 1;
+    }
+    hash ^= 0xff;
+    hash.wrapping_mul(FNV_PRIME)
+}
+
+const fn hash_u32(hash: u64, value: u32) -> u64 {
+    hash_bytes(hash, &value.to_le_bytes())
+}
+
+const fn hash_fixed_point(hash: u64, tag: u8, format: &FixedPoint) -> u64 {
+    let hash = hash_bytes(hash, &[tag]);
+    let hash = hash_u32(hash, format.min.to_bits());
+    let hash = hash_u32(hash, format.max.to_bits());
+    hash_u32(hash, format.precision.to_bits())
+}
+
+/// Continues `hash` over the name, type id and properties of each schema.
+pub const fn node_schemas_hash(mut hash: u64, schemas: &[NodeSchema]) -> u64 {
+    let mut i = 0;
+    while i < schemas.len() {
+        let schema = &schemas[i];
+        hash = hash_bytes(hash, schema.name.as_bytes());
+        hash = hash_u32(hash, schema.type_id);
+
+        let mut j = 0;
+        while j < schema.properties.len() {
+            let property = &schema.properties[j];
+            hash = hash_bytes(hash, property.name.as_bytes());
+            hash = match &property.property_type {
+                PropertyType::Bool => hash_bytes(hash, &[0]),
+                PropertyType::U32 => hash_bytes(hash, &[1]),
+                PropertyType::F32 => hash_bytes(hash, &[2]),
+                PropertyType::FixedPoint(format) => hash_fixed_point(hash, 3, format),
+                PropertyType::Half => hash_bytes(hash, &[4]),
+                PropertyType::Angle => hash_bytes(hash, &[5]),
+                PropertyType::FixedPointVec2(format) => hash_fixed_point(hash, 6, format),
+                PropertyType::HalfVec2 => hash_bytes(hash, &[7]),
+                PropertyType::UnitVec2 => hash_bytes(hash, &[8]),
+            };
+            hash = hash_bytes(hash, &[property.interpolation as u8]);
+            j += 1;
+        }
+        i += 1;
+    }
+
+    hash
+}
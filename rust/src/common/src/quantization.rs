@@ -0,0 +1,126 @@
+﻿use crate::bit_writer::bits_required;
+use glm::Vec2;
+use std::f32::consts::TAU;
+
+/// Maps floats in `min..=max` onto integers with steps of `precision`.
+///
+/// Values outside the range are clamped, values inside come back within
+/// `precision / 2` of the original.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FixedPoint {
+    pub min: f32,
+    pub max: f32,
+    pub precision: f32,
+}
+
+impl FixedPoint {
+    pub const fn new(min: f32, max: f32, precision: f32) -> Self {
+        Self {
+            min,
+            max,
+            precision,
+        }
+    }
+
+    /// Largest integer produced by `quantize`.
+    pub fn max_value(&self) -> u32 {
+        ((self.max - self.min) / self.precision).ceil() as u32
+    }
+
+    pub fn bits(&self) -> u32 {
+        bits_required(self.max_value())
+    }
+
+    /// Number of whole bytes needed to store a quantized value.
+    pub fn bytes(&self) -> usize {
+        self.bits().div_ceil(8).max(1) as usize
+    }
+
+    pub fn quantize(&self, value: f32) -> u32 {
+        let value = value.clamp(self.min, self.max);
+        (((value - self.min) / self.precision).round() as u32).min(self.max_value())
+    }
+
+    pub fn dequantize(&self, value: u32) -> f32 {
+        let value = self.min + value.min(self.max_value()) as f32 * self.precision;
+        value.min(self.max)
+    }
+}
+
+/// Converts to an IEEE 754 half float, rounding to nearest even.
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+
+    if half_exponent <= 0 {
+        if half_exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let half_mantissa = mantissa >> shift;
+        let halfway = 1 << (shift - 1);
+        let remainder = mantissa & ((1 << shift) - 1);
+        let mut result = half_mantissa;
+        if remainder > halfway || (remainder == halfway && half_mantissa & 1 != 0) {
+            result += 1;
+        }
+        return sign | result as u16;
+    }
+
+    let remainder = mantissa & 0x1fff;
+    // A carry out of the mantissa bumps the exponent, which is the right result.
+    let mut result = ((half_exponent as u32) << 10) | (mantissa >> 13);
+    if remainder > 0x1000 || (remainder == 0x1000 && result & 1 != 0) {
+        result += 1;
+    }
+    sign | result as u16
+}
+
+pub fn f16_to_f32(half: u16) -> f32 {
+    let sign = ((half as u32) & 0x8000) << 16;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+
+    match exponent {
+        0 => {
+            let value = mantissa as f32 / (1 << 24) as f32;
+            if sign != 0 { -value } else { value }
+        }
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(sign | ((exponent + 127 - 15) << 23) | (mantissa << 13)),
+    }
+}
+
+/// Maps an angle in radians onto the full `u16` range, wrapping around `TAU`.
+pub fn angle_to_u16(angle: f32) -> u16 {
+    let turns = angle.rem_euclid(TAU) / TAU;
+    ((turns * 65536.0).round() as u32 & 0xffff) as u16
+}
+
+/// Returns an angle in `0..TAU`.
+pub fn u16_to_angle(value: u16) -> f32 {
+    value as f32 / 65536.0 * TAU
+}
+
+/// Stores a unit vector as its angle. The length is not preserved.
+pub fn compress_unit_vec2(vec: Vec2) -> u16 {
+    angle_to_u16(vec.y.atan2(vec.x))
+}
+
+pub fn decompress_unit_vec2(value: u16) -> Vec2 {
+    let angle = u16_to_angle(value);
+    Vec2::new(angle.cos(), angle.sin())
+}
@@ -0,0 +1,318 @@
+﻿use crate::ack::{AckTracker, PacketStatus, sequence_greater_than};
+use crate::decode_error::DecodeError;
+use crate::fragment::MAX_PACKET_SIZE;
+use crate::message_header::{DataType, MessageHeader, MessageType};
+use crate::session::ENCRYPTION_OVERHEAD;
+use crate::stream_reader::{Deserializable, StreamReader};
+use crate::stream_writer::{Serializable, StreamWriter};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+pub const CHANNEL_COUNT: usize = 4;
+
+/// Relayed by the server to every other client.
+pub const CHAT_CHANNEL: u8 = 1;
+
+/// Messages of a channel in flight at once. The receiver buffers as many to
+/// put them back in order.
+pub const WINDOW_SIZE: usize = 64;
+
+/// Messages waiting on a channel, in flight or not, before `send` refuses more.
+pub const MAX_QUEUED_MESSAGES: usize = 1024;
+
+/// Room for messages in a `Reliable` packet once the header, channel and
+/// message count are written and it is encrypted.
+const PACKET_BUDGET: usize = MAX_PACKET_SIZE - MessageHeader::SIZE - ENCRYPTION_OVERHEAD - 2;
+
+/// Id and length prefix of each message.
+const MESSAGE_OVERHEAD: usize = 4;
+
+/// Largest message a channel carries, one always fits in a single datagram.
+pub const MAX_MESSAGE_SIZE: usize = PACKET_BUDGET - MESSAGE_OVERHEAD;
+
+/// A message not acknowledged after this long is sent again.
+pub const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Sent packets remembered to match their acknowledgement to their messages.
+const SENT_PACKETS: usize = 1024;
+
+/// Body of a `MessageType::Reliable` packet.
+#[derive(Debug, Serializable, Deserializable)]
+pub struct ReliablePacket<'a> {
+    pub channel: u8,
+    pub messages: Vec<ReliableMessage<'a>>,
+}
+
+#[derive(Debug, Serializable, Deserializable)]
+pub struct ReliableMessage<'a> {
+    pub id: u16,
+    pub data: Cow<'a, [u8]>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReliableError {
+    UnknownChannel(u8),
+    TooLarge(usize),
+    QueueFull,
+}
+
+impl Display for ReliableError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReliableError::UnknownChannel(channel) => write!(f, "unknown channel {}", channel),
+            ReliableError::TooLarge(len) => write!(
+                f,
+                "message of {} bytes is over the {} bytes limit",
+                len, MAX_MESSAGE_SIZE
+            ),
+            ReliableError::QueueFull => write!(f, "too many messages waiting"),
+        }
+    }
+}
+
+impl std::error::Error for ReliableError {}
+
+struct OutgoingMessage {
+    id: u16,
+    data: Vec<u8>,
+    last_sent: Option<Instant>,
+    acked: bool,
+}
+
+struct ReliableChannel {
+    next_send_id: u16,
+    /// Unacknowledged messages from the oldest, whose ids follow each other.
+    outgoing: VecDeque<OutgoingMessage>,
+    next_receive_id: u16,
+    /// Messages received ahead of `next_receive_id`, indexed by id.
+    incoming: Vec<Option<Vec<u8>>>,
+    received: VecDeque<Vec<u8>>,
+}
+
+impl ReliableChannel {
+    fn new() -> Self {
+        Self {
+            next_send_id: 0,
+            outgoing: VecDeque::new(),
+            next_receive_id: 0,
+            incoming: vec![None; WINDOW_SIZE],
+            received: VecDeque::new(),
+        }
+    }
+
+    fn message_mut(&mut self, id: u16) -> Option<&mut OutgoingMessage> {
+        let oldest = self.outgoing.front()?.id;
+        self.outgoing.get_mut(id.wrapping_sub(oldest) as usize)
+    }
+
+    fn ack(&mut self, id: u16) {
+        if let Some(message) = self.message_mut(id) {
+            message.acked = true;
+        }
+        while self.outgoing.front().is_some_and(|message| message.acked) {
+            self.outgoing.pop_front();
+        }
+    }
+
+    fn lost(&mut self, id: u16) {
+        if let Some(message) = self.message_mut(id) {
+            message.last_sent = None;
+        }
+    }
+
+    /// Picks the messages of the window due to be sent that fit in one
+    /// packet, oldest first, and marks them sent.
+    fn take_due(&mut self, now: Instant) -> Vec<usize> {
+        let mut due = Vec::new();
+        let mut size = 0;
+
+        for (index, message) in self.outgoing.iter_mut().take(WINDOW_SIZE).enumerate() {
+            let waiting = message
+                .last_sent
+                .is_none_or(|last_sent| now.duration_since(last_sent) >= RESEND_INTERVAL);
+            if message.acked || !waiting {
+                continue;
+            }
+
+            let cost = message.data.len() + MESSAGE_OVERHEAD;
+            if size + cost > PACKET_BUDGET {
+                break;
+            }
+            size += cost;
+            message.last_sent = Some(now);
+            due.push(index);
+        }
+
+        due
+    }
+
+    fn receive(&mut self, id: u16, data: Cow<[u8]>) {
+        // Already delivered, or past the window and sent again later.
+        if sequence_greater_than(self.next_receive_id, id)
+            || id.wrapping_sub(self.next_receive_id) as usize >= WINDOW_SIZE
+        {
+            return;
+        }
+
+        let slot = &mut self.incoming[id as usize % WINDOW_SIZE];
+        if slot.is_none() {
+            *slot = Some(data.into_owned());
+        }
+
+        while let Some(data) = self.incoming[self.next_receive_id as usize % WINDOW_SIZE].take() {
+            self.received.push_back(data);
+            self.next_receive_id = self.next_receive_id.wrapping_add(1);
+        }
+    }
+}
+
+struct SentPacket {
+    sequence: u16,
+    channel: u8,
+    ids: Vec<u16>,
+}
+
+/// Reliable ordered channels of one connection.
+///
+/// Messages queued with `send` go out in `MessageType::Reliable` packets from
+/// `write_packets`, which resends them every `RESEND_INTERVAL` until the
+/// packet carrying them is reported delivered to `on_packet_status`. Received
+/// packets go through `receive` and come out of `pop_received` in the order
+/// they were sent on their channel.
+pub struct ReliableEndpoint {
+    channels: Vec<ReliableChannel>,
+    sent_packets: Vec<Option<SentPacket>>,
+    stream_writer: StreamWriter<'static>,
+}
+
+impl Default for ReliableEndpoint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReliableEndpoint {
+    pub fn new() -> Self {
+        Self {
+            channels: (0..CHANNEL_COUNT).map(|_| ReliableChannel::new()).collect(),
+            sent_packets: (0..SENT_PACKETS).map(|_| None).collect(),
+            stream_writer: StreamWriter::with_capacity(MAX_PACKET_SIZE),
+        }
+    }
+
+    pub fn send(&mut self, channel: u8, data: &[u8]) -> Result<(), ReliableError> {
+        let Some(reliable_channel) = self.channels.get_mut(channel as usize) else {
+            return Err(ReliableError::UnknownChannel(channel));
+        };
+        if data.len() > MAX_MESSAGE_SIZE {
+            return Err(ReliableError::TooLarge(data.len()));
+        }
+        if reliable_channel.outgoing.len() >= MAX_QUEUED_MESSAGES {
+            return Err(ReliableError::QueueFull);
+        }
+
+        reliable_channel.outgoing.push_back(OutgoingMessage {
+            id: reliable_channel.next_send_id,
+            data: data.to_vec(),
+            last_sent: None,
+            acked: false,
+        });
+        reliable_channel.next_send_id = reliable_channel.next_send_id.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Calls `send` with each packet of messages due, numbered by `acks` but
+    /// not sealed yet.
+    pub fn write_packets(
+        &mut self,
+        acks: &mut AckTracker,
+        now: Instant,
+        mut send: impl FnMut(&mut [u8]),
+    ) {
+        for (channel, reliable_channel) in self.channels.iter_mut().enumerate() {
+            loop {
+                let due = reliable_channel.take_due(now);
+                if due.is_empty() {
+                    break;
+                }
+
+                let stream_writer = &mut self.stream_writer;
+                stream_writer.clear();
+                stream_writer
+                    .write_serializable(MessageHeader::init(MessageType::Reliable, DataType::None));
+                let header = acks.send();
+                MessageHeader::write_acks(stream_writer.get_data_mut(), header);
+
+                // Same layout as a serialized `ReliablePacket`.
+                stream_writer.write_u8(channel as u8);
+                stream_writer.write_var_u32(due.len() as u32);
+                let mut ids = Vec::with_capacity(due.len());
+                for index in due {
+                    let message = &reliable_channel.outgoing[index];
+                    stream_writer.write_u16(message.id);
+                    stream_writer.write_var_u32(message.data.len() as u32);
+                    stream_writer.write_bytes(&message.data);
+                    ids.push(message.id);
+                }
+
+                self.sent_packets[header.sequence as usize % SENT_PACKETS] = Some(SentPacket {
+                    sequence: header.sequence,
+                    channel: channel as u8,
+                    ids,
+                });
+                send(stream_writer.get_data_mut());
+            }
+        }
+    }
+
+    /// Acknowledges the messages carried by a delivered packet, or makes them
+    /// due again when it was lost.
+    pub fn on_packet_status(&mut self, sequence: u16, status: PacketStatus) {
+        let slot = &mut self.sent_packets[sequence as usize % SENT_PACKETS];
+        let Some(packet) = slot.take_if(|packet| packet.sequence == sequence) else {
+            return;
+        };
+
+        let reliable_channel = &mut self.channels[packet.channel as usize];
+        for id in packet.ids {
+            match status {
+                PacketStatus::Delivered => reliable_channel.ack(id),
+                PacketStatus::Lost => reliable_channel.lost(id),
+            }
+        }
+    }
+
+    /// Reads the body of a received `MessageType::Reliable` packet.
+    pub fn receive(&mut self, stream_reader: &mut StreamReader) -> Result<(), DecodeError> {
+        let offset = stream_reader.get_cursor();
+        let packet: ReliablePacket = stream_reader.try_read_serializable()?;
+        let Some(reliable_channel) = self.channels.get_mut(packet.channel as usize) else {
+            return Err(DecodeError::invalid_value(offset, packet.channel as usize));
+        };
+
+        for message in packet.messages {
+            reliable_channel.receive(message.id, message.data);
+        }
+        Ok(())
+    }
+
+    /// Next message received in order, with its channel.
+    pub fn pop_received(&mut self) -> Option<(u8, Vec<u8>)> {
+        self.channels
+            .iter_mut()
+            .enumerate()
+            .find_map(|(channel, reliable_channel)| {
+                Some((channel as u8, reliable_channel.received.pop_front()?))
+            })
+    }
+
+    /// Messages sent and not acknowledged yet, across all channels.
+    pub fn pending_messages(&self) -> usize {
+        self.channels
+            .iter()
+            .map(|reliable_channel| reliable_channel.outgoing.len())
+            .sum()
+    }
+}
@@ -0,0 +1,251 @@
+﻿use crate::decode_error::DecodeError;
+use crate::message_header::MessageHeader;
+use crate::sha256::HmacSha256;
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce, Tag};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+pub const KEY_SIZE: usize = 32;
+pub const PUBLIC_KEY_SIZE: usize = 32;
+const COUNTER_SIZE: usize = 8;
+const TAG_SIZE: usize = 16;
+
+/// Bytes encryption adds to a message: the packet counter and the tag.
+pub const ENCRYPTION_OVERHEAD: usize = COUNTER_SIZE + TAG_SIZE;
+
+/// Received counters remembered below the newest, older ones are refused.
+const REPLAY_WINDOW: u64 = 64;
+
+/// Bytes from the system random number generator.
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes).expect("System random number generator unavailable");
+    bytes
+}
+
+/// One side of the key exchange of the handshake.
+///
+/// The client sends its public key in its `Hsk`, the server answers with its
+/// own. Each side derives the session keys from the X25519 shared secret and
+/// the key of the client's connect token, so only the holder of the token can
+/// talk to the server and recorded traffic stays secret if the token leaks.
+pub struct KeyExchange {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl Default for KeyExchange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyExchange {
+    pub fn new() -> Self {
+        let secret = StaticSecret::from(random_bytes());
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+
+    pub fn public_key(&self) -> [u8; PUBLIC_KEY_SIZE] {
+        self.public.to_bytes()
+    }
+
+    /// Session of the client, None when the server key is not a valid one.
+    pub fn client_session(
+        &self,
+        server_public: &[u8; PUBLIC_KEY_SIZE],
+        token_key: &[u8; KEY_SIZE],
+    ) -> Option<Session> {
+        let keys = self.derive_keys(server_public, &self.public_key(), server_public, token_key)?;
+        Some(Session::new(&keys.client_to_server, &keys.server_to_client))
+    }
+
+    /// Session of the server, None when the client key is not a valid one.
+    pub fn server_session(
+        &self,
+        client_public: &[u8; PUBLIC_KEY_SIZE],
+        token_key: &[u8; KEY_SIZE],
+    ) -> Option<Session> {
+        let keys = self.derive_keys(client_public, client_public, &self.public_key(), token_key)?;
+        Some(Session::new(&keys.server_to_client, &keys.client_to_server))
+    }
+
+    fn derive_keys(
+        &self,
+        peer: &[u8; PUBLIC_KEY_SIZE],
+        client_public: &[u8; PUBLIC_KEY_SIZE],
+        server_public: &[u8; PUBLIC_KEY_SIZE],
+        token_key: &[u8; KEY_SIZE],
+    ) -> Option<SessionKeys> {
+        let shared = self.secret.diffie_hellman(&PublicKey::from(*peer));
+        // Low order points give a secret the peer did not contribute to.
+        if !shared.was_contributory() {
+            return None;
+        }
+
+        let derive = |label: &[u8]| {
+            let mut hmac = HmacSha256::new(token_key);
+            hmac.update(label);
+            hmac.update(shared.as_bytes());
+            hmac.update(client_public);
+            hmac.update(server_public);
+            hmac.finalize()
+        };
+        Some(SessionKeys {
+            client_to_server: derive(b"client to server"),
+            server_to_client: derive(b"server to client"),
+        })
+    }
+}
+
+struct SessionKeys {
+    client_to_server: [u8; KEY_SIZE],
+    server_to_client: [u8; KEY_SIZE],
+}
+
+/// Encryption of a connection once the handshake completes.
+///
+/// Everything after the `MessageHeader` of a message is encrypted with
+/// ChaCha20-Poly1305 after compression and before the packet is sealed, under a
+/// counter sent in the clear. The header is authenticated with the body, so
+/// its acks and message type cannot be altered either. Received counters are
+/// checked against a window to refuse replayed packets.
+pub struct Session {
+    send_cipher: ChaCha20Poly1305,
+    receive_cipher: ChaCha20Poly1305,
+    send_counter: u64,
+    /// Newest counter received, bit `n` of `received_bits` is set when
+    /// `newest_received - n - 1` was received too.
+    newest_received: Option<u64>,
+    received_bits: u64,
+    buffer: Vec<u8>,
+}
+
+impl Session {
+    pub fn new(send_key: &[u8; KEY_SIZE], receive_key: &[u8; KEY_SIZE]) -> Self {
+        Self {
+            send_cipher: ChaCha20Poly1305::new(send_key.into()),
+            receive_cipher: ChaCha20Poly1305::new(receive_key.into()),
+            send_counter: 0,
+            newest_received: None,
+            received_bits: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Returns the packet to seal and send for `packet`, a message starting
+    /// with a `MessageHeader`, with everything after the header encrypted.
+    pub fn encrypt(&mut self, packet: &[u8]) -> &mut [u8] {
+        self.encrypt_from(packet, MessageHeader::SIZE)
+    }
+
+    /// Same as `encrypt` with the bytes up to `offset` left readable, and
+    /// authenticated.
+    pub fn encrypt_from(&mut self, packet: &[u8], offset: usize) -> &mut [u8] {
+        let counter = self.send_counter;
+        self.send_counter += 1;
+
+        self.buffer.clear();
+        self.buffer.extend_from_slice(&packet[..offset]);
+        let flags = MessageHeader::flags(&self.buffer) | MessageHeader::ENCRYPTED;
+        MessageHeader::write_flags(&mut self.buffer, flags);
+        MessageHeader::clear_checksum(&mut self.buffer);
+        self.buffer.extend_from_slice(&counter.to_le_bytes());
+        self.buffer.extend_from_slice(&packet[offset..]);
+
+        let (associated_data, body) = self.buffer.split_at_mut(offset + COUNTER_SIZE);
+        let tag = self
+            .send_cipher
+            .encrypt_in_place_detached(&nonce(counter), &associated_data[..offset], body)
+            .expect("Message too large to encrypt");
+        self.buffer.extend_from_slice(&tag);
+        &mut self.buffer
+    }
+
+    /// Undoes `encrypt` on a received message.
+    pub fn decrypt(&mut self, message: &[u8]) -> Result<Vec<u8>, DecodeError> {
+        self.decrypt_from(message, MessageHeader::SIZE)
+    }
+
+    /// Undoes `encrypt_from` on a received message.
+    pub fn decrypt_from(&mut self, message: &[u8], offset: usize) -> Result<Vec<u8>, DecodeError> {
+        if MessageHeader::flags(message) & MessageHeader::ENCRYPTED == 0 {
+            return Err(DecodeError::unauthenticated(0));
+        }
+        if message.len() < offset + ENCRYPTION_OVERHEAD {
+            return Err(DecodeError::unexpected_end(
+                offset,
+                ENCRYPTION_OVERHEAD,
+                message.len().saturating_sub(offset),
+            ));
+        }
+
+        let mut counter_bytes = [0; COUNTER_SIZE];
+        counter_bytes.copy_from_slice(&message[offset..offset + COUNTER_SIZE]);
+        let counter = u64::from_le_bytes(counter_bytes);
+        if self.was_received(counter) {
+            return Err(DecodeError::replayed(offset, counter));
+        }
+
+        let mut associated_data = message[..offset].to_vec();
+        MessageHeader::clear_checksum(&mut associated_data);
+        let tag_offset = message.len() - TAG_SIZE;
+        let mut decrypted = Vec::with_capacity(tag_offset - COUNTER_SIZE);
+        decrypted.extend_from_slice(&message[..offset]);
+        decrypted.extend_from_slice(&message[offset + COUNTER_SIZE..tag_offset]);
+
+        self.receive_cipher
+            .decrypt_in_place_detached(
+                &nonce(counter),
+                &associated_data,
+                &mut decrypted[offset..],
+                Tag::from_slice(&message[tag_offset..]),
+            )
+            .map_err(|_| DecodeError::unauthenticated(offset))?;
+
+        self.mark_received(counter);
+        let flags = MessageHeader::flags(&decrypted) & !MessageHeader::ENCRYPTED;
+        MessageHeader::write_flags(&mut decrypted, flags);
+        Ok(decrypted)
+    }
+
+    fn was_received(&self, counter: u64) -> bool {
+        let Some(newest) = self.newest_received else {
+            return false;
+        };
+        if counter > newest {
+            return false;
+        }
+        if counter == newest {
+            return true;
+        }
+        let distance = newest - counter;
+        distance > REPLAY_WINDOW || self.received_bits & (1 << (distance - 1)) != 0
+    }
+
+    fn mark_received(&mut self, counter: u64) {
+        let Some(newest) = self.newest_received else {
+            self.newest_received = Some(counter);
+            return;
+        };
+
+        if counter > newest {
+            let shift = counter - newest;
+            self.received_bits = if shift <= REPLAY_WINDOW {
+                (((self.received_bits as u128) << shift) | (1 << (shift - 1))) as u64
+            } else {
+                0
+            };
+            self.newest_received = Some(counter);
+        } else {
+            self.received_bits |= 1 << (newest - counter - 1);
+        }
+    }
+}
+
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[..COUNTER_SIZE].copy_from_slice(&counter.to_le_bytes());
+    nonce.into()
+}
@@ -1,37 +1,262 @@
-﻿use crate::replicated_node::ReplicatedNode;
-use crate::stream_reader::{Deserializable, StreamReader};
+﻿use crate::bit_writer::{BitSerializable, BitWriter};
+use crate::decode_error::DecodeError;
+use crate::delta::{decode_delta, fields_changed, write_delta};
+use crate::property::{NodeSchema, node_schema};
+use crate::replicated_node::ReplicatedNode;
+use crate::stream_reader::Deserializable;
 use crate::stream_writer::{Serializable, StreamWriter};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 
-#[derive(Debug, Clone)]
-pub struct Snapshot {
+/// Snapshots the server keeps to encode deltas against. A client acknowledging
+/// nothing newer gets full snapshots again.
+pub const BASELINE_HISTORY: usize = 32;
+
+#[derive(Debug, Clone, Serializable, Deserializable)]
+pub struct Snapshot<'a> {
+    #[stream(varint)]
     pub frame: u32,
-    pub nodes: Vec<ReplicatedNode>,
+    pub nodes: Vec<ReplicatedNode<'a>>,
 }
 
-impl Snapshot {
-    pub fn new(frame: u32) -> Snapshot {
-        Snapshot {
+impl Snapshot<'_> {
+    pub fn new(frame: u32) -> Self {
+        Self {
             frame,
             nodes: Vec::new(),
         }
     }
 }
 
-impl Serializable for Snapshot {
-    fn serialize(&self, stream: &mut StreamWriter) {
-        stream.write_u32(self.frame);
-        stream.write_serializable_vec(self.nodes.clone());
+/// Body of a `DataType::Replication` message: the nodes of `frame` that changed
+/// since `baseline`, the newest frame the client acknowledged.
+///
+/// Nodes also in the baseline carry the fields that changed, as packed by
+/// `encode_delta`, unless their type has no schema. New nodes, and every
+/// node when there is no baseline, carry their whole state. Nodes left out are
+/// unchanged.
+#[derive(Debug, Clone, Serializable, Deserializable)]
+pub struct DeltaSnapshot<'a> {
+    #[stream(varint)]
+    pub frame: u32,
+    pub baseline: Option<u32>,
+    /// Sequence of the last frame the boat of the receiving client was
+    /// simulated for, with its input or a replacement, for it to replay the
+    /// inputs after.
+    pub last_input: Option<u32>,
+    /// Frames the newest input of the receiving client arrived ahead of the
+    /// frame it is for, negative when late.
+    pub input_lead: Option<i16>,
+    pub nodes: Vec<ReplicatedNode<'a>>,
+    /// Nodes of the baseline gone since.
+    pub removed: Vec<u32>,
+}
+
+/// Whether `node` is sent as a delta against `base`, and with which schema.
+fn delta_schema(base: &ReplicatedNode, node: &ReplicatedNode) -> Option<&'static NodeSchema> {
+    if base.type_id != node.type_id {
+        return None;
     }
+    node_schema(node.type_id)
 }
 
-impl Deserializable for Snapshot {
-    fn deserialize(stream_reader: &mut StreamReader) -> Self {
-        let frame = stream_reader.read_u32();
-        let players = stream_reader.read_serializable_vec();
+/// How a node of the snapshot is sent.
+enum NodeDelta<'b> {
+    Unchanged,
+    /// The fields that changed since the baseline's packed state.
+    Fields(&'static NodeSchema, &'b [u8]),
+    Whole,
+}
 
-        Snapshot {
-            frame,
-            nodes: players,
+fn node_delta<'b>(base: Option<&'b ReplicatedNode>, node: &ReplicatedNode) -> NodeDelta<'b> {
+    let Some(base) = base else {
+        return NodeDelta::Whole;
+    };
+    match delta_schema(base, node) {
+        Some(schema) if fields_changed(schema, &base.data, &node.data) => {
+            NodeDelta::Fields(schema, &base.data)
+        }
+        Some(_) => NodeDelta::Unchanged,
+        None if base.type_id == node.type_id && base.data == node.data => NodeDelta::Unchanged,
+        None => NodeDelta::Whole,
+    }
+}
+
+/// Packs the delta of a node in the block written by `StreamWriter::write_bit_packed`.
+struct FieldDelta<'d> {
+    schema: &'static NodeSchema,
+    baseline: &'d [u8],
+    data: &'d [u8],
+}
+
+impl BitSerializable for FieldDelta<'_> {
+    fn serialize_bits(&self, writer: &mut BitWriter) {
+        write_delta(self.schema, self.baseline, self.data, writer);
+    }
+}
+
+/// Pairs the nodes of `baseline` and `nodes`, both sorted by `net_id`, by `net_id`.
+fn merge_nodes<'s>(
+    baseline: &'s [ReplicatedNode<'s>],
+    nodes: &'s [ReplicatedNode<'s>],
+) -> impl Iterator<
+    Item = (
+        Option<&'s ReplicatedNode<'s>>,
+        Option<&'s ReplicatedNode<'s>>,
+    ),
+> {
+    let (mut baseline, mut nodes) = (baseline.iter().peekable(), nodes.iter().peekable());
+    std::iter::from_fn(move || match (baseline.peek(), nodes.peek()) {
+        (Some(base), Some(node)) if base.net_id == node.net_id => {
+            Some((baseline.next(), nodes.next()))
+        }
+        (Some(base), Some(node)) if base.net_id < node.net_id => Some((baseline.next(), None)),
+        (_, Some(_)) => Some((None, nodes.next())),
+        (Some(_), None) => Some((baseline.next(), None)),
+        (None, None) => None,
+    })
+}
+
+impl<'a> DeltaSnapshot<'a> {
+    /// Encodes `snapshot` against `baseline`, or whole without one.
+    pub fn encode(snapshot: &'a Snapshot, baseline: Option<&Snapshot>) -> Self {
+        let base_nodes: HashMap<u32, &ReplicatedNode> = baseline
+            .iter()
+            .flat_map(|baseline| &baseline.nodes)
+            .map(|node| (node.net_id, node))
+            .collect();
+
+        let mut nodes = Vec::new();
+        for node in &snapshot.nodes {
+            let data = match node_delta(base_nodes.get(&node.net_id).copied(), node) {
+                NodeDelta::Unchanged => continue,
+                NodeDelta::Fields(schema, base) => {
+                    let mut writer = BitWriter::new();
+                    write_delta(schema, base, &node.data, &mut writer);
+                    Cow::Owned(writer.into_bytes())
+                }
+                NodeDelta::Whole => Cow::Borrowed(&*node.data),
+            };
+            nodes.push(ReplicatedNode {
+                net_id: node.net_id,
+                type_id: node.type_id,
+                data,
+            });
+        }
+
+        let current: HashSet<u32> = snapshot.nodes.iter().map(|node| node.net_id).collect();
+        let removed = baseline
+            .iter()
+            .flat_map(|baseline| &baseline.nodes)
+            .map(|node| node.net_id)
+            .filter(|net_id| !current.contains(net_id))
+            .collect();
+
+        Self {
+            frame: snapshot.frame,
+            baseline: baseline.map(|baseline| baseline.frame),
+            last_input: None,
+            input_lead: None,
+            nodes,
+            removed,
+        }
+    }
+
+    /// Writes what serializing `encode(snapshot, baseline)` with `last_input`
+    /// and `input_lead` writes, straight from the packed states and without
+    /// allocating once `stream` has grown to size.
+    ///
+    /// The nodes of `snapshot` and `baseline` must be sorted by `net_id`.
+    pub fn write_from(
+        stream: &mut StreamWriter,
+        snapshot: &Snapshot,
+        baseline: Option<&Snapshot>,
+        last_input: Option<u32>,
+        input_lead: Option<i16>,
+    ) {
+        let base_nodes = baseline.map_or(&[][..], |baseline| &baseline.nodes[..]);
+        let sent = || {
+            merge_nodes(base_nodes, &snapshot.nodes).filter_map(|(base, node)| {
+                let node = node?;
+                match node_delta(base, node) {
+                    NodeDelta::Unchanged => None,
+                    delta => Some((node, delta)),
+                }
+            })
+        };
+        let removed = || {
+            merge_nodes(base_nodes, &snapshot.nodes)
+                .filter_map(|(base, node)| base.filter(|_| node.is_none()))
+        };
+
+        stream.write_var_u32(snapshot.frame);
+        stream.write_serializable(baseline.map(|baseline| baseline.frame));
+        stream.write_serializable(last_input);
+        stream.write_serializable(input_lead);
+
+        stream.write_var_u32(sent().count() as u32);
+        for (node, delta) in sent() {
+            stream.write_u32(node.net_id);
+            stream.write_var_u32(node.type_id);
+            match delta {
+                NodeDelta::Fields(schema, baseline) => stream.write_bit_packed(&FieldDelta {
+                    schema,
+                    baseline,
+                    data: &node.data,
+                }),
+                _ => stream.write_serializable_ref(&node.data),
+            }
         }
+
+        stream.write_var_u32(removed().count() as u32);
+        for base in removed() {
+            stream.write_u32(base.net_id);
+        }
+    }
+
+    /// Rebuilds the full snapshot from `baseline`, which must be the snapshot
+    /// of frame `self.baseline`.
+    pub fn apply(&self, baseline: Option<&Snapshot>) -> Result<Snapshot<'static>, DecodeError> {
+        let mut changed: HashMap<u32, &ReplicatedNode> =
+            self.nodes.iter().map(|node| (node.net_id, node)).collect();
+        let mut snapshot = Snapshot::new(self.frame);
+
+        for base in baseline.iter().flat_map(|baseline| &baseline.nodes) {
+            if self.removed.contains(&base.net_id) {
+                continue;
+            }
+            let node = match changed.remove(&base.net_id) {
+                Some(node) => {
+                    let data = match delta_schema(base, node) {
+                        Some(schema) => decode_delta(schema, &base.data, &node.data)?,
+                        None => node.data.to_vec(),
+                    };
+                    ReplicatedNode {
+                        net_id: node.net_id,
+                        type_id: node.type_id,
+                        data: Cow::Owned(data),
+                    }
+                }
+                None => ReplicatedNode {
+                    net_id: base.net_id,
+                    type_id: base.type_id,
+                    data: Cow::Owned(base.data.to_vec()),
+                },
+            };
+            snapshot.nodes.push(node);
+        }
+
+        // Whatever was not in the baseline is new.
+        for node in &self.nodes {
+            if changed.contains_key(&node.net_id) {
+                snapshot.nodes.push(ReplicatedNode {
+                    net_id: node.net_id,
+                    type_id: node.type_id,
+                    data: Cow::Owned(node.data.to_vec()),
+                });
+            }
+        }
+
+        Ok(snapshot)
     }
 }
@@ -1,115 +1,343 @@
-﻿use glm::Vec2;
+﻿use crate::bit_reader::{BitDeserializable, BitReader};
+use crate::decode_error::DecodeError;
+use crate::quantization::{FixedPoint, decompress_unit_vec2, f16_to_f32, u16_to_angle};
+use crate::varint::zigzag_decode;
+use glm::Vec2;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::hash::Hash;
 
-pub trait Deserializable {
-    fn deserialize(stream_reader: &mut StreamReader) -> Self;
+pub use common_derive::Deserializable;
+
+/// Upper bound on the length prefix accepted by `try_read_serializable_vec`
+/// unless the reader is configured otherwise.
+pub const DEFAULT_MAX_VEC_LEN: usize = 1024;
+
+/// Upper bound, in bytes, on strings read by `try_read_string`.
+pub const DEFAULT_MAX_STRING_LEN: usize = 256;
+
+pub trait Deserializable<'a> {
+    fn deserialize(stream_reader: &mut StreamReader<'a>) -> Self;
 }
 
-pub struct StreamReader {
-    buffer: Vec<u8>,
+/// Fallible counterpart of `Deserializable`, used on data coming from the network.
+///
+/// Every type implementing it with a `Default` is also `Deserializable`, whose
+/// infallible path falls back to the default on malformed input like the
+/// `read_*` methods do, hiding the error.
+///
+/// `'a` is the lifetime of the buffer being read, so decoded values can
+/// borrow from it instead of copying.
+pub trait TryDeserializable<'a>: Sized {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError>;
+}
+
+impl<'a, T: TryDeserializable<'a> + Default> Deserializable<'a> for T {
+    fn deserialize(stream_reader: &mut StreamReader<'a>) -> Self {
+        T::try_deserialize(stream_reader).unwrap_or_default()
+    }
+}
+
+/// Reads values from a borrowed buffer without copying it.
+pub struct StreamReader<'a> {
+    buffer: &'a [u8],
     cursor: usize,
+    max_vec_len: usize,
+    max_string_len: usize,
 }
 
-impl StreamReader {
-    pub fn new(buffer: Vec<u8>) -> Self {
-        Self { buffer, cursor: 0 }
+impl<'a> StreamReader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self::with_max_vec_len(buffer, DEFAULT_MAX_VEC_LEN)
     }
 
-    pub fn read_u8(&mut self) -> u8 {
-        if !self.remain_data() {
-            return 0;
+    pub fn with_max_vec_len(buffer: &'a [u8], max_vec_len: usize) -> Self {
+        Self::with_limits(buffer, max_vec_len, DEFAULT_MAX_STRING_LEN)
+    }
+
+    pub fn with_limits(buffer: &'a [u8], max_vec_len: usize, max_string_len: usize) -> Self {
+        Self {
+            buffer,
+            cursor: 0,
+            max_vec_len,
+            max_string_len,
         }
-        let data = self.buffer[self.cursor];
-        self.cursor += 1;
-        data
     }
 
-    pub fn read_u16(&mut self) -> u16 {
-        if !self.remain_data() {
-            return 0;
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.try_read_slice(N)?.try_into().unwrap())
+    }
+
+    /// Reads `len` raw bytes, borrowed from the underlying buffer.
+    pub fn try_read_slice(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let available = self.buffer.len() - self.cursor;
+        if available < len {
+            return Err(DecodeError::unexpected_end(self.cursor, len, available));
         }
-        let data = &self.buffer[self.cursor..self.cursor + 2];
-        self.cursor += 2;
-        u16::from_le_bytes(data.try_into().unwrap())
+        let data = &self.buffer[self.cursor..self.cursor + len];
+        self.cursor += len;
+        Ok(data)
     }
 
-    pub fn read_i16(&mut self) -> i16 {
-        if !self.remain_data() {
-            return 0;
+    /// Reads a varint length followed by that many bytes, borrowed from the
+    /// underlying buffer. Same wire format as a `Vec<u8>`.
+    pub fn try_read_bytes(&mut self) -> Result<&'a [u8], DecodeError> {
+        let offset = self.cursor;
+        let len = self.try_read_var_u32()? as usize;
+        if len > self.max_vec_len {
+            return Err(DecodeError::length_too_large(offset, self.max_vec_len, len));
         }
-        let data = &self.buffer[self.cursor..self.cursor + 2];
-        self.cursor += 2;
-        i16::from_le_bytes(data.try_into().unwrap())
+        self.try_read_slice(len)
     }
 
-    pub fn read_u32(&mut self) -> u32 {
-        if !self.remain_data() {
-            return 0;
+    pub fn try_read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(u8::from_le_bytes(self.take()?))
+    }
+
+    pub fn try_read_u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    pub fn try_read_i16(&mut self) -> Result<i16, DecodeError> {
+        Ok(i16::from_le_bytes(self.take()?))
+    }
+
+    pub fn try_read_u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn try_read_i32(&mut self) -> Result<i32, DecodeError> {
+        Ok(i32::from_le_bytes(self.take()?))
+    }
+
+    pub fn try_read_f32(&mut self) -> Result<f32, DecodeError> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+
+    pub fn try_read_u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    pub fn try_read_i64(&mut self) -> Result<i64, DecodeError> {
+        Ok(i64::from_le_bytes(self.take()?))
+    }
+
+    pub fn try_read_f64(&mut self) -> Result<f64, DecodeError> {
+        Ok(f64::from_le_bytes(self.take()?))
+    }
+
+    pub fn try_read_var_u64(&mut self) -> Result<u64, DecodeError> {
+        let offset = self.cursor;
+        let mut value = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.try_read_u8()?;
+            let bits = (byte & 0x7f) as u64;
+            // Bits shifted out overflow the u64, a zero last byte is an overlong encoding.
+            if bits << shift >> shift != bits || (byte == 0 && shift > 0) {
+                return Err(DecodeError::invalid_value(offset, byte as usize));
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
         }
-        let data = &self.buffer[self.cursor..self.cursor + 4];
-        self.cursor += 4;
-        u32::from_le_bytes(data.try_into().unwrap())
+
+        Err(DecodeError::invalid_value(offset, self.cursor - offset))
     }
 
-    pub fn read_i32(&mut self) -> i32 {
-        if !self.remain_data() {
-            return 0;
+    pub fn try_read_var_u32(&mut self) -> Result<u32, DecodeError> {
+        let offset = self.cursor;
+        let value = self.try_read_var_u64()?;
+        u32::try_from(value).map_err(|_| DecodeError::invalid_value(offset, value as usize))
+    }
+
+    pub fn try_read_var_i64(&mut self) -> Result<i64, DecodeError> {
+        Ok(zigzag_decode(self.try_read_var_u64()?))
+    }
+
+    pub fn try_read_var_i32(&mut self) -> Result<i32, DecodeError> {
+        let offset = self.cursor;
+        let value = self.try_read_var_i64()?;
+        i32::try_from(value).map_err(|_| DecodeError::invalid_value(offset, value as usize))
+    }
+
+    pub fn try_read_bool(&mut self) -> Result<bool, DecodeError> {
+        let offset = self.cursor;
+        match self.try_read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(DecodeError::invalid_value(offset, value as usize)),
         }
-        let data = &self.buffer[self.cursor..self.cursor + 4];
-        self.cursor += 4;
-        i32::from_le_bytes(data.try_into().unwrap())
     }
 
-    pub fn read_f32(&mut self) -> f32 {
-        if !self.remain_data() {
-            return 0.0;
+    /// Reads a varint byte length followed by that many bytes of UTF-8.
+    pub fn try_read_string(&mut self) -> Result<String, DecodeError> {
+        let offset = self.cursor;
+        let len = self.try_read_var_u32()? as usize;
+        if len > self.max_string_len {
+            return Err(DecodeError::length_too_large(
+                offset,
+                self.max_string_len,
+                len,
+            ));
+        }
+
+        let start = self.cursor;
+        let bytes = self.try_read_slice(len)?;
+        match std::str::from_utf8(bytes) {
+            Ok(string) => Ok(string.to_string()),
+            Err(e) => Err(DecodeError::invalid_value(
+                start + e.valid_up_to(),
+                bytes[e.valid_up_to()] as usize,
+            )),
         }
-        let data = &self.buffer[self.cursor..self.cursor + 4];
-        self.cursor += 4;
-        f32::from_le_bytes(data.try_into().unwrap())
     }
 
-    pub fn read_u64(&mut self) -> u64 {
-        if !self.remain_data() {
-            return 0;
+    pub fn try_read_vec2(&mut self) -> Result<Vec2, DecodeError> {
+        let x = self.try_read_f32()?;
+        let y = self.try_read_f32()?;
+        Ok(Vec2::new(x, y))
+    }
+
+    pub fn try_read_fixed_point(&mut self, format: &FixedPoint) -> Result<f32, DecodeError> {
+        let len = format.bytes();
+        let mut bytes = [0u8; 4];
+        bytes[..len].copy_from_slice(self.try_read_slice(len)?);
+        Ok(format.dequantize(u32::from_le_bytes(bytes)))
+    }
+
+    pub fn try_read_half_f32(&mut self) -> Result<f32, DecodeError> {
+        Ok(f16_to_f32(self.try_read_u16()?))
+    }
+
+    pub fn try_read_angle(&mut self) -> Result<f32, DecodeError> {
+        Ok(u16_to_angle(self.try_read_u16()?))
+    }
+
+    pub fn try_read_unit_vec2(&mut self) -> Result<Vec2, DecodeError> {
+        Ok(decompress_unit_vec2(self.try_read_u16()?))
+    }
+
+    pub fn try_read_serializable<T: TryDeserializable<'a>>(&mut self) -> Result<T, DecodeError> {
+        T::try_deserialize(self)
+    }
+
+    pub fn try_read_serializable_vec<T: TryDeserializable<'a>>(
+        &mut self,
+    ) -> Result<Vec<T>, DecodeError> {
+        let offset = self.cursor;
+        let len = self.try_read_var_u32()? as usize;
+        if len > self.max_vec_len {
+            return Err(DecodeError::length_too_large(offset, self.max_vec_len, len));
         }
-        let data = &self.buffer[self.cursor..self.cursor + 8];
-        self.cursor += 8;
-        u64::from_le_bytes(data.try_into().unwrap())
+
+        let mut vec = Vec::with_capacity(len);
+
+        for _ in 0..len {
+            vec.push(self.try_read_serializable()?);
+        }
+
+        Ok(vec)
     }
 
-    pub fn read_i64(&mut self) -> i64 {
-        if !self.remain_data() {
-            return 0;
+    pub fn try_read_serializable_map<K, V>(&mut self) -> Result<HashMap<K, V>, DecodeError>
+    where
+        K: TryDeserializable<'a> + Eq + Hash,
+        V: TryDeserializable<'a>,
+    {
+        let offset = self.cursor;
+        let len = self.try_read_var_u32()? as usize;
+        if len > self.max_vec_len {
+            return Err(DecodeError::length_too_large(offset, self.max_vec_len, len));
         }
-        let data = &self.buffer[self.cursor..self.cursor + 8];
-        self.cursor += 8;
-        i64::from_le_bytes(data.try_into().unwrap())
+
+        let mut map = HashMap::with_capacity(len);
+
+        for _ in 0..len {
+            let key = self.try_read_serializable()?;
+            let value = self.try_read_serializable()?;
+            map.insert(key, value);
+        }
+
+        Ok(map)
+    }
+
+    /// Reads a byte block written by `StreamWriter::write_bit_packed` and unpacks it.
+    pub fn try_read_bit_packed<T: BitDeserializable>(&mut self) -> Result<T, DecodeError> {
+        BitReader::new(self.try_read_bytes()?).try_read_serializable()
+    }
+
+    pub fn read_u8(&mut self) -> u8 {
+        self.try_read_u8().unwrap_or(0)
+    }
+
+    pub fn read_u16(&mut self) -> u16 {
+        self.try_read_u16().unwrap_or(0)
+    }
+
+    pub fn read_i16(&mut self) -> i16 {
+        self.try_read_i16().unwrap_or(0)
+    }
+
+    pub fn read_u32(&mut self) -> u32 {
+        self.try_read_u32().unwrap_or(0)
+    }
+
+    pub fn read_i32(&mut self) -> i32 {
+        self.try_read_i32().unwrap_or(0)
+    }
+
+    pub fn read_f32(&mut self) -> f32 {
+        self.try_read_f32().unwrap_or(0.0)
+    }
+
+    pub fn read_u64(&mut self) -> u64 {
+        self.try_read_u64().unwrap_or(0)
+    }
+
+    pub fn read_i64(&mut self) -> i64 {
+        self.try_read_i64().unwrap_or(0)
     }
 
     pub fn read_f64(&mut self) -> f64 {
-        if !self.remain_data() {
-            return 0.0;
-        }
-        let data = &self.buffer[self.cursor..self.cursor + 8];
-        self.cursor += 8;
-        f64::from_le_bytes(data.try_into().unwrap())
+        self.try_read_f64().unwrap_or(0.0)
+    }
+
+    pub fn read_var_u32(&mut self) -> u32 {
+        self.try_read_var_u32().unwrap_or(0)
+    }
+
+    pub fn read_var_u64(&mut self) -> u64 {
+        self.try_read_var_u64().unwrap_or(0)
+    }
+
+    pub fn read_var_i32(&mut self) -> i32 {
+        self.try_read_var_i32().unwrap_or(0)
+    }
+
+    pub fn read_var_i64(&mut self) -> i64 {
+        self.try_read_var_i64().unwrap_or(0)
+    }
+
+    pub fn read_bool(&mut self) -> bool {
+        self.try_read_bool().unwrap_or(false)
+    }
+
+    pub fn read_string(&mut self) -> String {
+        self.try_read_string().unwrap_or_default()
     }
 
     pub fn read_vec2(&mut self) -> Vec2 {
-        if !self.remain_data() {
-            return Vec2::new(0.0, 0.0);
-        }
-        let x = self.read_f32();
-        let y = self.read_f32();
-        Vec2::new(x, y)
+        self.try_read_vec2().unwrap_or(Vec2::new(0.0, 0.0))
     }
 
-    pub fn read_serializable<T: Deserializable>(&mut self) -> T {
+    pub fn read_serializable<T: Deserializable<'a>>(&mut self) -> T {
         T::deserialize(self)
     }
 
-    pub fn read_serializable_vec<T: Deserializable>(&mut self) -> Vec<T> {
-        let len = self.read_u32() as usize;
+    pub fn read_serializable_vec<T: Deserializable<'a>>(&mut self) -> Vec<T> {
+        let len = (self.read_var_u32() as usize).min(self.max_vec_len);
         let mut vec = Vec::with_capacity(len);
 
         for _ in 0..len {
@@ -119,17 +347,168 @@ impl StreamReader {
         vec
     }
 
-    pub fn get_rest_buffer(&self) -> &[u8] {
+    pub fn get_rest_buffer(&self) -> &'a [u8] {
         &self.buffer[self.cursor..]
     }
 
+    pub fn get_cursor(&self) -> usize {
+        self.cursor
+    }
+
     pub fn remain_data(&self) -> bool {
         self.cursor < self.buffer.len()
     }
 }
 
-impl Deserializable for u8 {
-    fn deserialize(stream_reader: &mut StreamReader) -> Self {
-        stream_reader.read_u8()
+/// Owned counterpart of `StreamReader`, for data that has to outlive the
+/// buffer it was received in.
+pub struct OwnedStreamReader {
+    buffer: Vec<u8>,
+}
+
+impl OwnedStreamReader {
+    pub fn new(buffer: Vec<u8>) -> Self {
+        Self { buffer }
+    }
+
+    pub fn reader(&self) -> StreamReader<'_> {
+        StreamReader::new(&self.buffer)
+    }
+}
+
+impl<'a> TryDeserializable<'a> for u8 {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_u8()
     }
 }
+
+impl<'a> TryDeserializable<'a> for u16 {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_u16()
+    }
+}
+
+impl<'a> TryDeserializable<'a> for i16 {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_i16()
+    }
+}
+
+impl<'a> TryDeserializable<'a> for u32 {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_u32()
+    }
+}
+
+impl<'a> TryDeserializable<'a> for i32 {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_i32()
+    }
+}
+
+impl<'a> TryDeserializable<'a> for f32 {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_f32()
+    }
+}
+
+impl<'a> TryDeserializable<'a> for u64 {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_u64()
+    }
+}
+
+impl<'a> TryDeserializable<'a> for i64 {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_i64()
+    }
+}
+
+impl<'a> TryDeserializable<'a> for f64 {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_f64()
+    }
+}
+
+impl<'a> TryDeserializable<'a> for Vec2 {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_vec2()
+    }
+}
+
+impl<'a, T: TryDeserializable<'a>> TryDeserializable<'a> for Vec<T> {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_serializable_vec()
+    }
+}
+
+impl<'a> TryDeserializable<'a> for &'a [u8] {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_bytes()
+    }
+}
+
+impl<'a> TryDeserializable<'a> for Cow<'a, [u8]> {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        Ok(Cow::Borrowed(stream_reader.try_read_bytes()?))
+    }
+}
+
+impl<'a> TryDeserializable<'a> for bool {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_bool()
+    }
+}
+
+impl<'a> TryDeserializable<'a> for String {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_string()
+    }
+}
+
+impl<'a, T: TryDeserializable<'a>> TryDeserializable<'a> for Option<T> {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        if stream_reader.try_read_bool()? {
+            Ok(Some(stream_reader.try_read_serializable()?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<'a, T: TryDeserializable<'a>, const N: usize> TryDeserializable<'a> for [T; N] {
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        let mut items = Vec::with_capacity(N);
+        for _ in 0..N {
+            items.push(stream_reader.try_read_serializable()?);
+        }
+        match items.try_into() {
+            Ok(array) => Ok(array),
+            Err(_) => unreachable!(),
+        }
+    }
+}
+
+impl<'a, K, V> TryDeserializable<'a> for HashMap<K, V>
+where
+    K: TryDeserializable<'a> + Eq + Hash,
+    V: TryDeserializable<'a>,
+{
+    fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_serializable_map()
+    }
+}
+
+macro_rules! tuple_deserializable {
+    ($($name:ident),+) => {
+        impl<'a, $($name: TryDeserializable<'a>),+> TryDeserializable<'a> for ($($name,)+) {
+            fn try_deserialize(stream_reader: &mut StreamReader<'a>) -> Result<Self, DecodeError> {
+                Ok(($(stream_reader.try_read_serializable::<$name>()?,)+))
+            }
+        }
+    };
+}
+
+tuple_deserializable!(A, B);
+tuple_deserializable!(A, B, C);
+tuple_deserializable!(A, B, C, D);
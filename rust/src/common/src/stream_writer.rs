@@ -1,64 +1,190 @@
-﻿use glm::Vec2;
+﻿use crate::bit_writer::{BitSerializable, BitWriter};
+use crate::quantization::{FixedPoint, angle_to_u16, compress_unit_vec2, f32_to_f16};
+use crate::varint::zigzag_encode;
+use glm::Vec2;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::{fmt, mem};
+
+pub use common_derive::Serializable;
 
 pub trait Serializable {
     fn serialize(&self, stream: &mut StreamWriter);
 }
 
-pub struct StreamWriter {
-    buffer: Vec<u8>,
+/// Returned when a `StreamWriter` over a fixed slice ran out of space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferOverflow {
+    pub capacity: usize,
+    pub required: usize,
+}
+
+impl fmt::Display for BufferOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "needed {} bytes but the buffer holds {}",
+            self.required, self.capacity
+        )
+    }
+}
+
+impl Error for BufferOverflow {}
+
+enum Buffer<'a> {
+    Owned(Vec<u8>),
+    Slice { data: &'a mut [u8], len: usize },
+}
+
+/// Writes values into either a growable buffer, which keeps its capacity
+/// across `clear` calls so a long-lived writer stops allocating, or a
+/// caller-provided slice.
+///
+/// Writes that do not fit in a slice are dropped, along with every write after
+/// them, and reported by `overflow`.
+pub struct StreamWriter<'a> {
+    buffer: Buffer<'a>,
+    required: usize,
+    bit_buffer: Vec<u8>,
 }
 
-impl StreamWriter {
+impl<'a> StreamWriter<'a> {
     pub fn new() -> Self {
-        Self { buffer: vec![] }
+        Self::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buffer: Buffer::Owned(Vec::with_capacity(capacity)),
+            required: 0,
+            bit_buffer: vec![],
+        }
+    }
+
+    pub fn from_slice(data: &'a mut [u8]) -> Self {
+        Self {
+            buffer: Buffer::Slice { data, len: 0 },
+            required: 0,
+            bit_buffer: vec![],
+        }
+    }
+
+    /// Empties the writer, keeping its buffer.
+    pub fn clear(&mut self) {
+        match &mut self.buffer {
+            Buffer::Owned(vec) => vec.clear(),
+            Buffer::Slice { len, .. } => *len = 0,
+        }
+        self.required = 0;
     }
 
     pub fn get_data(&self) -> &[u8] {
-        &self.buffer
+        match &self.buffer {
+            Buffer::Owned(vec) => vec,
+            Buffer::Slice { data, len } => &data[..*len],
+        }
+    }
+
+    /// Written bytes, for patching fields that depend on the rest of the data.
+    pub fn get_data_mut(&mut self) -> &mut [u8] {
+        match &mut self.buffer {
+            Buffer::Owned(vec) => vec,
+            Buffer::Slice { data, len } => &mut data[..*len],
+        }
+    }
+
+    pub fn overflow(&self) -> Option<BufferOverflow> {
+        match &self.buffer {
+            Buffer::Slice { data, .. } if self.required > data.len() => Some(BufferOverflow {
+                capacity: data.len(),
+                required: self.required,
+            }),
+            _ => None,
+        }
+    }
+
+    fn put(&mut self, bytes: &[u8]) {
+        self.required += bytes.len();
+        match &mut self.buffer {
+            Buffer::Owned(vec) => vec.extend_from_slice(bytes),
+            Buffer::Slice { data, len } => {
+                let end = *len + bytes.len();
+                // A mismatch with `required` means an earlier write overflowed.
+                if end == self.required && end <= data.len() {
+                    data[*len..end].copy_from_slice(bytes);
+                    *len = end;
+                }
+            }
+        }
     }
 
     pub fn write_u8(&mut self, data: u8) {
-        self.buffer.push(data);
+        self.put(&[data]);
     }
 
     pub fn write_u16(&mut self, data: u16) {
-        self.buffer
-            .extend_from_slice(u16::to_le_bytes(data).as_ref());
+        self.put(&u16::to_le_bytes(data));
     }
 
     pub fn write_i16(&mut self, data: i16) {
-        self.buffer
-            .extend_from_slice(i16::to_le_bytes(data).as_ref());
+        self.put(&i16::to_le_bytes(data));
     }
 
     pub fn write_u32(&mut self, data: u32) {
-        self.buffer
-            .extend_from_slice(u32::to_le_bytes(data).as_ref());
+        self.put(&u32::to_le_bytes(data));
     }
 
     pub fn write_i32(&mut self, data: i32) {
-        self.buffer
-            .extend_from_slice(i32::to_le_bytes(data).as_ref());
+        self.put(&i32::to_le_bytes(data));
     }
 
     pub fn write_f32(&mut self, data: f32) {
-        self.buffer
-            .extend_from_slice(f32::to_le_bytes(data).as_ref());
+        self.put(&f32::to_le_bytes(data));
     }
 
     pub fn write_u64(&mut self, data: u64) {
-        self.buffer
-            .extend_from_slice(u64::to_le_bytes(data).as_ref());
+        self.put(&u64::to_le_bytes(data));
     }
 
     pub fn write_i64(&mut self, data: i64) {
-        self.buffer
-            .extend_from_slice(i64::to_le_bytes(data).as_ref());
+        self.put(&i64::to_le_bytes(data));
     }
 
     pub fn write_f64(&mut self, data: f64) {
-        self.buffer
-            .extend_from_slice(f64::to_le_bytes(data).as_ref());
+        self.put(&f64::to_le_bytes(data));
+    }
+
+    /// Writes `data` as an LEB128 varint: 7 bits per byte, high bit set on
+    /// every byte but the last.
+    pub fn write_var_u64(&mut self, mut data: u64) {
+        while data >= 0x80 {
+            self.put(&[data as u8 | 0x80]);
+            data >>= 7;
+        }
+        self.put(&[data as u8]);
+    }
+
+    pub fn write_var_u32(&mut self, data: u32) {
+        self.write_var_u64(data as u64);
+    }
+
+    pub fn write_var_i64(&mut self, data: i64) {
+        self.write_var_u64(zigzag_encode(data));
+    }
+
+    pub fn write_var_i32(&mut self, data: i32) {
+        self.write_var_u64(zigzag_encode(data as i64));
+    }
+
+    pub fn write_bool(&mut self, data: bool) {
+        self.write_u8(data as u8);
+    }
+
+    /// Writes the UTF-8 byte length as a varint followed by the bytes.
+    pub fn write_string(&mut self, data: &str) {
+        self.write_var_u32(data.len() as u32);
+        self.write_bytes(data.as_bytes());
     }
 
     pub fn write_vec2(&mut self, vec: Vec2) {
@@ -66,28 +192,208 @@ impl StreamWriter {
         self.write_f32(vec.y);
     }
 
+    /// Writes the quantized value on the fewest whole bytes the format needs.
+    pub fn write_fixed_point(&mut self, data: f32, format: &FixedPoint) {
+        let bytes = format.quantize(data).to_le_bytes();
+        self.put(&bytes[..format.bytes()]);
+    }
+
+    pub fn write_half_f32(&mut self, data: f32) {
+        self.write_u16(f32_to_f16(data));
+    }
+
+    pub fn write_angle(&mut self, angle: f32) {
+        self.write_u16(angle_to_u16(angle));
+    }
+
+    pub fn write_unit_vec2(&mut self, vec: Vec2) {
+        self.write_u16(compress_unit_vec2(vec));
+    }
+
     pub fn write_bytes(&mut self, data: &[u8]) {
-        self.buffer.extend_from_slice(data);
+        self.put(data);
     }
 
     pub fn write_serializable<T: Serializable>(&mut self, data: T) {
         data.serialize(self);
     }
 
-    pub fn write_serializable_ref<T: Serializable>(&mut self, data: &T) {
+    pub fn write_serializable_ref<T: Serializable + ?Sized>(&mut self, data: &T) {
         data.serialize(self);
     }
 
-    pub fn write_serializable_vec<T: Serializable>(&mut self, data: Vec<T>) {
-        self.write_u32(data.len() as u32);
-        for serializable in data {
-            self.write_serializable(serializable);
+    /// Bit-packs `data` and writes it as a length-prefixed byte block.
+    pub fn write_bit_packed<T: BitSerializable>(&mut self, data: &T) {
+        let mut bit_writer = BitWriter::with_buffer(mem::take(&mut self.bit_buffer));
+        bit_writer.write_serializable_ref(data);
+        let bytes = bit_writer.into_bytes();
+        self.write_var_u32(bytes.len() as u32);
+        self.write_bytes(&bytes);
+        self.bit_buffer = bytes;
+    }
+
+    /// Writes a varint length followed by the items, same layout as a `Vec<T>`.
+    pub fn write_serializable_slice<T: Serializable>(&mut self, data: &[T]) {
+        self.write_serializable_iter(data);
+    }
+
+    /// Writes the items of `data` with the same layout as a `Vec<T>`, without
+    /// collecting them first.
+    pub fn write_serializable_iter<I>(&mut self, data: I)
+    where
+        I: IntoIterator,
+        I::IntoIter: ExactSizeIterator,
+        I::Item: Serializable,
+    {
+        let iter = data.into_iter();
+        self.write_var_u32(iter.len() as u32);
+        for serializable in iter {
+            serializable.serialize(self);
         }
     }
 }
 
+impl Default for StreamWriter<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Serializable for u8 {
     fn serialize(&self, stream: &mut StreamWriter) {
         stream.write_u8(*self);
     }
 }
+
+impl Serializable for u16 {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_u16(*self);
+    }
+}
+
+impl Serializable for i16 {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_i16(*self);
+    }
+}
+
+impl Serializable for u32 {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_u32(*self);
+    }
+}
+
+impl Serializable for i32 {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_i32(*self);
+    }
+}
+
+impl Serializable for f32 {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_f32(*self);
+    }
+}
+
+impl Serializable for u64 {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_u64(*self);
+    }
+}
+
+impl Serializable for i64 {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_i64(*self);
+    }
+}
+
+impl Serializable for f64 {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_f64(*self);
+    }
+}
+
+impl Serializable for Vec2 {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_vec2(*self);
+    }
+}
+
+impl<T: Serializable> Serializable for [T] {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_serializable_slice(self);
+    }
+}
+
+impl<T: Serializable> Serializable for Vec<T> {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_serializable_slice(self);
+    }
+}
+
+impl<T: Serializable + ?Sized> Serializable for &T {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        (**self).serialize(stream);
+    }
+}
+
+impl Serializable for Cow<'_, [u8]> {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_var_u32(self.len() as u32);
+        stream.write_bytes(self);
+    }
+}
+
+impl Serializable for bool {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_bool(*self);
+    }
+}
+
+impl Serializable for String {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_string(self);
+    }
+}
+
+impl<T: Serializable> Serializable for Option<T> {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_bool(self.is_some());
+        if let Some(data) = self {
+            stream.write_serializable_ref(data);
+        }
+    }
+}
+
+/// Fixed-size arrays have no length prefix.
+impl<T: Serializable, const N: usize> Serializable for [T; N] {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        for serializable in self {
+            stream.write_serializable_ref(serializable);
+        }
+    }
+}
+
+impl<K: Serializable, V: Serializable> Serializable for HashMap<K, V> {
+    fn serialize(&self, stream: &mut StreamWriter) {
+        stream.write_var_u32(self.len() as u32);
+        for (key, value) in self {
+            stream.write_serializable_ref(key);
+            stream.write_serializable_ref(value);
+        }
+    }
+}
+
+macro_rules! tuple_serializable {
+    ($($name:ident $index:tt),+) => {
+        impl<$($name: Serializable),+> Serializable for ($($name,)+) {
+            fn serialize(&self, stream: &mut StreamWriter) {
+                $(stream.write_serializable_ref(&self.$index);)+
+            }
+        }
+    };
+}
+
+tuple_serializable!(A 0, B 1);
+tuple_serializable!(A 0, B 1, C 2);
+tuple_serializable!(A 0, B 1, C 2, D 3);
@@ -0,0 +1,137 @@
+﻿use std::collections::HashMap;
+use std::io;
+use std::net::UdpSocket;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::sync::{Arc, Mutex};
+
+/// Moves datagrams between addresses written as `ip:port`.
+///
+/// Sending never waits and a packet may be lost like on UDP, `recv_from`
+/// returns the next packet received, or None at once when there is none.
+pub trait Transport: Send + Sync {
+    fn send_to(&self, addr: &str, packet: &[u8]) -> io::Result<()>;
+
+    /// Copies the next packet received into `buf`, returning its size and
+    /// the address it came from.
+    fn recv_from(&self, buf: &mut [u8]) -> Option<(usize, String)>;
+
+    fn local_addr(&self) -> io::Result<String>;
+}
+
+/// Non-blocking UDP socket.
+pub struct UdpTransport {
+    socket: UdpSocket,
+}
+
+impl UdpTransport {
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket })
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_to(&self, addr: &str, packet: &[u8]) -> io::Result<()> {
+        self.socket.send_to(packet, addr).map(|_| ())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> Option<(usize, String)> {
+        loop {
+            match self.socket.recv_from(buf) {
+                Ok((size, addr)) => return Some((size, addr.to_string())),
+                // Windows reports a packet sent earlier to a closed port here,
+                // the next one may still be waiting.
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        self.socket.local_addr().map(|addr| addr.to_string())
+    }
+}
+
+type Datagram = (Vec<u8>, String);
+
+/// Sender of the endpoint bound to an address, with its binding id.
+type Endpoint = (u64, Sender<Datagram>);
+
+/// Addresses of the `MemoryTransport`s bound to it, for tests to run a server
+/// and its clients in one process without sockets.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    endpoints: Arc<Mutex<HashMap<String, Endpoint>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Endpoint the others of the network reach at `addr`, replacing any
+    /// bound there before.
+    pub fn bind(&self, addr: &str) -> MemoryTransport {
+        let (sender, receiver) = channel();
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.endpoints
+            .lock()
+            .unwrap()
+            .insert(addr.to_string(), (id, sender));
+
+        MemoryTransport {
+            network: self.clone(),
+            addr: addr.to_string(),
+            id,
+            receiver: Mutex::new(receiver),
+        }
+    }
+}
+
+/// In-memory endpoint of a `MemoryNetwork`. Packets to an address nothing is
+/// bound to are dropped, like on UDP.
+pub struct MemoryTransport {
+    network: MemoryNetwork,
+    addr: String,
+    id: u64,
+    receiver: Mutex<Receiver<Datagram>>,
+}
+
+impl Transport for MemoryTransport {
+    fn send_to(&self, addr: &str, packet: &[u8]) -> io::Result<()> {
+        let endpoints = self.network.endpoints.lock().unwrap();
+        if let Some((_, sender)) = endpoints.get(addr) {
+            // The receiving end was dropped, the packet is lost.
+            let _ = sender.send((packet.to_vec(), self.addr.clone()));
+        }
+        Ok(())
+    }
+
+    fn recv_from(&self, buf: &mut [u8]) -> Option<(usize, String)> {
+        let (packet, from) = self.receiver.lock().unwrap().try_recv().ok()?;
+        // Truncated to the buffer, like a datagram larger than it.
+        let size = packet.len().min(buf.len());
+        buf[..size].copy_from_slice(&packet[..size]);
+        Some((size, from))
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        Ok(self.addr.clone())
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        let mut endpoints = self.network.endpoints.lock().unwrap();
+        // Unless another endpoint was bound to the address since.
+        if endpoints
+            .get(&self.addr)
+            .is_some_and(|(id, _)| *id == self.id)
+        {
+            endpoints.remove(&self.addr);
+        }
+    }
+}
@@ -0,0 +1,59 @@
+﻿use crate::decode_error::DecodeError;
+use crate::stream_reader::StreamReader;
+use crate::stream_writer::StreamWriter;
+
+/// Maps signed integers onto unsigned ones so small magnitudes stay small:
+/// 0, -1, 1, -2, 2... become 0, 1, 2, 3, 4...
+pub fn zigzag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+pub fn zigzag_decode(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// Integers that can be written as LEB128 varints, used by `#[stream(varint)]`.
+pub trait VarInt: Sized {
+    fn write_var(&self, stream: &mut StreamWriter);
+    fn try_read_var(stream_reader: &mut StreamReader<'_>) -> Result<Self, DecodeError>;
+}
+
+impl VarInt for u32 {
+    fn write_var(&self, stream: &mut StreamWriter) {
+        stream.write_var_u32(*self);
+    }
+
+    fn try_read_var(stream_reader: &mut StreamReader<'_>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_var_u32()
+    }
+}
+
+impl VarInt for u64 {
+    fn write_var(&self, stream: &mut StreamWriter) {
+        stream.write_var_u64(*self);
+    }
+
+    fn try_read_var(stream_reader: &mut StreamReader<'_>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_var_u64()
+    }
+}
+
+impl VarInt for i32 {
+    fn write_var(&self, stream: &mut StreamWriter) {
+        stream.write_var_i32(*self);
+    }
+
+    fn try_read_var(stream_reader: &mut StreamReader<'_>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_var_i32()
+    }
+}
+
+impl VarInt for i64 {
+    fn write_var(&self, stream: &mut StreamWriter) {
+        stream.write_var_i64(*self);
+    }
+
+    fn try_read_var(stream_reader: &mut StreamReader<'_>) -> Result<Self, DecodeError> {
+        stream_reader.try_read_var_i64()
+    }
+}
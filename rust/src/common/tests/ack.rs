@@ -0,0 +1,177 @@
+﻿use common::ack::{ACK_BITS, AckHeader, AckTracker, PacketStatus, sequence_greater_than};
+use common::message_header::{DataType, MessageHeader, MessageType};
+use common::stream_reader::StreamReader;
+use common::stream_writer::StreamWriter;
+
+fn deliver(to: &mut AckTracker, header: &AckHeader) -> (bool, Vec<(u16, PacketStatus)>) {
+    let mut resolved = Vec::new();
+    let fresh = to.receive(header, |sequence, status| resolved.push((sequence, status)));
+    (fresh, resolved)
+}
+
+#[test]
+fn sequences_compare_across_wrap_around() {
+    assert!(sequence_greater_than(1, 0));
+    assert!(!sequence_greater_than(0, 1));
+    assert!(sequence_greater_than(0, u16::MAX));
+    assert!(sequence_greater_than(10, 65500));
+    assert!(!sequence_greater_than(65500, 10));
+}
+
+#[test]
+fn packets_left_unacknowledged_are_lost() {
+    let mut client = AckTracker::new();
+    let mut server = AckTracker::new();
+
+    // Only the first packet could not be handled.
+    let first = server.send();
+    assert!(client.receive_unacknowledged(&first, |_, _| {}));
+    for _ in 0..ACK_BITS + 1 {
+        let header = server.send();
+        assert!(client.receive_unacknowledged(&header, |_, _| {}));
+        client.acknowledge(header.sequence);
+        // Still fresh should it come again, until too old.
+        if header.sequence == 1 {
+            assert!(client.receive_unacknowledged(&first, |_, _| {}));
+        }
+    }
+    assert!(!client.receive_unacknowledged(&first, |_, _| {}));
+
+    let (_, resolved) = deliver(&mut server, &client.send());
+    assert_eq!(resolved.len(), ACK_BITS as usize + 2);
+    assert_eq!(resolved[0], (0, PacketStatus::Lost));
+    assert!(
+        resolved[1..]
+            .iter()
+            .all(|(_, status)| *status == PacketStatus::Delivered)
+    );
+}
+
+#[test]
+fn acknowledged_packets_are_delivered() {
+    let mut client = AckTracker::new();
+    let mut server = AckTracker::new();
+
+    for _ in 0..3 {
+        let header = client.send();
+        assert!(deliver(&mut server, &header).0);
+    }
+
+    let (fresh, resolved) = deliver(&mut client, &server.send());
+    assert!(fresh);
+    assert_eq!(
+        resolved,
+        vec![
+            (0, PacketStatus::Delivered),
+            (1, PacketStatus::Delivered),
+            (2, PacketStatus::Delivered)
+        ]
+    );
+    assert_eq!(client.delivered_packets, 3);
+    assert_eq!(client.packet_loss(), 0.0);
+}
+
+#[test]
+fn nothing_is_acknowledged_before_the_peer_receives() {
+    let mut client = AckTracker::new();
+    let mut server = AckTracker::new();
+
+    client.send();
+    let (_, resolved) = deliver(&mut client, &server.send());
+    assert!(resolved.is_empty());
+}
+
+#[test]
+fn packets_out_of_the_window_are_lost() {
+    let mut client = AckTracker::new();
+    let mut server = AckTracker::new();
+
+    let lost = client.send();
+    for _ in 0..32 {
+        let header = client.send();
+        deliver(&mut server, &header);
+    }
+
+    let (_, resolved) = deliver(&mut client, &server.send());
+    assert_eq!(resolved.len(), 32);
+    assert!(
+        resolved
+            .iter()
+            .all(|(_, status)| *status == PacketStatus::Delivered)
+    );
+
+    // Acknowledging one more pushes the first packet out of reach.
+    let header = client.send();
+    deliver(&mut server, &header);
+    let (_, resolved) = deliver(&mut client, &server.send());
+    assert_eq!(
+        resolved,
+        vec![
+            (lost.sequence, PacketStatus::Lost),
+            (header.sequence, PacketStatus::Delivered)
+        ]
+    );
+    assert_eq!(client.lost_packets, 1);
+    assert_eq!(client.delivered_packets, 33);
+}
+
+#[test]
+fn out_of_order_packets_are_acknowledged() {
+    let mut client = AckTracker::new();
+    let mut server = AckTracker::new();
+
+    let headers: Vec<_> = (0..5).map(|_| client.send()).collect();
+    for i in [4, 0, 2, 1] {
+        assert!(deliver(&mut server, &headers[i]).0);
+    }
+
+    let (_, resolved) = deliver(&mut client, &server.send());
+    let mut delivered: Vec<_> = resolved.iter().map(|(sequence, _)| *sequence).collect();
+    delivered.sort();
+    assert_eq!(delivered, vec![0, 1, 2, 4]);
+
+    // 3 is still within the window and may yet arrive.
+    assert!(deliver(&mut server, &headers[3]).0);
+    let (_, resolved) = deliver(&mut client, &server.send());
+    assert_eq!(resolved, vec![(3, PacketStatus::Delivered)]);
+}
+
+#[test]
+fn duplicates_and_stale_packets_are_dropped() {
+    let mut client = AckTracker::new();
+    let mut server = AckTracker::new();
+
+    let first = client.send();
+    assert!(deliver(&mut server, &first).0);
+    assert!(!deliver(&mut server, &first).0);
+
+    let headers: Vec<_> = (0..40).map(|_| client.send()).collect();
+    assert!(deliver(&mut server, &headers[39]).0);
+    assert!(!deliver(&mut server, &headers[0]).0);
+    assert!(deliver(&mut server, &headers[20]).0);
+    assert!(!deliver(&mut server, &headers[20]).0);
+    assert_eq!(server.duplicate_packets, 3);
+}
+
+#[test]
+fn acks_are_written_into_the_header() {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable(MessageHeader::init(MessageType::Data, DataType::Input));
+    stream_writer.write_u32(7);
+    assert_eq!(stream_writer.get_data().len(), MessageHeader::SIZE + 4);
+
+    let acks = AckHeader {
+        sequence: 12,
+        ack: 34,
+        ack_bits: 0xdead_beef,
+    };
+    MessageHeader::write_acks(stream_writer.get_data_mut(), acks);
+    MessageHeader::seal(stream_writer.get_data_mut());
+
+    let packet = stream_writer.get_data();
+    assert_eq!(MessageHeader::verify(packet), Ok(()));
+    let mut stream_reader = StreamReader::new(packet);
+    let header: MessageHeader = stream_reader.try_read_serializable().unwrap();
+    assert_eq!(header.acks, acks);
+    assert_eq!(stream_reader.try_read_u32(), Ok(7));
+}
@@ -0,0 +1,51 @@
+﻿use common::backoff::Backoff;
+use std::time::{Duration, Instant};
+
+const INITIAL: Duration = Duration::from_millis(100);
+const MAX: Duration = Duration::from_secs(2);
+
+#[test]
+fn delay_doubles_up_to_the_maximum() {
+    let mut backoff = Backoff::new(INITIAL, MAX);
+    let now = Instant::now();
+
+    let delays: Vec<_> = (0..8).map(|_| backoff.fail(now).as_millis()).collect();
+    assert_eq!(delays, [100, 200, 400, 800, 1600, 2000, 2000, 2000]);
+    assert_eq!(backoff.failures(), 8);
+
+    for _ in 0..100 {
+        backoff.fail(now);
+    }
+    assert_eq!(backoff.fail(now), MAX);
+}
+
+#[test]
+fn attempts_wait_for_the_delay() {
+    let mut backoff = Backoff::new(INITIAL, MAX);
+    let now = Instant::now();
+    assert!(backoff.is_ready(now));
+
+    backoff.fail(now);
+    assert!(!backoff.is_ready(now));
+    assert!(!backoff.is_ready(now + INITIAL / 2));
+    assert!(backoff.is_ready(now + INITIAL));
+
+    let later = now + INITIAL;
+    backoff.fail(later);
+    assert!(!backoff.is_ready(later + INITIAL));
+    assert!(backoff.is_ready(later + INITIAL * 2));
+}
+
+#[test]
+fn success_resets_the_delay() {
+    let mut backoff = Backoff::new(INITIAL, MAX);
+    let now = Instant::now();
+    for _ in 0..4 {
+        backoff.fail(now);
+    }
+
+    backoff.reset();
+    assert!(backoff.is_ready(now));
+    assert_eq!(backoff.failures(), 0);
+    assert_eq!(backoff.fail(now), INITIAL);
+}
@@ -0,0 +1,198 @@
+﻿use common::bit_reader::BitReader;
+use common::bit_writer::{BitWriter, bits_required};
+use common::decode_error::DecodeErrorKind;
+use common::stream_reader::StreamReader;
+use common::stream_writer::StreamWriter;
+
+#[test]
+fn bits_required_covers_the_range() {
+    assert_eq!(bits_required(0), 0);
+    assert_eq!(bits_required(1), 1);
+    assert_eq!(bits_required(2), 2);
+    assert_eq!(bits_required(255), 8);
+    assert_eq!(bits_required(256), 9);
+    assert_eq!(bits_required(u32::MAX), 32);
+}
+
+#[test]
+fn bits_are_packed_least_significant_first() {
+    let mut writer = BitWriter::new();
+    writer.write_bits(0b101, 3);
+    writer.write_bits(0b11, 2);
+    writer.write_bool(true);
+    assert_eq!(writer.bits_written(), 6);
+
+    assert_eq!(writer.into_bytes(), [0b0011_1101]);
+}
+
+#[test]
+fn values_round_trip_across_byte_boundaries() {
+    let mut writer = BitWriter::new();
+    writer.write_bool(true);
+    writer.write_u32(u32::MAX);
+    writer.write_bits(0x1234, 13);
+    writer.write_u8(0xa5);
+    writer.write_bool(false);
+    writer.write_u16(0xbeef);
+    writer.write_f32(-1.5);
+    writer.write_bits(7, 0);
+    writer.write_u32(0);
+    let bytes = writer.into_bytes();
+    assert_eq!(
+        bytes.len(),
+        (1 + 32 + 13 + 8 + 1 + 16 + 32 + 32usize).div_ceil(8)
+    );
+
+    let mut reader = BitReader::new(&bytes);
+    assert!(reader.try_read_bool().unwrap());
+    assert_eq!(reader.try_read_u32().unwrap(), u32::MAX);
+    assert_eq!(reader.try_read_bits(13).unwrap(), 0x1234);
+    assert_eq!(reader.try_read_u8().unwrap(), 0xa5);
+    assert!(!reader.try_read_bool().unwrap());
+    assert_eq!(reader.try_read_u16().unwrap(), 0xbeef);
+    assert_eq!(reader.try_read_f32().unwrap(), -1.5);
+    assert_eq!(reader.try_read_bits(0).unwrap(), 0);
+    assert_eq!(reader.try_read_u32().unwrap(), 0);
+    assert!(reader.bits_remaining() < 8);
+}
+
+#[test]
+fn ranged_values_use_only_the_bits_they_need() {
+    let mut writer = BitWriter::new();
+    writer.write_ranged_u32(10, 10, 17);
+    writer.write_ranged_u32(17, 10, 17);
+    writer.write_ranged_i32(-5, -5, 2);
+    writer.write_ranged_i32(2, -5, 2);
+    writer.write_ranged_u32(4, 4, 4);
+    assert_eq!(writer.bits_written(), 12);
+
+    let bytes = writer.into_bytes();
+    let mut reader = BitReader::new(&bytes);
+    assert_eq!(reader.try_read_ranged_u32(10, 17).unwrap(), 10);
+    assert_eq!(reader.try_read_ranged_u32(10, 17).unwrap(), 17);
+    assert_eq!(reader.try_read_ranged_i32(-5, 2).unwrap(), -5);
+    assert_eq!(reader.try_read_ranged_i32(-5, 2).unwrap(), 2);
+    assert_eq!(reader.try_read_ranged_u32(4, 4).unwrap(), 4);
+}
+
+#[test]
+fn ranged_values_are_clamped() {
+    let mut writer = BitWriter::new();
+    writer.write_ranged_u32(3, 10, 20);
+    writer.write_ranged_u32(u32::MAX, 10, 20);
+    writer.write_ranged_i32(i32::MIN, -100, 100);
+    writer.write_ranged_i32(i32::MAX, -100, 100);
+    let bytes = writer.into_bytes();
+
+    let mut reader = BitReader::new(&bytes);
+    assert_eq!(reader.try_read_ranged_u32(10, 20).unwrap(), 10);
+    assert_eq!(reader.try_read_ranged_u32(10, 20).unwrap(), 20);
+    assert_eq!(reader.try_read_ranged_i32(-100, 100).unwrap(), -100);
+    assert_eq!(reader.try_read_ranged_i32(-100, 100).unwrap(), 100);
+}
+
+#[test]
+fn full_i32_range_round_trips() {
+    let mut writer = BitWriter::new();
+    for value in [i32::MIN, -1, 0, 1, i32::MAX] {
+        writer.write_ranged_i32(value, i32::MIN, i32::MAX);
+    }
+    assert_eq!(writer.bits_written(), 5 * 32);
+    let bytes = writer.into_bytes();
+
+    let mut reader = BitReader::new(&bytes);
+    for value in [i32::MIN, -1, 0, 1, i32::MAX] {
+        assert_eq!(
+            reader.try_read_ranged_i32(i32::MIN, i32::MAX).unwrap(),
+            value
+        );
+    }
+}
+
+#[test]
+fn ranged_value_outside_the_range_is_rejected() {
+    let mut writer = BitWriter::new();
+    writer.write_bits(7, 3);
+    let bytes = writer.into_bytes();
+
+    let error = BitReader::new(&bytes)
+        .try_read_ranged_u32(0, 4)
+        .unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::InvalidValue);
+    assert_eq!(error.actual, 7);
+}
+
+#[test]
+fn reading_past_the_end_is_rejected() {
+    let mut writer = BitWriter::new();
+    writer.write_bits(0x3ff, 10);
+    let bytes = writer.into_bytes();
+
+    let mut reader = BitReader::new(&bytes);
+    assert_eq!(reader.try_read_bits(10).unwrap(), 0x3ff);
+    let error = reader.try_read_u8().unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::UnexpectedEnd);
+    assert_eq!(error.offset, 10);
+    assert_eq!(error.expected, 8);
+    assert_eq!(error.actual, 6);
+
+    assert!(BitReader::new(&[]).try_read_bool().is_err());
+    assert!(BitReader::new(&[0xff; 3]).try_read_u32().is_err());
+}
+
+#[test]
+fn align_skips_to_the_next_byte() {
+    let mut writer = BitWriter::new();
+    writer.write_bits(1, 3);
+    writer.align();
+    writer.align();
+    writer.write_u8(0x42);
+    let bytes = writer.into_bytes();
+    assert_eq!(bytes, [1, 0x42]);
+
+    let mut reader = BitReader::new(&bytes);
+    assert_eq!(reader.try_read_bits(3).unwrap(), 1);
+    reader.align();
+    reader.align();
+    assert_eq!(reader.try_read_u8().unwrap(), 0x42);
+    assert_eq!(reader.bits_remaining(), 0);
+}
+
+#[test]
+fn slices_round_trip_and_are_truncated_to_the_maximum() {
+    let mut writer = BitWriter::new();
+    writer.write_serializable_slice(&[1u8, 2, 3], 8);
+    writer.write_serializable_slice(&[true, false, true], 2);
+    let bytes = writer.into_bytes();
+
+    let mut reader = BitReader::new(&bytes);
+    assert_eq!(
+        reader.try_read_serializable_vec::<u8>(8).unwrap(),
+        [1, 2, 3]
+    );
+    assert_eq!(
+        reader.try_read_serializable_vec::<bool>(2).unwrap(),
+        [true, false]
+    );
+}
+
+#[test]
+fn bit_packed_blocks_are_embedded_in_byte_streams() {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_u8(9);
+    stream_writer.write_bit_packed(&0xdead_beefu32);
+    stream_writer.write_u8(10);
+    let bytes = stream_writer.get_data().to_vec();
+
+    let mut stream_reader = StreamReader::new(&bytes);
+    assert_eq!(stream_reader.try_read_u8().unwrap(), 9);
+    assert_eq!(
+        stream_reader.try_read_bit_packed::<u32>().unwrap(),
+        0xdead_beef
+    );
+    assert_eq!(stream_reader.try_read_u8().unwrap(), 10);
+
+    let mut stream_reader = StreamReader::new(&bytes[..4]);
+    stream_reader.try_read_u8().unwrap();
+    assert!(stream_reader.try_read_bit_packed::<u32>().is_err());
+}
@@ -0,0 +1,147 @@
+﻿use common::decode_error::DecodeErrorKind;
+use common::replicated_node::ReplicatedNode;
+use common::stream_reader::{OwnedStreamReader, StreamReader};
+use common::stream_writer::StreamWriter;
+use std::borrow::Cow;
+
+fn points_into(buffer: &[u8], slice: &[u8]) -> bool {
+    let range = buffer.as_ptr_range();
+    range.start <= slice.as_ptr() && slice.as_ptr_range().end <= range.end
+}
+
+fn node(net_id: u32, data: Vec<u8>) -> ReplicatedNode<'static> {
+    ReplicatedNode {
+        net_id,
+        type_id: 1,
+        data: Cow::Owned(data),
+    }
+}
+
+#[test]
+fn bytes_are_borrowed_from_the_buffer() {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_u8(9);
+    stream_writer.write_serializable(vec![1u8, 2, 3]);
+    stream_writer.write_serializable(Vec::<u8>::new());
+    let bytes = stream_writer.get_data().to_vec();
+
+    let mut stream_reader = StreamReader::new(&bytes);
+    stream_reader.try_read_u8().unwrap();
+    let data = stream_reader.try_read_bytes().unwrap();
+    assert_eq!(data, [1, 2, 3]);
+    assert_eq!(data.as_ptr(), bytes[2..].as_ptr());
+
+    assert!(stream_reader.try_read_bytes().unwrap().is_empty());
+    assert!(!stream_reader.remain_data());
+}
+
+#[test]
+fn raw_slices_and_the_rest_are_borrowed() {
+    let bytes = [1, 2, 3, 4, 5];
+    let mut stream_reader = StreamReader::new(&bytes);
+
+    let head = stream_reader.try_read_slice(2).unwrap();
+    assert_eq!(head.as_ptr(), bytes.as_ptr());
+    assert_eq!(stream_reader.get_cursor(), 2);
+    assert_eq!(stream_reader.get_rest_buffer(), [3, 4, 5]);
+    assert!(points_into(&bytes, stream_reader.get_rest_buffer()));
+
+    let error = stream_reader.try_read_slice(4).unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::UnexpectedEnd);
+    assert_eq!(error.offset, 2);
+    assert_eq!(error.expected, 4);
+    assert_eq!(error.actual, 3);
+    // A failed read leaves the cursor where it was.
+    assert_eq!(stream_reader.try_read_slice(3).unwrap(), [3, 4, 5]);
+}
+
+#[test]
+fn byte_length_past_the_limit_is_rejected() {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable(vec![0u8; 9]);
+    let bytes = stream_writer.get_data().to_vec();
+
+    let error = StreamReader::with_max_vec_len(&bytes, 8)
+        .try_read_bytes()
+        .unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::LengthTooLarge);
+
+    let error = StreamReader::new(&bytes[..5]).try_read_bytes().unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::UnexpectedEnd);
+}
+
+#[test]
+fn decoded_nodes_borrow_their_data() {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable_slice(&[node(1, vec![10, 11]), node(2, vec![20])]);
+    let bytes = stream_writer.get_data().to_vec();
+
+    let nodes: Vec<ReplicatedNode> = StreamReader::new(&bytes)
+        .try_read_serializable_vec()
+        .unwrap();
+    assert_eq!(nodes.len(), 2);
+    for node in &nodes {
+        assert!(matches!(node.data, Cow::Borrowed(_)));
+        assert!(points_into(&bytes, &node.data));
+    }
+    assert_eq!(*nodes[0].data, [10, 11]);
+    assert_eq!(*nodes[1].data, [20]);
+}
+
+#[test]
+fn nested_payloads_are_read_without_copying() {
+    let mut inner = StreamWriter::new();
+    inner.write_u32(0xfeed);
+    inner.write_string("inner");
+
+    let mut outer = StreamWriter::new();
+    outer.write_u8(1);
+    outer.write_serializable(inner.get_data().to_vec());
+    outer.write_u8(2);
+    let bytes = outer.get_data().to_vec();
+
+    let mut stream_reader = StreamReader::new(&bytes);
+    assert_eq!(stream_reader.try_read_u8().unwrap(), 1);
+    let payload = stream_reader.try_read_bytes().unwrap();
+    assert!(points_into(&bytes, payload));
+    assert_eq!(stream_reader.try_read_u8().unwrap(), 2);
+
+    let mut payload_reader = StreamReader::new(payload);
+    assert_eq!(payload_reader.try_read_u32().unwrap(), 0xfeed);
+    assert_eq!(payload_reader.try_read_string().unwrap(), "inner");
+    assert!(!payload_reader.remain_data());
+}
+
+#[test]
+fn owned_reader_outlives_the_received_buffer() {
+    let owned = {
+        let mut stream_writer = StreamWriter::new();
+        stream_writer.write_serializable(node(5, vec![1, 2, 3]));
+        OwnedStreamReader::new(stream_writer.get_data().to_vec())
+    };
+
+    // Every reader starts from the beginning.
+    for _ in 0..2 {
+        let node: ReplicatedNode = owned.reader().try_read_serializable().unwrap();
+        assert_eq!(node.net_id, 5);
+        assert_eq!(*node.data, [1, 2, 3]);
+        assert!(matches!(node.data, Cow::Borrowed(_)));
+    }
+}
+
+#[test]
+fn borrowed_nodes_can_be_made_owned() {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable(node(3, vec![7, 8]));
+    let bytes = stream_writer.get_data().to_vec();
+
+    let owned: ReplicatedNode<'static> = {
+        let node: ReplicatedNode = StreamReader::new(&bytes).try_read_serializable().unwrap();
+        ReplicatedNode {
+            data: Cow::Owned(node.data.into_owned()),
+            ..node
+        }
+    };
+    drop(bytes);
+    assert_eq!(*owned.data, [7, 8]);
+}
@@ -0,0 +1,150 @@
+﻿use common::challenge::{COOKIE_LIFETIME, Cookie, CookieIssuer, HANDSHAKE_PACKET_SIZE};
+use common::handshake::{Handshake, KeyShare, ProtocolInfo, RejectReason, Rejection};
+use common::message_header::MessageHeader;
+use common::session::{ENCRYPTION_OVERHEAD, PUBLIC_KEY_SIZE};
+use common::sha256::{Sha256, hmac_sha256, sha256};
+use common::stream_reader::StreamReader;
+use common::stream_writer::StreamWriter;
+
+const ADDR: &str = "127.0.0.1:40000";
+const NOW: u64 = 1_700_000_000;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn serialized_size<T: common::stream_writer::Serializable>(value: T) -> usize {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable(value);
+    stream_writer.get_data().len()
+}
+
+#[test]
+fn sha256_matches_known_digests() {
+    assert_eq!(
+        hex(&sha256(b"")),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(
+        hex(&sha256(b"abc")),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(
+        hex(&sha256(
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+        )),
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+}
+
+#[test]
+fn sha256_updates_in_pieces() {
+    let data: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+    let mut hasher = Sha256::new();
+    for chunk in data.chunks(37) {
+        hasher.update(chunk);
+    }
+    assert_eq!(hasher.finalize(), sha256(&data));
+}
+
+#[test]
+fn hmac_matches_rfc_4231() {
+    assert_eq!(
+        hex(&hmac_sha256(&[0x0b; 20], b"Hi There")),
+        "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+    );
+    assert_eq!(
+        hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+        "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+    // Keys longer than a block are hashed first.
+    assert_eq!(
+        hex(&hmac_sha256(
+            &[0xaa; 131],
+            b"Test Using Larger Than Block-Size Key - Hash Key First"
+        )),
+        "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+    );
+}
+
+#[test]
+fn issued_cookie_is_accepted() {
+    let issuer = CookieIssuer::new([7; 32]);
+    let cookie = issuer.issue(ADDR, NOW);
+    assert!(issuer.verify(ADDR, &cookie, NOW));
+    assert!(issuer.verify(ADDR, &cookie, NOW + COOKIE_LIFETIME.as_secs()));
+}
+
+#[test]
+fn cookie_is_bound_to_its_address() {
+    let issuer = CookieIssuer::new([7; 32]);
+    let cookie = issuer.issue(ADDR, NOW);
+    assert!(!issuer.verify("127.0.0.1:40001", &cookie, NOW));
+}
+
+#[test]
+fn cookie_expires() {
+    let issuer = CookieIssuer::new([7; 32]);
+    let cookie = issuer.issue(ADDR, NOW);
+    assert!(!issuer.verify(ADDR, &cookie, NOW + COOKIE_LIFETIME.as_secs() + 1));
+    // Not issued yet, e.g. after the server clock went back.
+    assert!(!issuer.verify(ADDR, &cookie, NOW - 1));
+}
+
+#[test]
+fn forged_cookies_are_refused() {
+    let issuer = CookieIssuer::new([7; 32]);
+    let cookie = issuer.issue(ADDR, NOW);
+
+    let mut tampered = cookie;
+    tampered.mac[0] ^= 1;
+    assert!(!issuer.verify(ADDR, &tampered, NOW));
+
+    // Moving the timestamp forward does not extend the lifetime.
+    let mut renewed = cookie;
+    renewed.timestamp += 5;
+    assert!(!issuer.verify(ADDR, &renewed, NOW + 5));
+
+    let other_server = CookieIssuer::new([8; 32]);
+    assert!(!other_server.verify(ADDR, &cookie, NOW));
+}
+
+#[test]
+fn cookie_round_trips() {
+    let cookie = CookieIssuer::new([7; 32]).issue(ADDR, NOW);
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable(cookie);
+
+    let mut stream_reader = StreamReader::new(stream_writer.get_data());
+    let read: Cookie = stream_reader.try_read_serializable().unwrap();
+    assert_eq!(read, cookie);
+}
+
+#[test]
+fn replies_fit_in_padded_requests() {
+    let cookie = CookieIssuer::new([7; 32]).issue(ADDR, NOW);
+    let protocol = ProtocolInfo::current();
+
+    let challenge = MessageHeader::SIZE + serialized_size(cookie);
+    let handshake = MessageHeader::SIZE
+        + serialized_size(protocol)
+        + serialized_size(KeyShare {
+            public_key: [0; PUBLIC_KEY_SIZE],
+        })
+        + ENCRYPTION_OVERHEAD
+        + serialized_size(Handshake {
+            client_id: u32::MAX,
+            server_frequency: 60.0,
+            compression: true,
+        });
+    let mismatched = ProtocolInfo {
+        version: u16::MAX,
+        schema_hash: u64::MAX,
+    };
+    let rejections = [RejectReason::ProtocolVersion, RejectReason::SchemaHash]
+        .map(|reason| MessageHeader::SIZE + serialized_size(Rejection::new(reason, &mismatched)));
+
+    for reply in [challenge, handshake].into_iter().chain(rejections) {
+        assert!(reply <= HANDSHAKE_PACKET_SIZE, "{} bytes", reply);
+    }
+}
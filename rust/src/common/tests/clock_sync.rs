@@ -0,0 +1,188 @@
+﻿use common::clock_sync::{CLOCK_SAMPLES, ClockSync, MAX_DRIFT, MAX_SLEW_RATE, MIN_CLOCK_SAMPLES};
+use common::ping_request::PingResponse;
+
+const FREQUENCY: f64 = 30.0;
+const FRAME: f64 = 1.0 / FREQUENCY;
+
+/// Server started `started_at` milliseconds into the client's clock, whose
+/// clocks run `drift` faster than the client's.
+struct Server {
+    started_at: f64,
+    wall_offset: f64,
+    drift: f64,
+}
+
+impl Server {
+    fn new(started_at: f64) -> Self {
+        Self {
+            started_at,
+            wall_offset: 1_700_000_000_000.0,
+            drift: 0.0,
+        }
+    }
+
+    /// Seconds simulated at `local` milliseconds on the client's clock.
+    fn time(&self, local: f64) -> f64 {
+        (local - self.started_at) * (1.0 + self.drift) / 1000.0
+    }
+
+    /// Answer to a ping sent at `sent` and taking `up` milliseconds to arrive.
+    fn answer(&self, sent: u64, up: u64) -> PingResponse {
+        let answered = (sent + up) as f64;
+        PingResponse {
+            time_client_request: sent,
+            time_server_response: (answered + self.wall_offset) as u64,
+            server_frame: (self.time(answered) * FREQUENCY) as u32,
+        }
+    }
+}
+
+/// Pings `server` about once a second from `from` for `count` seconds, the
+/// round trips given by `delays` as the milliseconds up and down.
+fn ping(
+    clock_sync: &mut ClockSync,
+    server: &Server,
+    from: u64,
+    count: u64,
+    delays: impl Fn(u64) -> (u64, u64),
+) -> u64 {
+    for i in 0..count {
+        // Off the frame grid, or the truncation of frames hides any drift.
+        let sent = from + i * 1000 + i * 7 % 33;
+        let (up, down) = delays(i);
+        let response = server.answer(sent, up);
+        clock_sync.add_sample(&response, sent + up + down).unwrap();
+    }
+    from + count * 1000
+}
+
+#[test]
+fn half_the_round_trip_is_accounted_for() {
+    let server = Server::new(-5000.0);
+    let mut clock_sync = ClockSync::new(FREQUENCY);
+    assert_eq!(clock_sync.server_time(0), None);
+
+    let now = ping(&mut clock_sync, &server, 0, 4, |_| (150, 150));
+    assert!(clock_sync.is_synchronized());
+    assert!((clock_sync.rtt() - 0.3).abs() < 1e-9);
+    assert!((clock_sync.offset() - server.wall_offset / 1000.0).abs() < 1e-3);
+
+    // The frame read from a ping is counted down from the tick, a frame at
+    // most behind.
+    let time = clock_sync.server_time(now).unwrap();
+    let expected = server.time(now as f64);
+    assert!(
+        time <= expected && expected - time < FRAME,
+        "{time} for {expected}"
+    );
+
+    let frame = clock_sync.server_frame(now).unwrap();
+    assert!((frame as f64 - expected * FREQUENCY).abs() <= 1.0);
+}
+
+#[test]
+fn delayed_samples_are_filtered() {
+    let server = Server::new(0.0);
+    let mut clock_sync = ClockSync::new(FREQUENCY);
+    // Every third answer waits in a queue on its way back.
+    let now = ping(&mut clock_sync, &server, 1000, CLOCK_SAMPLES as u64, |i| {
+        if i % 3 == 0 { (20, 900) } else { (20, 20) }
+    });
+
+    assert!((clock_sync.rtt() - 0.04).abs() < 1e-9);
+    let time = clock_sync.server_time(now).unwrap();
+    assert!((time - server.time(now as f64)).abs() < FRAME);
+}
+
+#[test]
+fn drift_is_estimated() {
+    let mut server = Server::new(0.0);
+    server.drift = MAX_DRIFT;
+    let mut clock_sync = ClockSync::new(FREQUENCY);
+    let now = ping(&mut clock_sync, &server, 1000, CLOCK_SAMPLES as u64, |_| {
+        (40, 40)
+    });
+
+    assert!(clock_sync.drift() > 0.0);
+    // Still right long after the last sample.
+    let later = now + 10_000;
+    let time = clock_sync.server_time(later).unwrap();
+    assert!((time - server.time(later as f64)).abs() < FRAME);
+}
+
+#[test]
+fn corrections_are_slewed_and_never_go_back() {
+    let server = Server::new(0.0);
+    let mut clock_sync = ClockSync::new(FREQUENCY);
+    let mut now = ping(
+        &mut clock_sync,
+        &server,
+        1000,
+        MIN_CLOCK_SAMPLES as u64,
+        |_| (30, 30),
+    );
+
+    // The server turns out to be a tenth of a second further.
+    let ahead = Server::new(-100.0);
+    let mut next_ping = now;
+    let mut served = clock_sync.server_time(now).unwrap();
+    let mut frame = clock_sync.server_frame(now).unwrap();
+    while now < 40_000 {
+        if now >= next_ping {
+            ping(&mut clock_sync, &ahead, next_ping, 1, |_| (30, 30));
+            next_ping += 1000;
+        }
+
+        now += 16;
+        let time = clock_sync.server_time(now).unwrap();
+        // Offset by a drift fitted across the jump at most.
+        let elapsed = 0.016;
+        assert!(time >= served);
+        assert!(time - served <= elapsed * (1.0 + MAX_SLEW_RATE + MAX_DRIFT) + 1e-9);
+        assert!(time - served >= elapsed * (1.0 - MAX_SLEW_RATE - MAX_DRIFT) - 1e-9);
+        served = time;
+
+        let next_frame = clock_sync.server_frame(now).unwrap();
+        assert!(next_frame == frame || next_frame == frame + 1);
+        frame = next_frame;
+    }
+    assert!((served - ahead.time(now as f64)).abs() < FRAME);
+}
+
+#[test]
+fn large_errors_jump_forward_and_wait_backward() {
+    let mut clock_sync = ClockSync::new(FREQUENCY);
+    let now = ping(&mut clock_sync, &Server::new(0.0), 1000, 4, |_| (30, 30));
+    let before = clock_sync.server_time(now).unwrap();
+
+    // A second further, jumped to at once.
+    let ahead = Server::new(-1000.0);
+    let now = ping(&mut clock_sync, &ahead, now, CLOCK_SAMPLES as u64, |_| {
+        (30, 30)
+    });
+    let time = clock_sync.server_time(now).unwrap();
+    assert!(time > before);
+    assert!((time - ahead.time(now as f64)).abs() < FRAME);
+
+    // Two seconds back, the served time slows down and stops until the
+    // estimate reaches it.
+    let behind = Server::new(1000.0);
+    let mut served = time;
+    let mut now = now;
+    for _ in 0..CLOCK_SAMPLES + 4 {
+        now = ping(&mut clock_sync, &behind, now, 1, |_| (30, 30));
+        let time = clock_sync.server_time(now).unwrap();
+        assert!(time >= served);
+        served = time;
+    }
+    assert!((served - behind.time(now as f64)).abs() < FRAME);
+}
+
+#[test]
+fn answers_received_before_sent_are_ignored() {
+    let server = Server::new(0.0);
+    let mut clock_sync = ClockSync::new(FREQUENCY);
+    let response = server.answer(5000, 20);
+    assert_eq!(clock_sync.add_sample(&response, 4000), None);
+    assert_eq!(clock_sync.server_time(5000), None);
+}
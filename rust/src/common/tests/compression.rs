@@ -0,0 +1,151 @@
+﻿use common::compression::{
+    Compressor, MAX_DECOMPRESSED_SIZE, compress_block, decompress, decompress_block,
+};
+use common::message_header::{DataType, MessageHeader, MessageType};
+use common::stream_writer::StreamWriter;
+
+fn message(message_type: MessageType, body: &[u8]) -> Vec<u8> {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable(MessageHeader::init(message_type, DataType::Replication));
+    stream_writer.write_bytes(body);
+    stream_writer.get_data().to_vec()
+}
+
+/// Bytes that do not repeat, from a linear congruential generator.
+fn noise(len: usize) -> Vec<u8> {
+    let mut state: u32 = 0x1234_5678;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 24) as u8
+        })
+        .collect()
+}
+
+fn repetitive(len: usize) -> Vec<u8> {
+    (0..len).map(|i| [1, 0, 0, 0, 42, 7][i % 6]).collect()
+}
+
+fn round_trip_block(input: &[u8]) {
+    let mut block = Vec::new();
+    compress_block(input, &mut block);
+    let mut output = Vec::new();
+    decompress_block(&block, input.len(), &mut output).unwrap();
+    assert_eq!(output, input);
+}
+
+#[test]
+fn blocks_round_trip() {
+    round_trip_block(&[]);
+    round_trip_block(&[9; 3]);
+    round_trip_block(&[9; 13]);
+    round_trip_block(&noise(2000));
+    round_trip_block(&repetitive(5000));
+    // Long literal runs followed by long matches.
+    let mut mixed = noise(300);
+    mixed.extend(repetitive(700));
+    mixed.extend(noise(20));
+    round_trip_block(&mixed);
+}
+
+#[test]
+fn repetitive_messages_shrink() {
+    let original = message(MessageType::Data, &repetitive(1000));
+    let mut packet = original.clone();
+    let mut compressor = Compressor::new();
+
+    let compressed = compressor.compress(&mut packet).to_vec();
+    assert!(compressed.len() < original.len() / 4);
+    assert_ne!(
+        MessageHeader::flags(&compressed) & MessageHeader::COMPRESSED,
+        0
+    );
+
+    let decompressed = decompress(&compressed).unwrap();
+    assert_eq!(decompressed.as_ref(), original.as_slice());
+
+    let stats = compressor.stats_for(MessageType::Data);
+    assert_eq!(stats.messages, 1);
+    assert_eq!(stats.compressed_messages, 1);
+    assert_eq!(stats.original_bytes, original.len() as u64);
+    assert_eq!(stats.sent_bytes, compressed.len() as u64);
+    assert!(stats.ratio() < 0.25);
+}
+
+#[test]
+fn incompressible_messages_are_sent_as_is() {
+    let original = message(MessageType::Data, &noise(500));
+    let mut packet = original.clone();
+    let mut compressor = Compressor::new();
+
+    assert_eq!(compressor.compress(&mut packet), original.as_slice());
+    assert_eq!(decompress(&original).unwrap().as_ref(), original.as_slice());
+
+    let stats = compressor.stats_for(MessageType::Data);
+    assert_eq!(stats.messages, 1);
+    assert_eq!(stats.compressed_messages, 0);
+    assert_eq!(stats.ratio(), 1.0);
+}
+
+#[test]
+fn handshake_messages_are_never_compressed() {
+    let original = message(MessageType::Hsk, &repetitive(1000));
+    let mut packet = original.clone();
+    let mut compressor = Compressor::new();
+
+    assert_eq!(compressor.compress(&mut packet), original.as_slice());
+    assert!(compressor.stats().is_empty());
+}
+
+#[test]
+fn stats_are_kept_per_message_type() {
+    let mut compressor = Compressor::new();
+    compressor.compress(&mut message(MessageType::Data, &repetitive(600)));
+    compressor.compress(&mut message(MessageType::Reliable, &noise(600)));
+    compressor.compress(&mut message(MessageType::Data, &repetitive(600)));
+
+    assert_eq!(compressor.stats().len(), 2);
+    assert_eq!(
+        compressor.stats_for(MessageType::Data).compressed_messages,
+        2
+    );
+    assert_eq!(
+        compressor
+            .stats_for(MessageType::Reliable)
+            .compressed_messages,
+        0
+    );
+    assert_eq!(compressor.stats_for(MessageType::Ping).messages, 0);
+}
+
+#[test]
+fn malformed_blocks_are_rejected() {
+    let mut compressor = Compressor::new();
+    let compressed = compressor
+        .compress(&mut message(MessageType::Data, &repetitive(1000)))
+        .to_vec();
+
+    assert!(decompress(&compressed[..compressed.len() - 1]).is_err());
+
+    // A copy reaching before the start of the output.
+    let mut output = Vec::new();
+    assert!(decompress_block(&[0x10, 7, 5, 0], 100, &mut output).is_err());
+
+    // Expanding past the announced size.
+    let mut block = Vec::new();
+    compress_block(&repetitive(1000), &mut block);
+    assert!(decompress_block(&block, 999, &mut Vec::new()).is_err());
+}
+
+#[test]
+fn oversized_bodies_are_refused() {
+    let mut packet = message(MessageType::Data, &[]);
+    let flags = MessageHeader::flags(&packet) | MessageHeader::COMPRESSED;
+    MessageHeader::write_flags(&mut packet, flags);
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_var_u32(MAX_DECOMPRESSED_SIZE as u32 + 1);
+    packet.extend_from_slice(stream_writer.get_data());
+    packet.push(0);
+
+    assert!(decompress(&packet).is_err());
+}
@@ -0,0 +1,273 @@
+﻿use common::conditioner::{
+    ConditionedTransport, ConditionsError, JitterDistribution, MAX_QUEUE_DELAY, NetworkConditions,
+    REORDER_DELAY,
+};
+use common::transport::{MemoryNetwork, MemoryTransport, Transport};
+use std::time::{Duration, Instant};
+
+const SEED: u64 = 0x5EED;
+
+/// A plain client sending to a server whose incoming packets go through
+/// `conditions`.
+fn conditioned_server(
+    conditions: NetworkConditions,
+) -> (MemoryTransport, ConditionedTransport<MemoryTransport>) {
+    let network = MemoryNetwork::new();
+    let server = ConditionedTransport::with_seed(network.bind("server"), conditions, SEED);
+    (network.bind("client"), server)
+}
+
+/// Packets the server has received by `now`, as their first byte and index.
+fn received(server: &ConditionedTransport<MemoryTransport>, now: Instant) -> Vec<u16> {
+    let mut buf = [0; 16];
+    let mut packets = Vec::new();
+    while let Some((size, from)) = server.recv_from_at(&mut buf, now) {
+        assert_eq!(from, "client");
+        assert_eq!(size, 2);
+        packets.push(u16::from_le_bytes([buf[0], buf[1]]));
+    }
+    packets
+}
+
+fn send_numbered(client: &MemoryTransport, count: u16) {
+    for i in 0..count {
+        client.send_to("server", &i.to_le_bytes()).unwrap();
+    }
+}
+
+#[test]
+fn ideal_conditions_change_nothing() {
+    let conditions = NetworkConditions::default();
+    assert!(conditions.is_ideal());
+
+    let (client, server) = conditioned_server(conditions);
+    let now = Instant::now();
+    send_numbered(&client, 10);
+    assert_eq!(received(&server, now), (0..10).collect::<Vec<_>>());
+}
+
+#[test]
+fn latency_holds_packets_until_due() {
+    let (client, server) = conditioned_server(NetworkConditions {
+        latency: Duration::from_millis(100),
+        ..Default::default()
+    });
+    let now = Instant::now();
+    send_numbered(&client, 3);
+
+    assert!(received(&server, now).is_empty());
+    assert!(received(&server, now + Duration::from_millis(99)).is_empty());
+    assert_eq!(
+        received(&server, now + Duration::from_millis(100)),
+        vec![0, 1, 2]
+    );
+}
+
+#[test]
+fn sent_packets_go_out_once_due() {
+    let network = MemoryNetwork::new();
+    let server = network.bind("server");
+    let client = ConditionedTransport::with_seed(
+        network.bind("client"),
+        NetworkConditions {
+            latency: Duration::from_millis(50),
+            ..Default::default()
+        },
+        SEED,
+    );
+
+    let now = Instant::now();
+    client.send_to_at("server", b"input", now).unwrap();
+    let mut buf = [0; 16];
+    assert_eq!(server.recv_from(&mut buf), None);
+
+    // Polling the client lets it out.
+    assert_eq!(
+        client.recv_from_at(&mut buf, now + Duration::from_millis(50)),
+        None
+    );
+    assert_eq!(server.recv_from(&mut buf), Some((5, "client".to_string())));
+    assert_eq!(client.local_addr().unwrap(), "client");
+}
+
+#[test]
+fn jitter_spreads_delays_without_reordering() {
+    for jitter_distribution in [JitterDistribution::Uniform, JitterDistribution::Normal] {
+        let (client, server) = conditioned_server(NetworkConditions {
+            latency: Duration::from_millis(100),
+            jitter: Duration::from_millis(30),
+            jitter_distribution,
+            ..Default::default()
+        });
+        let now = Instant::now();
+        send_numbered(&client, 200);
+
+        let mut arrivals = Vec::new();
+        for millis in 0..300 {
+            let packets = received(&server, now + Duration::from_millis(millis));
+            arrivals.extend(packets.into_iter().map(|i| (i, millis)));
+        }
+        assert_eq!(
+            arrivals.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+            (0..200).collect::<Vec<_>>()
+        );
+        // Held back behind slower ones, but spread around the latency.
+        let first = arrivals.first().unwrap().1;
+        let last = arrivals.last().unwrap().1;
+        assert!(last - first > 20, "{:?}", jitter_distribution);
+        if jitter_distribution == JitterDistribution::Uniform {
+            assert!(first >= 70 && last <= 130, "from {} to {}", first, last);
+        }
+    }
+}
+
+#[test]
+fn loss_drops_the_given_share() {
+    let (client, server) = conditioned_server(NetworkConditions {
+        loss: 0.2,
+        ..Default::default()
+    });
+    send_numbered(&client, 10_000);
+
+    let count = received(&server, Instant::now()).len();
+    assert!((7_700..8_300).contains(&count), "{} received", count);
+}
+
+/// Average length of the runs of lost packets.
+fn mean_burst(received: &[u16], sent: u16) -> f64 {
+    let mut bursts = 0;
+    let mut lost = 0;
+    let mut expected = 0;
+    for &i in received.iter().chain([sent].iter()) {
+        if i > expected {
+            bursts += 1;
+            lost += i - expected;
+        }
+        expected = i + 1;
+    }
+    lost as f64 / bursts as f64
+}
+
+#[test]
+fn burst_loss_groups_losses() {
+    let independent = NetworkConditions {
+        loss: 0.05,
+        ..Default::default()
+    };
+    let (client, server) = conditioned_server(independent);
+    send_numbered(&client, 10_000);
+    let independent = mean_burst(&received(&server, Instant::now()), 10_000);
+
+    let (client, server) = conditioned_server(NetworkConditions {
+        loss: 0.05,
+        burst_loss: 0.8,
+        ..Default::default()
+    });
+    send_numbered(&client, 10_000);
+    let bursty = mean_burst(&received(&server, Instant::now()), 10_000);
+
+    assert!(independent < 1.2, "independent bursts of {}", independent);
+    assert!(bursty > 3.0, "bursts of {}", bursty);
+}
+
+#[test]
+fn duplication_delivers_twice() {
+    let (client, server) = conditioned_server(NetworkConditions {
+        duplication: 1.0,
+        ..Default::default()
+    });
+    send_numbered(&client, 3);
+    assert_eq!(received(&server, Instant::now()), vec![0, 0, 1, 1, 2, 2]);
+}
+
+#[test]
+fn reordered_packets_arrive_after_the_next_ones() {
+    let (client, server) = conditioned_server(NetworkConditions {
+        reorder: 0.3,
+        ..Default::default()
+    });
+    let now = Instant::now();
+    send_numbered(&client, 100);
+
+    let on_time = received(&server, now);
+    let late = received(&server, now + REORDER_DELAY);
+    assert!((15..45).contains(&late.len()), "{} reordered", late.len());
+    assert!(on_time.is_sorted() && late.is_sorted());
+
+    let mut all = [on_time, late].concat();
+    all.sort();
+    assert_eq!(all, (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn bandwidth_paces_packets_and_drops_past_the_queue() {
+    // A 2 byte packet every 10 ms.
+    let (client, server) = conditioned_server(NetworkConditions {
+        bandwidth: 200,
+        ..Default::default()
+    });
+    let now = Instant::now();
+    send_numbered(&client, 200);
+
+    assert!(received(&server, now).is_empty());
+    assert_eq!(received(&server, now + Duration::from_millis(10)), vec![0]);
+    assert_eq!(
+        received(&server, now + Duration::from_millis(50)),
+        vec![1, 2, 3, 4]
+    );
+
+    // What would wait past the queue is dropped.
+    let rest = received(&server, now + Duration::from_secs(10));
+    let queued = (MAX_QUEUE_DELAY.as_millis() / 10) as usize;
+    assert!((queued..=queued + 1).contains(&(rest.len() + 5)));
+    assert!(rest.is_sorted());
+}
+
+#[test]
+fn conditions_parse_from_flags() {
+    let conditions: NetworkConditions = "--latency 80 --jitter 12.5 --jitter-distribution normal \
+        --loss 5 --burst-loss 50 --duplicate 1 --reorder 2 --bandwidth 512"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        conditions,
+        NetworkConditions {
+            latency: Duration::from_millis(80),
+            jitter: Duration::from_micros(12_500),
+            jitter_distribution: JitterDistribution::Normal,
+            loss: 0.05,
+            burst_loss: 0.5,
+            duplication: 0.01,
+            reorder: 0.02,
+            bandwidth: 64_000,
+        }
+    );
+    assert!(!conditions.is_ideal());
+    assert_eq!("".parse(), Ok(NetworkConditions::default()));
+}
+
+#[test]
+fn bad_flags_are_refused() {
+    assert_eq!(
+        NetworkConditions::from_args(["--lag", "80"]),
+        Err(ConditionsError::UnknownFlag("--lag".to_string()))
+    );
+    assert_eq!(
+        NetworkConditions::from_args(["--loss"]),
+        Err(ConditionsError::MissingValue("--loss".to_string()))
+    );
+    for (flag, value) in [
+        ("--loss", "120"),
+        ("--latency", "-5"),
+        ("--jitter-distribution", "gaussian"),
+        ("--bandwidth", "fast"),
+    ] {
+        assert_eq!(
+            NetworkConditions::from_args([flag, value]),
+            Err(ConditionsError::InvalidValue {
+                flag: flag.to_string(),
+                value: value.to_string(),
+            })
+        );
+    }
+}
@@ -0,0 +1,170 @@
+﻿use common::decode_error::DecodeErrorKind;
+use common::stream_reader::{DEFAULT_MAX_STRING_LEN, StreamReader, TryDeserializable};
+use common::stream_writer::{Serializable, StreamWriter};
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+fn encode<T: Serializable>(value: &T) -> Vec<u8> {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable_ref(value);
+    stream_writer.get_data().to_vec()
+}
+
+fn round_trip<T>(value: T) -> Vec<u8>
+where
+    T: Serializable + for<'a> TryDeserializable<'a> + PartialEq + Debug,
+{
+    let bytes = encode(&value);
+    let mut stream_reader = StreamReader::new(&bytes);
+    assert_eq!(stream_reader.try_read_serializable::<T>().unwrap(), value);
+    assert!(!stream_reader.remain_data());
+    bytes
+}
+
+#[test]
+fn strings_round_trip() {
+    assert_eq!(round_trip(String::new()), [0]);
+    assert_eq!(round_trip("boat".to_string()), [4, b'b', b'o', b'a', b't']);
+
+    // The length prefix counts bytes, not characters.
+    let bytes = round_trip("été ⚓".to_string());
+    assert_eq!(bytes[0] as usize, "été ⚓".len());
+}
+
+#[test]
+fn string_at_the_length_limit_is_accepted() {
+    let exact = "é".repeat(DEFAULT_MAX_STRING_LEN / 2);
+    round_trip(exact);
+
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_string(&"x".repeat(20));
+    let bytes = stream_writer.get_data().to_vec();
+    let error = StreamReader::with_limits(&bytes, 16, 19)
+        .try_read_string()
+        .unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::LengthTooLarge);
+    assert_eq!(
+        StreamReader::with_limits(&bytes, 16, 20)
+            .try_read_string()
+            .unwrap()
+            .len(),
+        20
+    );
+}
+
+#[test]
+fn invalid_utf8_is_rejected() {
+    let bytes = [3, b'a', 0xc3, b'b'];
+    let error = StreamReader::new(&bytes).try_read_string().unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::InvalidValue);
+    assert_eq!(error.offset, 2);
+    assert_eq!(error.actual, 0xc3);
+
+    // A character cut by the length is invalid too.
+    let bytes = [1, 0xc3, 0xa9];
+    assert!(StreamReader::new(&bytes).try_read_string().is_err());
+}
+
+#[test]
+fn bools_only_accept_zero_and_one() {
+    assert_eq!(round_trip(false), [0]);
+    assert_eq!(round_trip(true), [1]);
+
+    for byte in [2, 0x80, 0xff] {
+        let error = StreamReader::new(&[byte]).try_read_bool().unwrap_err();
+        assert_eq!(error.kind, DecodeErrorKind::InvalidValue);
+        assert_eq!(error.actual, byte as usize);
+    }
+}
+
+#[test]
+fn options_round_trip() {
+    assert_eq!(round_trip(None::<u32>), [0]);
+    assert_eq!(round_trip(Some(7u16)), [1, 7, 0]);
+    round_trip(Some(Some(false)));
+    round_trip(Some(None::<u8>));
+
+    let error = StreamReader::new(&[2, 7])
+        .try_read_serializable::<Option<u8>>()
+        .unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::InvalidValue);
+    assert!(
+        StreamReader::new(&[1])
+            .try_read_serializable::<Option<u8>>()
+            .is_err()
+    );
+}
+
+#[test]
+fn tuples_are_written_field_by_field() {
+    assert_eq!(round_trip((1u8, 2u16)), [1, 2, 0]);
+    round_trip((true, -3i32, "a".to_string()));
+    round_trip((1u8, 2u32, 3u64, Some(4i16)));
+
+    assert!(
+        StreamReader::new(&[1, 2])
+            .try_read_serializable::<(u8, u16)>()
+            .is_err()
+    );
+}
+
+#[test]
+fn arrays_have_no_length_prefix() {
+    assert_eq!(round_trip([1u8, 2, 3]), [1, 2, 3]);
+    assert!(round_trip::<[u32; 0]>([]).is_empty());
+    round_trip([(1u8, true), (2, false)]);
+
+    assert!(
+        StreamReader::new(&[1, 2])
+            .try_read_serializable::<[u8; 3]>()
+            .is_err()
+    );
+}
+
+#[test]
+fn vecs_round_trip() {
+    assert_eq!(round_trip(Vec::<u16>::new()), [0]);
+    assert_eq!(round_trip(vec![1u8, 2]), [2, 1, 2]);
+    round_trip(vec![vec![1u32], vec![], vec![2, 3]]);
+}
+
+#[test]
+fn maps_round_trip() {
+    round_trip(HashMap::<u32, String>::new());
+
+    let map: HashMap<u32, String> = (0..20).map(|i| (i, i.to_string())).collect();
+    let bytes = round_trip(map);
+    assert_eq!(bytes[0], 20);
+
+    let nested: HashMap<String, Vec<u8>> = [("a".to_string(), vec![1]), ("b".to_string(), vec![])]
+        .into_iter()
+        .collect();
+    round_trip(nested);
+}
+
+#[test]
+fn map_length_past_the_limit_is_rejected() {
+    let map: HashMap<u8, u8> = (0..5).map(|i| (i, i)).collect();
+    let bytes = encode(&map);
+
+    let error = StreamReader::with_max_vec_len(&bytes, 4)
+        .try_read_serializable::<HashMap<u8, u8>>()
+        .unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::LengthTooLarge);
+    assert_eq!(error.expected, 4);
+    assert_eq!(error.actual, 5);
+
+    let error = StreamReader::new(&bytes[..bytes.len() - 1])
+        .try_read_serializable::<HashMap<u8, u8>>()
+        .unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::UnexpectedEnd);
+}
+
+#[test]
+fn duplicate_map_keys_keep_the_last_value() {
+    let bytes = [2, 1, 10, 1, 20];
+    let map = StreamReader::new(&bytes)
+        .try_read_serializable::<HashMap<u8, u8>>()
+        .unwrap();
+    assert_eq!(map, HashMap::from([(1, 20)]));
+}
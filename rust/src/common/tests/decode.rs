@@ -0,0 +1,122 @@
+﻿use common::decode_error::DecodeErrorKind;
+use common::input_packet::{InputBuffer, InputPacket};
+use common::ping_request::PingResponse;
+use common::stream_reader::{DEFAULT_MAX_STRING_LEN, DEFAULT_MAX_VEC_LEN, StreamReader};
+use common::stream_writer::StreamWriter;
+
+fn ping_response() -> Vec<u8> {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable(PingResponse {
+        time_client_request: 1_000,
+        time_server_response: 1_020,
+        server_frame: 300,
+    });
+    stream_writer.get_data().to_vec()
+}
+
+#[test]
+fn truncated_primitives_are_rejected() {
+    let error = StreamReader::new(&[1, 2]).try_read_u32().unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::UnexpectedEnd);
+    assert_eq!(error.offset, 0);
+
+    assert!(StreamReader::new(&[]).try_read_u8().is_err());
+    assert!(StreamReader::new(&[0x80, 0x80]).try_read_var_u64().is_err());
+}
+
+#[test]
+fn every_truncation_of_a_message_is_rejected() {
+    let bytes = ping_response();
+
+    for len in 0..bytes.len() {
+        let mut stream_reader = StreamReader::new(&bytes[..len]);
+        let error = stream_reader
+            .try_read_serializable::<PingResponse>()
+            .unwrap_err();
+        assert_eq!(error.kind, DecodeErrorKind::UnexpectedEnd, "length {len}");
+    }
+
+    let mut stream_reader = StreamReader::new(&bytes);
+    let response: PingResponse = stream_reader.try_read_serializable().unwrap();
+    assert_eq!(response.server_frame, 300);
+    assert!(!stream_reader.remain_data());
+}
+
+#[test]
+fn vec_length_past_the_limit_is_rejected() {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_u32(1);
+    stream_writer.write_u32(2);
+    stream_writer.write_var_u32(DEFAULT_MAX_VEC_LEN as u32 + 1);
+    let bytes = stream_writer.get_data().to_vec();
+
+    let error = StreamReader::new(&bytes)
+        .try_read_serializable::<InputBuffer>()
+        .unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::LengthTooLarge);
+    assert_eq!(error.offset, 8);
+    assert_eq!(error.expected, DEFAULT_MAX_VEC_LEN);
+    assert_eq!(error.actual, DEFAULT_MAX_VEC_LEN + 1);
+}
+
+#[test]
+fn vec_length_past_the_data_is_rejected() {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_var_u32(DEFAULT_MAX_VEC_LEN as u32);
+    stream_writer.write_serializable(InputPacket::default());
+    let bytes = stream_writer.get_data().to_vec();
+
+    let error = StreamReader::new(&bytes)
+        .try_read_serializable_vec::<InputPacket>()
+        .unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::UnexpectedEnd);
+}
+
+#[test]
+fn string_length_past_the_limit_is_rejected() {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_string(&"a".repeat(DEFAULT_MAX_STRING_LEN + 1));
+    let bytes = stream_writer.get_data().to_vec();
+
+    let error = StreamReader::new(&bytes).try_read_string().unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::LengthTooLarge);
+    assert_eq!(error.expected, DEFAULT_MAX_STRING_LEN);
+    assert_eq!(error.actual, DEFAULT_MAX_STRING_LEN + 1);
+
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_string(&"a".repeat(DEFAULT_MAX_STRING_LEN));
+    let bytes = stream_writer.get_data().to_vec();
+    assert_eq!(
+        StreamReader::new(&bytes).try_read_string().unwrap().len(),
+        DEFAULT_MAX_STRING_LEN
+    );
+}
+
+#[test]
+fn string_length_past_the_data_is_rejected() {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_var_u32(10);
+    stream_writer.write_u32(0x6161_6161);
+    let bytes = stream_writer.get_data().to_vec();
+
+    let error = StreamReader::new(&bytes).try_read_string().unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::UnexpectedEnd);
+}
+
+#[test]
+fn infallible_reads_fall_back_to_the_default() {
+    let bytes = ping_response();
+    let mut stream_reader = StreamReader::new(&bytes[..3]);
+
+    let input: InputPacket = stream_reader.read_serializable();
+    assert_eq!(input.sequence, 0);
+    assert_eq!(input.keys, 0);
+
+    let mut stream_reader = StreamReader::new(&[0xff; 4]);
+    assert!(
+        stream_reader
+            .read_serializable_vec::<InputPacket>()
+            .is_empty()
+    );
+    assert_eq!(StreamReader::new(&[1]).read_u32(), 0);
+}
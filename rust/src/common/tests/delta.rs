@@ -0,0 +1,278 @@
+﻿use common::ack::{AckTracker, PacketStatus};
+use common::bit_reader::BitReader;
+use common::bit_writer::BitWriter;
+use common::delta::{decode_delta, encode_delta};
+use common::player_state::{PLAYER_SCHEMA, PLAYER_TYPE_ID, PlayerState};
+use common::replicated_node::ReplicatedNode;
+use common::snapshot::{DeltaSnapshot, Snapshot};
+use common::stream_reader::StreamReader;
+use common::stream_writer::StreamWriter;
+use glm::Vec2;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+fn state(x: f32, y: f32, owner_id: u32) -> PlayerState {
+    PlayerState {
+        position: Vec2::new(x, y),
+        velocity: Vec2::new(12.5, -3.0),
+        owner_id,
+    }
+}
+
+fn node(net_id: u32, state: PlayerState) -> ReplicatedNode<'static> {
+    let mut writer = BitWriter::new();
+    writer.write_serializable(state);
+    ReplicatedNode {
+        net_id,
+        type_id: PLAYER_TYPE_ID,
+        data: Cow::Owned(writer.into_bytes()),
+    }
+}
+
+fn snapshot(frame: u32, nodes: Vec<ReplicatedNode<'static>>) -> Snapshot<'static> {
+    Snapshot { frame, nodes }
+}
+
+/// Sends `delta` through a stream and rebuilds it against `baseline`.
+fn round_trip(delta: &DeltaSnapshot, baseline: Option<&Snapshot>) -> Snapshot<'static> {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable_ref(delta);
+    let bytes = stream_writer.get_data().to_vec();
+    let received: DeltaSnapshot = StreamReader::new(&bytes).try_read_serializable().unwrap();
+    received.apply(baseline).unwrap()
+}
+
+fn nodes(snapshot: &Snapshot) -> Vec<(u32, u32, Vec<u8>)> {
+    snapshot
+        .nodes
+        .iter()
+        .map(|node| (node.net_id, node.type_id, node.data.to_vec()))
+        .collect()
+}
+
+#[test]
+fn only_changed_fields_are_sent() {
+    let schema = &PLAYER_SCHEMA;
+    let baseline = node(1, state(100.0, 200.0, 7));
+    let moved = node(1, state(150.0, 200.0, 7));
+
+    assert_eq!(encode_delta(schema, &baseline.data, &baseline.data), None);
+
+    let delta = encode_delta(schema, &baseline.data, &moved.data).unwrap();
+    // Five mask bits and the 18 bits of the x axis.
+    assert_eq!(delta.len(), 3);
+    let rebuilt = decode_delta(schema, &baseline.data, &delta).unwrap();
+    assert_eq!(rebuilt, moved.data.to_vec());
+
+    let decoded: PlayerState = BitReader::new(&rebuilt).try_read_serializable().unwrap();
+    assert!((decoded.position.x - 150.0).abs() <= 0.05);
+}
+
+#[test]
+fn no_baseline_sends_every_node_whole() {
+    let current = snapshot(
+        10,
+        vec![node(1, state(1.0, 2.0, 1)), node(2, state(3.0, 4.0, 2))],
+    );
+
+    let delta = DeltaSnapshot::encode(&current, None);
+    assert_eq!(delta.baseline, None);
+    assert_eq!(delta.nodes.len(), 2);
+
+    let rebuilt = round_trip(&delta, None);
+    assert_eq!(rebuilt.frame, 10);
+    assert_eq!(nodes(&rebuilt), nodes(&current));
+}
+
+#[test]
+fn unchanged_nodes_are_omitted() {
+    let baseline = snapshot(
+        10,
+        vec![node(1, state(1.0, 2.0, 1)), node(2, state(3.0, 4.0, 2))],
+    );
+    let current = snapshot(
+        12,
+        vec![node(1, state(1.0, 2.0, 1)), node(2, state(3.0, 9.0, 2))],
+    );
+
+    let delta = DeltaSnapshot::encode(&current, Some(&baseline));
+    assert_eq!(delta.baseline, Some(10));
+    assert_eq!(delta.nodes.len(), 1);
+    assert_eq!(delta.nodes[0].net_id, 2);
+    assert!(delta.nodes[0].data.len() < current.nodes[1].data.len());
+
+    let rebuilt = round_trip(&delta, Some(&baseline));
+    assert_eq!(rebuilt.frame, 12);
+    assert_eq!(nodes(&rebuilt), nodes(&current));
+}
+
+#[test]
+fn spawned_and_removed_nodes_are_applied() {
+    let baseline = snapshot(
+        10,
+        vec![node(1, state(1.0, 2.0, 1)), node(2, state(3.0, 4.0, 2))],
+    );
+    let current = snapshot(
+        11,
+        vec![node(2, state(3.0, 4.0, 2)), node(3, state(5.0, 6.0, 3))],
+    );
+
+    let delta = DeltaSnapshot::encode(&current, Some(&baseline));
+    assert_eq!(delta.removed, vec![1]);
+    assert_eq!(delta.nodes.len(), 1);
+    assert_eq!(delta.nodes[0].data, current.nodes[1].data);
+
+    let rebuilt = round_trip(&delta, Some(&baseline));
+    assert_eq!(nodes(&rebuilt), nodes(&current));
+}
+
+#[test]
+fn types_without_a_layout_are_sent_whole_when_changed() {
+    let opaque = |data: &[u8]| ReplicatedNode {
+        net_id: 5,
+        type_id: 99,
+        data: Cow::Owned(data.to_vec()),
+    };
+    let baseline = snapshot(1, vec![opaque(&[1, 2, 3])]);
+
+    let same = snapshot(2, vec![opaque(&[1, 2, 3])]);
+    assert!(
+        DeltaSnapshot::encode(&same, Some(&baseline))
+            .nodes
+            .is_empty()
+    );
+
+    let changed = snapshot(2, vec![opaque(&[1, 2, 4])]);
+    let delta = DeltaSnapshot::encode(&changed, Some(&baseline));
+    assert_eq!(delta.nodes[0].data, Cow::Borrowed(&[1u8, 2, 4][..]));
+    assert_eq!(nodes(&round_trip(&delta, Some(&baseline))), nodes(&changed));
+}
+
+#[test]
+fn written_in_place_like_the_encoded_delta() {
+    let opaque = |net_id: u32, data: &[u8]| ReplicatedNode {
+        net_id,
+        type_id: 99,
+        data: Cow::Owned(data.to_vec()),
+    };
+    let baseline = snapshot(
+        10,
+        vec![
+            node(1, state(1.0, 2.0, 1)),
+            node(2, state(3.0, 4.0, 2)),
+            opaque(4, &[1, 2]),
+            node(5, state(5.0, 6.0, 5)),
+            opaque(6, &[3]),
+        ],
+    );
+    let current = snapshot(
+        12,
+        vec![
+            node(2, state(3.0, 9.0, 2)),
+            node(3, state(7.0, 8.0, 3)),
+            opaque(4, &[1, 2]),
+            node(5, state(5.0, 6.0, 5)),
+            opaque(6, &[4]),
+            node(7, state(0.0, 0.0, 7)),
+        ],
+    );
+
+    for baseline in [None, Some(&baseline)] {
+        let mut delta = DeltaSnapshot::encode(&current, baseline);
+        delta.last_input = Some(3);
+        delta.input_lead = Some(-1);
+        let mut encoded = StreamWriter::new();
+        encoded.write_serializable_ref(&delta);
+
+        let mut written = StreamWriter::new();
+        DeltaSnapshot::write_from(&mut written, &current, baseline, Some(3), Some(-1));
+        assert_eq!(written.get_data(), encoded.get_data());
+    }
+
+    let delta = DeltaSnapshot::encode(&current, Some(&baseline));
+    assert_eq!(delta.removed, vec![1]);
+    let net_ids: Vec<u32> = delta.nodes.iter().map(|node| node.net_id).collect();
+    assert_eq!(net_ids, [2, 3, 6, 7]);
+}
+
+#[test]
+fn truncated_delta_is_rejected() {
+    let baseline = snapshot(10, vec![node(1, state(1.0, 2.0, 1))]);
+    let delta = DeltaSnapshot {
+        frame: 11,
+        baseline: Some(10),
+        last_input: None,
+        input_lead: None,
+        nodes: vec![ReplicatedNode {
+            net_id: 1,
+            type_id: PLAYER_TYPE_ID,
+            data: Cow::Borrowed(&[0xff]),
+        }],
+        removed: vec![],
+    };
+
+    assert!(delta.apply(Some(&baseline)).is_err());
+}
+
+#[test]
+fn snapshots_missing_their_baseline_are_not_acknowledged() {
+    // Snapshots kept by the server to delta against, and by the client.
+    const HISTORY: usize = 8;
+    let mut server_acks = AckTracker::new();
+    let mut client_acks = AckTracker::new();
+    let mut history: VecDeque<Snapshot> = VecDeque::new();
+    let mut sent = Vec::new();
+    let mut acked_frame = None;
+    let mut baselines: VecDeque<Snapshot> = VecDeque::new();
+    let mut applied = Vec::new();
+
+    for frame in 0..30 {
+        let current = snapshot(frame, vec![node(1, state(frame as f32, 0.0, 7))]);
+        let baseline = acked_frame.and_then(|acked| history.iter().find(|s| s.frame == acked));
+        let mut stream_writer = StreamWriter::new();
+        stream_writer.write_serializable_ref(&DeltaSnapshot::encode(&current, baseline));
+        let header = server_acks.send();
+        sent.push((header.sequence, frame));
+        if history.len() >= HISTORY {
+            history.pop_front();
+        }
+        history.push_back(current);
+
+        // The client loses what it had, like when its baselines are evicted.
+        if frame == 10 {
+            baselines.clear();
+        }
+        assert!(client_acks.receive_unacknowledged(&header, |_, _| {}));
+        let delta: DeltaSnapshot = StreamReader::new(stream_writer.get_data())
+            .try_read_serializable()
+            .unwrap();
+        let baseline = match delta.baseline {
+            Some(frame) => baselines.iter().find(|baseline| baseline.frame == frame),
+            None => None,
+        };
+        if delta.baseline.is_none() || baseline.is_some() {
+            let snapshot = delta.apply(baseline).unwrap();
+            if baselines.len() >= HISTORY {
+                baselines.pop_front();
+            }
+            baselines.push_back(snapshot);
+            applied.push(frame);
+            client_acks.acknowledge(header.sequence);
+        }
+
+        server_acks.receive(&client_acks.send(), |sequence, status| {
+            let (_, frame) = sent.iter().find(|(sent, _)| *sent == sequence).unwrap();
+            if status == PacketStatus::Delivered && acked_frame.is_none_or(|acked| *frame > acked) {
+                acked_frame = Some(*frame);
+            }
+        });
+        // Never a frame the client does not have.
+        assert!(acked_frame.is_none_or(|acked| applied.contains(&acked)));
+    }
+
+    // Deltas against frame 9 fail until the server forgets it, with the
+    // snapshots of the frames since, and sends a whole snapshot.
+    let recovered = 10 + HISTORY as u32;
+    assert_eq!(applied, (0..10).chain(recovered..30).collect::<Vec<u32>>());
+    assert_eq!(acked_frame, Some(29));
+}
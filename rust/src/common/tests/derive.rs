@@ -0,0 +1,200 @@
+﻿use common::decode_error::{DecodeError, DecodeErrorKind};
+use common::protocol::WireSchema;
+use common::stream_reader::{Deserializable, StreamReader, TryDeserializable};
+use common::stream_writer::{Serializable, StreamWriter};
+use std::borrow::Cow;
+
+mod angle {
+    use common::decode_error::DecodeError;
+    use common::stream_reader::StreamReader;
+    use common::stream_writer::StreamWriter;
+
+    pub fn serialize(value: &f32, stream: &mut StreamWriter) {
+        stream.write_angle(*value);
+    }
+
+    pub fn try_deserialize(stream_reader: &mut StreamReader) -> Result<f32, DecodeError> {
+        stream_reader.try_read_angle()
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serializable, Deserializable)]
+struct Everything {
+    id: u32,
+    #[stream(varint)]
+    small: u64,
+    #[stream(varint)]
+    signed: i32,
+    #[stream(skip)]
+    cached: u32,
+    #[stream(with = "angle")]
+    heading: f32,
+    name: String,
+    grid: [u8; 3],
+    tags: Vec<u16>,
+    parent: Option<u32>,
+}
+
+#[derive(Debug, PartialEq, Serializable, Deserializable)]
+struct Pair(u8, #[stream(varint)] u32);
+
+#[derive(Debug, PartialEq, Serializable, Deserializable)]
+struct Unit;
+
+#[derive(Debug, PartialEq, Serializable, Deserializable)]
+enum Shape {
+    Point,
+    Circle { radius: f32 },
+    Segment(u16, u16),
+}
+
+#[derive(Debug, PartialEq, Serializable, Deserializable)]
+enum Command {
+    Stop = 3,
+    Go = 10,
+    Wait,
+}
+
+#[derive(Debug, PartialEq, Serializable, Deserializable)]
+struct Borrowed<'a> {
+    raw: &'a [u8],
+    payload: Cow<'a, [u8]>,
+}
+
+fn encode<T: Serializable>(value: &T) -> Vec<u8> {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable_ref(value);
+    stream_writer.get_data().to_vec()
+}
+
+fn decode<'a, T: TryDeserializable<'a>>(bytes: &'a [u8]) -> Result<T, DecodeError> {
+    let mut stream_reader = StreamReader::new(bytes);
+    let value = stream_reader.try_read_serializable()?;
+    assert!(!stream_reader.remain_data());
+    Ok(value)
+}
+
+fn everything() -> Everything {
+    Everything {
+        id: 7,
+        small: 300,
+        signed: -2,
+        cached: 99,
+        heading: std::f32::consts::FRAC_PI_2,
+        name: "boat".to_string(),
+        grid: [1, 2, 3],
+        tags: vec![4, 5],
+        parent: Some(1),
+    }
+}
+
+#[test]
+fn struct_round_trips_with_field_attributes() {
+    let bytes = encode(&everything());
+    let decoded: Everything = decode(&bytes).unwrap();
+
+    assert_eq!(decoded.cached, 0);
+    assert!((decoded.heading - std::f32::consts::FRAC_PI_2).abs() < 1e-3);
+    assert_eq!(
+        decoded,
+        Everything {
+            cached: 0,
+            heading: decoded.heading,
+            ..everything()
+        }
+    );
+}
+
+#[test]
+fn fields_are_written_in_declaration_order() {
+    let bytes = encode(&everything());
+
+    assert_eq!(&bytes[..4], &7u32.to_le_bytes());
+    // 300 as a varint, then -2 zigzagged to 3; the skipped field takes no room.
+    assert_eq!(&bytes[4..7], &[0xac, 0x02, 0x03]);
+    // Angle, then the string with its length prefix and the array without one.
+    assert_eq!(&bytes[9..14], &[4, b'b', b'o', b'a', b't']);
+    assert_eq!(&bytes[14..17], &[1, 2, 3]);
+}
+
+#[test]
+fn tuple_and_unit_structs_round_trip() {
+    let bytes = encode(&Pair(1, 200));
+    assert_eq!(bytes, [1, 0xc8, 0x01]);
+    assert_eq!(decode::<Pair>(&bytes).unwrap(), Pair(1, 200));
+
+    assert!(encode(&Unit).is_empty());
+    assert_eq!(decode::<Unit>(&[]).unwrap(), Unit);
+}
+
+#[test]
+fn enum_variants_round_trip() {
+    for shape in [
+        Shape::Point,
+        Shape::Circle { radius: 2.5 },
+        Shape::Segment(3, 4),
+    ] {
+        let bytes = encode(&shape);
+        assert_eq!(decode::<Shape>(&bytes).unwrap(), shape);
+    }
+
+    assert_eq!(encode(&Shape::Point), [0]);
+    assert_eq!(encode(&Shape::Segment(3, 4)), [2, 3, 0, 4, 0]);
+}
+
+#[test]
+fn enum_tags_follow_explicit_discriminants() {
+    assert_eq!(encode(&Command::Stop), [3]);
+    assert_eq!(encode(&Command::Go), [10]);
+    assert_eq!(encode(&Command::Wait), [2]);
+
+    for command in [Command::Stop, Command::Go, Command::Wait] {
+        assert_eq!(decode::<Command>(&encode(&command)).unwrap(), command);
+    }
+}
+
+#[test]
+fn unknown_enum_tag_is_rejected() {
+    let error = decode::<Command>(&[4]).unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::InvalidValue);
+    assert_eq!(error.actual, 4);
+
+    let error = decode::<Shape>(&[1, 0]).unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::UnexpectedEnd);
+}
+
+#[test]
+fn borrowed_fields_point_into_the_buffer() {
+    let value = Borrowed {
+        raw: &[1, 2, 3],
+        payload: Cow::Owned(vec![4, 5]),
+    };
+    let bytes = encode(&value);
+    let decoded: Borrowed = decode(&bytes).unwrap();
+
+    assert_eq!(decoded, value);
+    assert!(matches!(decoded.payload, Cow::Borrowed(_)));
+    assert!(bytes.as_ptr_range().contains(&decoded.raw.as_ptr()));
+}
+
+#[test]
+fn infallible_path_is_available_with_a_default() {
+    let bytes = encode(&everything());
+    let mut stream_reader = StreamReader::new(&bytes);
+    let decoded = Everything::deserialize(&mut stream_reader);
+    assert_eq!(decoded.name, "boat");
+
+    let decoded = Everything::deserialize(&mut StreamReader::new(&bytes[..5]));
+    assert_eq!(decoded, Everything::default());
+}
+
+#[test]
+fn schema_describes_the_encoding() {
+    assert_eq!(Pair::SCHEMA, "Pair { u8, varint u32 }");
+    assert_eq!(
+        Command::SCHEMA,
+        "Command { Stop = 3 (), Go = 10 (), Wait = 2 () }"
+    );
+    assert!(Everything::SCHEMA.contains("heading: f32 with angle"));
+    assert!(!Everything::SCHEMA.contains("cached"));
+}
@@ -0,0 +1,175 @@
+﻿use common::fragment::{FRAGMENT_SIZE, Fragmenter, MAX_FRAGMENTS, MAX_PACKET_SIZE, Reassembler};
+use common::message_header::{DataType, MessageHeader, MessageType};
+use common::stream_writer::StreamWriter;
+use std::borrow::Cow;
+use std::time::{Duration, Instant};
+
+fn sealed_message(len: usize) -> Vec<u8> {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable(MessageHeader::init(
+        MessageType::Data,
+        DataType::Replication,
+    ));
+    for i in 0..len {
+        stream_writer.write_u8(i as u8);
+    }
+    MessageHeader::seal(stream_writer.get_data_mut());
+    stream_writer.get_data().to_vec()
+}
+
+fn fragments(message: &[u8]) -> Vec<Vec<u8>> {
+    let mut packets = Vec::new();
+    Fragmenter::default()
+        .send(message, |packet| packets.push(packet.to_vec()))
+        .unwrap();
+    packets
+}
+
+#[test]
+fn small_messages_are_sent_as_is() {
+    let message = sealed_message(100);
+    let packets = fragments(&message);
+    assert_eq!(packets, vec![message.clone()]);
+
+    let mut reassembler = Reassembler::new(4);
+    let received = reassembler.receive((), &message, Instant::now()).unwrap();
+    assert!(matches!(received, Some(Cow::Borrowed(_))));
+}
+
+#[test]
+fn fragments_rebuild_the_message_in_any_order() {
+    let message = sealed_message(3 * FRAGMENT_SIZE + 17);
+    let mut packets = fragments(&message);
+    assert_eq!(packets.len(), 4);
+    for packet in &packets {
+        assert!(packet.len() <= MAX_PACKET_SIZE);
+        assert_eq!(MessageHeader::verify(packet), Ok(()));
+    }
+
+    packets.reverse();
+    let duplicate = packets[1].clone();
+    packets.insert(2, duplicate);
+
+    let mut reassembler = Reassembler::new(4);
+    let now = Instant::now();
+    let (last, rest) = packets.split_last().unwrap();
+    for packet in rest {
+        assert_eq!(reassembler.receive(1, packet, now), Ok(None));
+    }
+    let rebuilt = reassembler.receive(1, last, now).unwrap().unwrap();
+    assert_eq!(rebuilt, message);
+    assert_eq!(MessageHeader::verify(&rebuilt), Ok(()));
+}
+
+#[test]
+fn senders_are_reassembled_separately() {
+    let message = sealed_message(2 * FRAGMENT_SIZE);
+    let packets = fragments(&message);
+
+    let mut reassembler = Reassembler::new(4);
+    let now = Instant::now();
+    assert_eq!(reassembler.receive("a", &packets[0], now), Ok(None));
+    assert_eq!(reassembler.receive("b", &packets[1], now), Ok(None));
+    assert!(
+        reassembler
+            .receive("a", &packets[2], now)
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(
+        reassembler.receive("a", &packets[1], now),
+        Ok(Some(Cow::Owned(message)))
+    );
+}
+
+#[test]
+fn incomplete_messages_time_out() {
+    let packets = fragments(&sealed_message(2 * FRAGMENT_SIZE));
+
+    let mut reassembler = Reassembler::new(4);
+    let now = Instant::now();
+    assert_eq!(reassembler.receive((), &packets[0], now), Ok(None));
+    reassembler.expire(now + Duration::from_secs(1));
+    assert_eq!(reassembler.discarded_groups, 1);
+
+    // The group starts over, so the other fragments alone do not complete it.
+    assert_eq!(reassembler.receive((), &packets[1], now), Ok(None));
+    assert_eq!(reassembler.receive((), &packets[2], now), Ok(None));
+}
+
+#[test]
+fn oldest_group_is_dropped_when_full() {
+    let fragmenter = Fragmenter::default();
+    let mut groups = Vec::new();
+    for _ in 0..3 {
+        let mut packets = Vec::new();
+        fragmenter
+            .send(&sealed_message(FRAGMENT_SIZE + 100), |packet| {
+                packets.push(packet.to_vec())
+            })
+            .unwrap();
+        groups.push(packets);
+    }
+
+    let mut reassembler = Reassembler::new(2);
+    let now = Instant::now();
+    for (i, packets) in groups.iter().enumerate() {
+        let started = now + Duration::from_millis(i as u64);
+        assert_eq!(reassembler.receive((), &packets[0], started), Ok(None));
+    }
+    assert_eq!(reassembler.discarded_groups, 1);
+    assert_eq!(reassembler.receive((), &groups[0][1], now), Ok(None));
+    assert!(
+        reassembler
+            .receive((), &groups[2][1], now)
+            .unwrap()
+            .is_some()
+    );
+}
+
+#[test]
+fn one_sender_cannot_push_out_the_others() {
+    let fragmenter = Fragmenter::default();
+    let groups: Vec<Vec<Vec<u8>>> = (0..5)
+        .map(|_| {
+            let mut packets = Vec::new();
+            fragmenter
+                .send(&sealed_message(FRAGMENT_SIZE + 100), |packet| {
+                    packets.push(packet.to_vec())
+                })
+                .unwrap();
+            packets
+        })
+        .collect();
+
+    let mut reassembler = Reassembler::with_sender_limit(4, 2);
+    let now = Instant::now();
+    assert_eq!(reassembler.receive("victim", &groups[0][0], now), Ok(None));
+    for (i, packets) in groups[1..].iter().enumerate() {
+        let started = now + Duration::from_millis(i as u64 + 1);
+        assert_eq!(reassembler.receive("flood", &packets[0], started), Ok(None));
+    }
+
+    // Only the flooding sender lost groups, its oldest ones.
+    assert_eq!(reassembler.discarded_groups, 2);
+    assert!(
+        reassembler
+            .receive("victim", &groups[0][1], now)
+            .unwrap()
+            .is_some()
+    );
+    assert_eq!(reassembler.receive("flood", &groups[1][1], now), Ok(None));
+    assert!(
+        reassembler
+            .receive("flood", &groups[4][1], now)
+            .unwrap()
+            .is_some()
+    );
+}
+
+#[test]
+fn messages_over_the_fragment_limit_are_refused() {
+    let message = sealed_message(MAX_FRAGMENTS * FRAGMENT_SIZE);
+    let result = Fragmenter::default().send(&message, |_| panic!("nothing should be sent"));
+    assert!(result.is_err());
+}
@@ -0,0 +1,89 @@
+﻿use common::handshake::{ProtocolInfo, RejectReason, Rejection};
+use common::protocol::{PROTOCOL_VERSION, SCHEMA_HASH, WireSchema, schema_hash};
+use common::stream_reader::{Deserializable, StreamReader};
+use common::stream_writer::{Serializable, StreamWriter};
+
+mod before {
+    use super::*;
+
+    #[derive(Serializable, Deserializable)]
+    pub struct Message {
+        pub id: u32,
+        pub speed: f32,
+    }
+}
+
+mod retyped {
+    use super::*;
+
+    #[derive(Serializable, Deserializable)]
+    pub struct Message {
+        pub id: u16,
+        pub speed: f32,
+    }
+}
+
+mod renamed {
+    use super::*;
+
+    #[derive(Serializable, Deserializable)]
+    pub struct Message {
+        pub id: u32,
+        pub velocity: f32,
+    }
+}
+
+#[test]
+fn check_names_what_differs() {
+    assert_eq!(ProtocolInfo::current().check(), Ok(()));
+
+    let other_build = ProtocolInfo {
+        schema_hash: SCHEMA_HASH ^ 1,
+        ..ProtocolInfo::current()
+    };
+    assert_eq!(other_build.check(), Err(RejectReason::SchemaHash));
+
+    // The version comes first, the schema of another version means nothing.
+    let other_version = ProtocolInfo {
+        version: PROTOCOL_VERSION + 1,
+        schema_hash: SCHEMA_HASH ^ 1,
+    };
+    assert_eq!(other_version.check(), Err(RejectReason::ProtocolVersion));
+}
+
+#[test]
+fn rejections_round_trip() {
+    let client = ProtocolInfo {
+        version: PROTOCOL_VERSION,
+        schema_hash: 0x1234,
+    };
+    for reason in [RejectReason::ProtocolVersion, RejectReason::SchemaHash] {
+        let rejection = Rejection::new(reason, &client);
+        let mut stream_writer = StreamWriter::new();
+        stream_writer.write_serializable_ref(&rejection);
+
+        let received: Rejection = StreamReader::new(stream_writer.get_data())
+            .try_read_serializable()
+            .unwrap();
+        assert_eq!(received.reason, reason);
+        assert_eq!(received.server, ProtocolInfo::current());
+        assert_eq!(received.message, rejection.message);
+    }
+
+    let rejection = Rejection::new(RejectReason::SchemaHash, &client);
+    assert!(rejection.message.contains("0000000000001234"));
+}
+
+#[test]
+fn schema_hash_changes_with_any_field() {
+    let hash = schema_hash(&[before::Message::SCHEMA]);
+    assert_eq!(hash, schema_hash(&[before::Message::SCHEMA]));
+    assert_ne!(hash, schema_hash(&[retyped::Message::SCHEMA]));
+    assert_ne!(hash, schema_hash(&[renamed::Message::SCHEMA]));
+
+    // Moving a field from one message to the next changes it too.
+    assert_ne!(
+        schema_hash(&["A { u32 }", "B { }"]),
+        schema_hash(&["A { }", "B { u32 }"])
+    );
+}
@@ -0,0 +1,187 @@
+﻿use common::input_clock::{
+    InputClock, InputSampler, MAX_DILATION, MAX_LEAD_ERROR, TARGET_INPUT_LEAD,
+};
+use common::input_packet::Input;
+use common::movement::{BOAT_CONFIG, BoatState};
+use common::prediction::Prediction;
+use glm::Vec2;
+use std::collections::VecDeque;
+
+const FREQUENCY: f64 = 30.0;
+const PHYSICS_DELTA: f64 = 1.0 / 60.0;
+
+#[test]
+fn starts_ahead_by_the_way_there_and_the_target() {
+    let mut input_clock = InputClock::new(FREQUENCY);
+    assert_eq!(input_clock.sequence(), None);
+
+    input_clock.advance(PHYSICS_DELTA, 10.0, 0.2);
+    let expected = (10.0 + 0.1) * FREQUENCY + 1.0 + TARGET_INPUT_LEAD;
+    assert_eq!(input_clock.sequence(), Some(expected as u32));
+
+    // Without reports it keeps the pace of the server.
+    for _ in 0..60 {
+        input_clock.advance(PHYSICS_DELTA, 0.0, 0.2);
+    }
+    assert_eq!(input_clock.sequence(), Some(expected as u32 + 30));
+}
+
+#[test]
+fn late_inputs_speed_the_clock_up_and_early_ones_slow_it_down() {
+    let mut input_clock = InputClock::new(FREQUENCY);
+    input_clock.advance(PHYSICS_DELTA, 0.0, 0.1);
+
+    input_clock.report_lead(0);
+    assert!(input_clock.dilation() > 0.0);
+    input_clock.report_lead(TARGET_INPUT_LEAD as i16);
+    input_clock.report_lead(TARGET_INPUT_LEAD as i16 + 20);
+    assert!(input_clock.dilation() < 0.0);
+
+    // Far ahead only slows down as much as allowed, the sequence never goes
+    // back.
+    for _ in 0..100 {
+        input_clock.report_lead(100);
+    }
+    assert_eq!(input_clock.dilation(), -MAX_DILATION);
+    let sequence = input_clock.sequence().unwrap();
+    input_clock.advance(1.0, 0.0, 0.1);
+    assert!(input_clock.sequence().unwrap() >= sequence + 28);
+}
+
+#[test]
+fn far_behind_jumps_ahead_and_waits_for_the_next_reports() {
+    let mut input_clock = InputClock::new(FREQUENCY);
+    input_clock.advance(PHYSICS_DELTA, 0.0, 0.1);
+    let sequence = input_clock.sequence().unwrap();
+
+    let lead = (TARGET_INPUT_LEAD - MAX_LEAD_ERROR) as i16 - 4;
+    input_clock.report_lead(lead);
+    assert_eq!(
+        input_clock.sequence(),
+        Some(sequence + (TARGET_INPUT_LEAD as i16 - lead) as u32)
+    );
+    assert_eq!(input_clock.lead(), None);
+
+    // Reports of inputs sent before the jump are still on their way.
+    input_clock.report_lead(lead);
+    assert_eq!(input_clock.lead(), None);
+    input_clock.advance(0.2, 0.0, 0.1);
+    input_clock.report_lead(TARGET_INPUT_LEAD as i16);
+    assert_eq!(input_clock.lead(), Some(TARGET_INPUT_LEAD));
+}
+
+#[test]
+fn inputs_are_complete_once_the_clock_moves_on() {
+    let mut sampler = InputSampler::new();
+    assert!(sampler.sample(7, Vec2::new(1.0, 0.0)).is_none());
+    assert!(sampler.sample(7, Vec2::new(0.0, 1.0)).is_none());
+
+    // Both directions of the two rendered frames of sequence 7.
+    let input = sampler.sample(8, Vec2::new(-1.0, 0.0)).unwrap();
+    assert_eq!(input.sequence, 7);
+    assert!(input.read_input(Input::Right) && input.read_input(Input::Up));
+    assert!(!input.read_input(Input::Left));
+
+    // Skipped sequences have no input.
+    let input = sampler.sample(10, Vec2::new(0.0, 0.0)).unwrap();
+    assert_eq!(input.sequence, 8);
+    assert!(input.read_input(Input::Left));
+}
+
+#[test]
+fn prediction_under_dilation_steps_like_the_server() {
+    let frame_duration = (1.0 / FREQUENCY) as f32;
+    // Sped up, slowed down, and rendering faster than the server.
+    for (lead, physics_delta) in [(-4, PHYSICS_DELTA), (40, PHYSICS_DELTA), (0, 1.0 / 144.0)] {
+        let mut input_clock = InputClock::new(FREQUENCY);
+        input_clock.advance(physics_delta, 5.0, 0.1);
+        for _ in 0..20 {
+            input_clock.report_lead(lead);
+        }
+        assert_ne!(input_clock.dilation(), 0.0);
+
+        let mut sampler = InputSampler::new();
+        let mut prediction = Prediction::new();
+        let mut predicted = BoatState::at(Vec2::new(100.0, 200.0));
+        let mut sent = Vec::new();
+        for rendered in 0..600 {
+            input_clock.advance(physics_delta, 0.0, 0.1);
+            let direction = Vec2::new(((rendered / 7) % 3) as f32 - 1.0, 1.0);
+            if let Some(input) = sampler.sample(input_clock.sequence().unwrap(), direction) {
+                sent.push(input.clone());
+                predicted = prediction.apply(&BOAT_CONFIG, predicted, input, frame_duration);
+            }
+        }
+
+        // The server applies each input sent on its frame, once.
+        assert!(
+            sent.windows(2)
+                .all(|pair| pair[0].sequence < pair[1].sequence)
+        );
+        let simulated = sent
+            .iter()
+            .fold(BoatState::at(Vec2::new(100.0, 200.0)), |state, input| {
+                BOAT_CONFIG.step(state, input, frame_duration)
+            });
+        assert_eq!(
+            predicted.position.x.to_bits(),
+            simulated.position.x.to_bits()
+        );
+        assert_eq!(
+            predicted.position.y.to_bits(),
+            simulated.position.y.to_bits()
+        );
+    }
+}
+
+/// Client sampling inputs at 60 frames per second against a server at 30,
+/// each way taking `latency` seconds, with its estimate of the server's clock
+/// `clock_error` seconds off. Returns the leads measured by the server.
+fn simulate(latency: f64, clock_error: f64, seconds: f64) -> Vec<i64> {
+    let mut input_clock = InputClock::new(FREQUENCY);
+    // Sequences sent and leads reported, with the time they arrive.
+    let mut sent: VecDeque<(f64, u32)> = VecDeque::new();
+    let mut reported: VecDeque<(f64, i64)> = VecDeque::new();
+    let mut leads = Vec::new();
+    let mut previous = 0;
+
+    let mut time = 1.0;
+    while time < seconds {
+        input_clock.advance(PHYSICS_DELTA, time + clock_error, latency * 2.0);
+        let sequence = input_clock.sequence().unwrap();
+        assert!(sequence >= previous);
+        previous = sequence;
+        sent.push_back((time + latency, sequence));
+
+        while sent.front().is_some_and(|(arrival, _)| *arrival <= time) {
+            let (arrival, sequence) = sent.pop_front().unwrap();
+            let frame = (arrival * FREQUENCY).ceil() as i64;
+            let lead = sequence as i64 - frame;
+            leads.push(lead);
+            reported.push_back((arrival + latency, lead));
+        }
+        while reported
+            .front()
+            .is_some_and(|(arrival, _)| *arrival <= time)
+        {
+            let (_, lead) = reported.pop_front().unwrap();
+            input_clock.report_lead(lead as i16);
+        }
+        time += PHYSICS_DELTA;
+    }
+    leads
+}
+
+#[test]
+fn inputs_settle_on_the_target_lead() {
+    for (latency, clock_error) in [(0.05, 0.0), (0.1, -0.12), (0.15, 0.1), (0.03, -0.5)] {
+        let leads = simulate(latency, clock_error, 20.0);
+        let settled = &leads[leads.len() - 300..];
+        let mean = settled.iter().sum::<i64>() as f64 / settled.len() as f64;
+        assert!(
+            (mean - TARGET_INPUT_LEAD).abs() < 1.0,
+            "lead {mean} with {latency} s latency and a clock {clock_error} s off"
+        );
+        assert!(settled.iter().all(|lead| *lead >= 0));
+    }
+}
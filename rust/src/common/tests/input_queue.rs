@@ -0,0 +1,160 @@
+﻿use common::input_packet::{Input, InputPacket};
+use common::input_queue::{InputQueue, InputStats, MAX_BUFFERED_INPUTS, MissingInput};
+
+fn input(sequence: u32, key: Input) -> InputPacket {
+    let mut packet = InputPacket::new();
+    packet.sequence = sequence;
+    packet.add_input(key);
+    packet
+}
+
+/// What a client sends on `frame`: its input for it and the `history` before.
+fn message(frame: u32, history: u32) -> Vec<InputPacket> {
+    (frame.saturating_sub(history)..=frame)
+        .map(|sequence| input(sequence, Input::Right))
+        .collect()
+}
+
+#[test]
+fn early_inputs_wait_for_their_frame() {
+    let mut queue = InputQueue::new();
+    queue.push(input(12, Input::Up), 10);
+    queue.push(input(11, Input::Down), 10);
+    assert_eq!(queue.buffered(), 2);
+
+    let repeat = MissingInput::default();
+    assert_eq!(queue.pop(10, repeat).keys, 0);
+    assert!(queue.pop(11, repeat).read_input(Input::Down));
+    assert!(queue.pop(12, repeat).read_input(Input::Up));
+    assert_eq!(queue.last_simulated(), Some(12));
+    assert_eq!(queue.buffered(), 0);
+    assert_eq!(queue.stats.applied, 2);
+    assert_eq!(queue.stats.missing, 1);
+}
+
+#[test]
+fn repeated_history_is_counted_once() {
+    let mut queue = InputQueue::new();
+    for frame in 0..30 {
+        // Sent two frames ahead, all but the newest already received after
+        // the first message.
+        for packet in message(frame + 2, 19) {
+            queue.push(packet, frame);
+        }
+        let applied = queue.pop(frame, MissingInput::default());
+        assert!(applied.read_input(Input::Right));
+        assert_eq!(applied.sequence, frame);
+    }
+
+    assert_eq!(
+        queue.stats,
+        InputStats {
+            applied: 30,
+            late: 0,
+            missing: 0,
+            duplicated: (1..30).map(|frame: u32| (frame + 2).min(19)).sum(),
+        }
+    );
+}
+
+#[test]
+fn late_inputs_are_not_applied() {
+    let mut queue = InputQueue::new();
+    queue.pop(5, MissingInput::Neutral);
+    queue.push(input(5, Input::Up), 6);
+    queue.push(input(5, Input::Up), 6);
+
+    assert_eq!(queue.buffered(), 0);
+    assert_eq!(queue.stats.late, 1);
+    assert_eq!(queue.stats.duplicated, 1);
+    // The client must not replay it, the frame went by without it.
+    assert_eq!(queue.last_simulated(), Some(5));
+}
+
+#[test]
+fn missing_inputs_repeat_the_last_one_for_a_while() {
+    let mut queue = InputQueue::new();
+    queue.push(input(0, Input::Left), 0);
+    assert!(
+        queue
+            .pop(0, MissingInput::RepeatLast(2))
+            .read_input(Input::Left)
+    );
+
+    let repeated = queue.pop(1, MissingInput::RepeatLast(2));
+    assert!(repeated.read_input(Input::Left));
+    assert_eq!(repeated.sequence, 0);
+    assert!(
+        queue
+            .pop(2, MissingInput::RepeatLast(2))
+            .read_input(Input::Left)
+    );
+    assert_eq!(queue.pop(3, MissingInput::RepeatLast(2)).keys, 0);
+    assert_eq!(queue.stats.missing, 3);
+    assert_eq!(queue.last_simulated(), Some(3));
+
+    // A new input starts the count over.
+    queue.push(input(4, Input::Up), 4);
+    queue.pop(4, MissingInput::RepeatLast(2));
+    assert!(
+        queue
+            .pop(5, MissingInput::RepeatLast(2))
+            .read_input(Input::Up)
+    );
+}
+
+#[test]
+fn replaced_inputs_count_as_simulated() {
+    let mut queue = InputQueue::new();
+    assert_eq!(queue.last_simulated(), None);
+
+    queue.push(input(0, Input::Left), 0);
+    queue.pop(0, MissingInput::default());
+    queue.pop(1, MissingInput::default());
+    queue.pop(2, MissingInput::Neutral);
+    assert_eq!(queue.last_simulated(), Some(2));
+
+    // Arriving after their frames, inputs 1 and 2 are never applied.
+    queue.push(input(1, Input::Up), 3);
+    queue.push(input(2, Input::Up), 3);
+    queue.push(input(3, Input::Up), 3);
+    assert!(queue.pop(3, MissingInput::default()).read_input(Input::Up));
+    assert_eq!(queue.last_simulated(), Some(3));
+    assert_eq!(queue.stats.late, 2);
+}
+
+#[test]
+fn neutral_policy_releases_the_keys() {
+    let mut queue = InputQueue::new();
+    queue.push(input(0, Input::Left), 0);
+    queue.pop(0, MissingInput::Neutral);
+    assert_eq!(queue.pop(1, MissingInput::Neutral).keys, 0);
+}
+
+#[test]
+fn inputs_far_ahead_are_dropped() {
+    let mut queue = InputQueue::new();
+    queue.push(input(MAX_BUFFERED_INPUTS + 3, Input::Up), 3);
+    queue.push(input(MAX_BUFFERED_INPUTS + 2, Input::Up), 3);
+    assert_eq!(queue.buffered(), 1);
+}
+
+#[test]
+fn lead_follows_the_newest_input() {
+    let mut queue = InputQueue::new();
+    assert_eq!(queue.lead(), None);
+
+    for packet in message(12, 3) {
+        queue.push(packet, 10);
+    }
+    queue.pop(10, MissingInput::default());
+    assert_eq!(queue.lead(), Some(2));
+
+    // Kept over frames nothing arrives on.
+    queue.pop(11, MissingInput::default());
+    assert_eq!(queue.lead(), Some(2));
+
+    queue.push(input(10, Input::Up), 13);
+    queue.pop(13, MissingInput::default());
+    assert_eq!(queue.lead(), Some(-3));
+}
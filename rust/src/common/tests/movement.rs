@@ -0,0 +1,149 @@
+﻿use common::bit_reader::BitReader;
+use common::bit_writer::BitWriter;
+use common::input_packet::InputPacket;
+use common::movement::{BOAT_CONFIG, BoatConfig, BoatState};
+use common::player_state::{POSITION_FORMAT, PlayerState};
+use common::prediction::Prediction;
+use glm::Vec2;
+
+const SERVER_TICK: f32 = 1.0 / 30.0;
+
+fn input(sequence: u32, x: f32, y: f32) -> InputPacket {
+    let mut packet = InputPacket::new();
+    packet.sequence = sequence;
+    packet.add_direction(Vec2::new(x, y));
+    packet
+}
+
+/// Inputs of a boat zigzagging and stopping, one per server frame.
+fn inputs() -> Vec<InputPacket> {
+    (0..90)
+        .map(|sequence| match sequence % 9 {
+            0..=2 => input(sequence, 1.0, 0.0),
+            3..=4 => input(sequence, 1.0, -1.0),
+            5 => input(sequence, 0.0, 0.0),
+            6..=7 => input(sequence, -1.0, 1.0),
+            _ => input(sequence, 0.0, 1.0),
+        })
+        .collect()
+}
+
+fn bits(state: BoatState) -> [u32; 4] {
+    [
+        state.position.x.to_bits(),
+        state.position.y.to_bits(),
+        state.velocity.x.to_bits(),
+        state.velocity.y.to_bits(),
+    ]
+}
+
+fn start() -> BoatState {
+    BoatState::at(Vec2::new(1234.5, 678.25))
+}
+
+#[test]
+fn boats_move_at_the_configured_speed() {
+    let config = BoatConfig { speed: 300.0 };
+    let state = config.step(start(), &input(0, 1.0, 1.0), 0.5);
+    assert_eq!(state.velocity.x, 300.0);
+    assert_eq!(state.velocity.y, 300.0);
+    assert_eq!(state.position.x, 1234.5 + 150.0);
+    assert_eq!(state.position.y, 678.25 + 150.0);
+
+    // Without input the boat stops at once.
+    let stopped = config.step(state, &input(1, 0.0, 0.0), 0.5);
+    assert_eq!(bits(stopped)[..2], bits(state)[..2]);
+    assert_eq!((stopped.velocity.x, stopped.velocity.y), (0.0, 0.0));
+}
+
+#[test]
+fn input_directions_match_the_screen() {
+    let mut packet = InputPacket::new();
+    packet.add_direction(Vec2::new(0.7, -0.7));
+    let velocity = BOAT_CONFIG.velocity(&packet);
+    // Each axis at full speed, diagonals are not normalized.
+    assert_eq!(
+        (velocity.x, velocity.y),
+        (BOAT_CONFIG.speed, -BOAT_CONFIG.speed)
+    );
+
+    let mut packet = InputPacket::new();
+    packet.add_direction(Vec2::new(-1.0, 0.0));
+    let velocity = BOAT_CONFIG.velocity(&packet);
+    assert_eq!((velocity.x, velocity.y), (-BOAT_CONFIG.speed, 0.0));
+}
+
+#[test]
+fn boats_stay_where_positions_can_be_replicated() {
+    let corner = BoatState::at(Vec2::new(
+        POSITION_FORMAT.min + 1.0,
+        POSITION_FORMAT.max - 1.0,
+    ));
+    // Steered out of the map on both axes.
+    let state = (0..30).fold(corner, |state, sequence| {
+        BOAT_CONFIG.step(state, &input(sequence, -1.0, 1.0), SERVER_TICK)
+    });
+    assert_eq!(state.position.x, POSITION_FORMAT.min);
+    assert_eq!(state.position.y, POSITION_FORMAT.max);
+
+    // Sent as is, so the prediction replayed from it stays put too.
+    let mut writer = BitWriter::new();
+    writer.write_serializable(PlayerState {
+        position: state.position,
+        velocity: state.velocity,
+        owner_id: 1,
+    });
+    let bytes = writer.into_bytes();
+    let replicated: PlayerState = BitReader::new(&bytes).try_read_serializable().unwrap();
+    assert_eq!(replicated.position.x, state.position.x);
+    assert_eq!(replicated.position.y, state.position.y);
+
+    // Back in from the edge on the next step.
+    let back = BOAT_CONFIG.step(state, &input(30, 1.0, -1.0), SERVER_TICK);
+    assert!(back.position.x > POSITION_FORMAT.min && back.position.y < POSITION_FORMAT.max);
+}
+
+#[test]
+fn identical_inputs_give_identical_states() {
+    let first = inputs().iter().fold(start(), |state, input| {
+        BOAT_CONFIG.step(state, input, SERVER_TICK)
+    });
+    let second = inputs().iter().fold(start(), |state, input| {
+        BOAT_CONFIG.step(state, input, SERVER_TICK)
+    });
+    assert_eq!(bits(first), bits(second));
+}
+
+#[test]
+fn prediction_matches_the_server_simulation() {
+    let inputs = inputs();
+
+    // Server side, one step per frame as its inputs arrive.
+    let mut server_states = Vec::new();
+    let mut server = start();
+    for input in &inputs {
+        server = BOAT_CONFIG.step(server, input, SERVER_TICK);
+        server_states.push(server);
+    }
+
+    // Client side, ahead of the server by the snapshots in flight.
+    let mut prediction = Prediction::new();
+    let mut client = start();
+    for (i, input) in inputs.iter().enumerate() {
+        client = prediction.apply(&BOAT_CONFIG, client, input.clone(), SERVER_TICK);
+        assert_eq!(bits(client), bits(server_states[i]));
+
+        if i >= 5 {
+            let applied = i - 5;
+            let replayed = prediction
+                .reconcile(
+                    &BOAT_CONFIG,
+                    applied as u32 + 1,
+                    server_states[applied],
+                    Some(applied as u32),
+                )
+                .unwrap();
+            assert_eq!(bits(replayed), bits(client));
+        }
+    }
+}
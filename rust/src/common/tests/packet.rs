@@ -0,0 +1,63 @@
+﻿use common::crc32::crc32;
+use common::message_header::{DataType, MessageHeader, MessageType, PacketError};
+use common::stream_reader::StreamReader;
+use common::stream_writer::StreamWriter;
+
+fn sealed_packet() -> Vec<u8> {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable(MessageHeader::init(MessageType::Ping, DataType::None));
+    stream_writer.write_u64(1234);
+    MessageHeader::seal(stream_writer.get_data_mut());
+    stream_writer.get_data().to_vec()
+}
+
+#[test]
+fn crc32_matches_the_standard_check_value() {
+    assert_eq!(crc32(0, b"123456789"), 0xcbf4_3926);
+    assert_ne!(crc32(1, b"123456789"), 0xcbf4_3926);
+}
+
+#[test]
+fn sealed_packet_verifies_and_decodes() {
+    let packet = sealed_packet();
+    assert_eq!(MessageHeader::verify(&packet), Ok(()));
+
+    let mut stream_reader = StreamReader::new(&packet);
+    let header: MessageHeader = stream_reader.try_read_serializable().unwrap();
+    assert_eq!(header.message_type, MessageType::Ping);
+    assert_eq!(stream_reader.try_read_u64(), Ok(1234));
+}
+
+#[test]
+fn any_flipped_bit_fails_verification() {
+    let packet = sealed_packet();
+
+    for bit in 32..packet.len() * 8 {
+        let mut corrupted = packet.clone();
+        corrupted[bit / 8] ^= 1 << (bit % 8);
+        assert_eq!(
+            MessageHeader::verify(&corrupted),
+            Err(PacketError::BadChecksum),
+            "bit {bit}"
+        );
+    }
+}
+
+#[test]
+fn foreign_datagrams_are_rejected() {
+    assert_eq!(
+        MessageHeader::verify(&[]),
+        Err(PacketError::UnknownProtocol)
+    );
+    assert_eq!(
+        MessageHeader::verify(b"GET / HTTP/1.1\r\n"),
+        Err(PacketError::UnknownProtocol)
+    );
+
+    let mut packet = sealed_packet();
+    packet[0] ^= 0xff;
+    assert_eq!(
+        MessageHeader::verify(&packet),
+        Err(PacketError::UnknownProtocol)
+    );
+}
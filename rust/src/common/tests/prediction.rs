@@ -0,0 +1,111 @@
+﻿use common::input_packet::{Input, InputPacket};
+use common::movement::{BOAT_CONFIG, BoatState};
+use common::prediction::{MAX_PENDING_INPUTS, Prediction};
+use common::snapshot::{DeltaSnapshot, Snapshot};
+use common::stream_reader::StreamReader;
+use common::stream_writer::StreamWriter;
+use glm::Vec2;
+
+const TICK: f32 = 1.0 / 60.0;
+
+fn right(sequence: u32) -> InputPacket {
+    let mut packet = InputPacket::new();
+    packet.sequence = sequence;
+    packet.add_input(Input::Right);
+    packet
+}
+
+fn at(x: f32, y: f32) -> BoatState {
+    BoatState::at(Vec2::new(x, y))
+}
+
+fn sequences(prediction: &Prediction) -> Vec<u32> {
+    prediction
+        .pending()
+        .map(|pending| pending.input.sequence)
+        .collect()
+}
+
+fn assert_near(state: BoatState, x: f32, y: f32) {
+    assert!(
+        (state.position.x - x).abs() < 1e-3 && (state.position.y - y).abs() < 1e-3,
+        "({}, {}) instead of ({}, {})",
+        state.position.x,
+        state.position.y,
+        x,
+        y
+    );
+}
+
+#[test]
+fn replay_starts_after_the_last_input_applied() {
+    let mut prediction = Prediction::new();
+    let mut state = at(0.0, 0.0);
+    for sequence in 10..16 {
+        state = prediction.apply(&BOAT_CONFIG, state, right(sequence), TICK);
+    }
+    assert_near(state, 6.0 * BOAT_CONFIG.speed * TICK, 0.0);
+
+    // The server has moved the boat by the inputs up to 12.
+    let replayed = prediction
+        .reconcile(&BOAT_CONFIG, 13, at(100.0, 50.0), Some(12))
+        .unwrap();
+    assert_near(replayed, 100.0 + 3.0 * BOAT_CONFIG.speed * TICK, 50.0);
+    assert_eq!(sequences(&prediction), vec![13, 14, 15]);
+}
+
+#[test]
+fn mispredictions_move_the_replayed_state() {
+    let mut prediction = Prediction::new();
+    let state = prediction.apply(&BOAT_CONFIG, at(0.0, 0.0), right(1), TICK);
+    prediction.apply(&BOAT_CONFIG, state, right(2), TICK);
+
+    // Something pushed the boat on the server.
+    let replayed = prediction
+        .reconcile(&BOAT_CONFIG, 2, at(-40.0, 0.0), Some(1))
+        .unwrap();
+    assert_near(replayed, -40.0 + BOAT_CONFIG.speed * TICK, 0.0);
+}
+
+#[test]
+fn older_snapshots_are_not_reconciled_again() {
+    let mut prediction = Prediction::new();
+    prediction.apply(&BOAT_CONFIG, at(0.0, 0.0), right(5), TICK);
+
+    let reconcile = |prediction: &mut Prediction, frame, last_input| {
+        prediction.reconcile(&BOAT_CONFIG, frame, at(3.0, 0.0), last_input)
+    };
+    assert!(reconcile(&mut prediction, 8, None).is_some());
+    assert!(reconcile(&mut prediction, 8, None).is_none());
+    assert!(reconcile(&mut prediction, 7, Some(5)).is_none());
+    assert_eq!(sequences(&prediction), vec![5]);
+
+    prediction.reset();
+    assert!(sequences(&prediction).is_empty());
+    assert!(reconcile(&mut prediction, 7, None).is_some());
+}
+
+#[test]
+fn pending_inputs_are_bounded() {
+    let mut prediction = Prediction::new();
+    for sequence in 0..MAX_PENDING_INPUTS as u32 + 10 {
+        prediction.apply(&BOAT_CONFIG, at(0.0, 0.0), right(sequence), TICK);
+    }
+    assert_eq!(prediction.pending().count(), MAX_PENDING_INPUTS);
+    assert_eq!(sequences(&prediction)[0], 10);
+}
+
+#[test]
+fn snapshots_carry_the_last_input() {
+    let snapshot = Snapshot::new(42);
+    let mut delta = DeltaSnapshot::encode(&snapshot, None);
+    assert_eq!(delta.last_input, None);
+    delta.last_input = Some(41);
+
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable_ref(&delta);
+    let received: DeltaSnapshot = StreamReader::new(stream_writer.get_data())
+        .try_read_serializable()
+        .unwrap();
+    assert_eq!(received.last_input, Some(41));
+}
@@ -0,0 +1,159 @@
+﻿use common::bit_reader::BitReader;
+use common::bit_writer::BitWriter;
+use common::player_state::{PLAYER_SCHEMA, PLAYER_TYPE_ID, PlayerState};
+use common::property::{
+    Interpolation, NODE_SCHEMAS, NodeSchema, Property, PropertyType, PropertyValue, node_schema,
+};
+use common::protocol::node_schemas_hash;
+use common::quantization::FixedPoint;
+use glm::Vec2;
+
+const EVERY_TYPE: NodeSchema = NodeSchema {
+    type_id: 42,
+    name: "EveryType",
+    properties: &[
+        Property {
+            name: "visible",
+            property_type: PropertyType::Bool,
+            interpolation: Interpolation::None,
+        },
+        Property {
+            name: "score",
+            property_type: PropertyType::U32,
+            interpolation: Interpolation::None,
+        },
+        Property {
+            name: "health",
+            property_type: PropertyType::FixedPoint(FixedPoint::new(0.0, 100.0, 0.5)),
+            interpolation: Interpolation::Linear,
+        },
+        Property {
+            name: "heading",
+            property_type: PropertyType::Angle,
+            interpolation: Interpolation::None,
+        },
+        Property {
+            name: "aim",
+            property_type: PropertyType::UnitVec2,
+            interpolation: Interpolation::None,
+        },
+    ],
+};
+
+#[test]
+fn player_schema_is_registered() {
+    assert_eq!(node_schema(PLAYER_TYPE_ID), Some(&PLAYER_SCHEMA));
+    assert_eq!(node_schema(u32::MAX), None);
+    assert!(NODE_SCHEMAS.contains(&PLAYER_SCHEMA));
+    assert_eq!(PLAYER_SCHEMA.index_of("velocity"), Some(1));
+}
+
+#[test]
+fn values_round_trip_through_the_schema() {
+    let values = [
+        PropertyValue::Bool(true),
+        PropertyValue::U32(123_456),
+        PropertyValue::F32(37.5),
+        PropertyValue::F32(0.0),
+        PropertyValue::Vec2(Vec2::new(1.0, 0.0)),
+    ];
+
+    let mut writer = BitWriter::new();
+    EVERY_TYPE.write(&values, &mut writer);
+    assert_eq!(
+        writer.bits_written(),
+        EVERY_TYPE.field_bits().sum::<u32>() as usize
+    );
+
+    let bytes = writer.into_bytes();
+    let decoded = EVERY_TYPE.try_read(&mut BitReader::new(&bytes)).unwrap();
+    assert_eq!(decoded[..3], values[..3]);
+    let PropertyValue::Vec2(aim) = decoded[4] else {
+        panic!("aim decoded as {:?}", decoded[4]);
+    };
+    assert!((aim.x - 1.0).abs() < 0.01 && aim.y.abs() < 0.01);
+}
+
+#[test]
+fn player_state_is_packed_by_its_schema() {
+    let state = PlayerState {
+        position: Vec2::new(100.0, 200.0),
+        velocity: Vec2::new(-50.0, 0.5),
+        owner_id: 7,
+    };
+
+    let mut writer = BitWriter::new();
+    writer.write_serializable(state);
+    assert_eq!(
+        writer.bits_written(),
+        PLAYER_SCHEMA.field_bits().sum::<u32>() as usize
+    );
+
+    let bytes = writer.into_bytes();
+    let values = PLAYER_SCHEMA.try_read(&mut BitReader::new(&bytes)).unwrap();
+    let decoded = PlayerState::from_values(&values).unwrap();
+    assert_eq!(decoded.owner_id, 7);
+    assert_eq!(decoded.velocity, state.velocity);
+}
+
+#[test]
+fn interpolation_follows_each_property() {
+    let from = [
+        PropertyValue::Vec2(Vec2::new(0.0, 10.0)),
+        PropertyValue::Vec2(Vec2::new(1.0, 1.0)),
+        PropertyValue::U32(1),
+    ];
+    let to = [
+        PropertyValue::Vec2(Vec2::new(10.0, 20.0)),
+        PropertyValue::Vec2(Vec2::new(3.0, 3.0)),
+        PropertyValue::U32(2),
+    ];
+
+    let blended = PLAYER_SCHEMA.interpolate(&from, &to, 0.25);
+    assert_eq!(blended[0], PropertyValue::Vec2(Vec2::new(2.5, 12.5)));
+    assert_eq!(blended[1], to[1]);
+    assert_eq!(blended[2], to[2]);
+}
+
+#[test]
+fn truncated_state_is_rejected() {
+    let mut writer = BitWriter::new();
+    writer.write_u32(5);
+    let bytes = writer.into_bytes();
+
+    assert!(PLAYER_SCHEMA.try_read(&mut BitReader::new(&bytes)).is_err());
+}
+
+#[test]
+fn schema_changes_change_the_hash() {
+    const COARSER: NodeSchema = NodeSchema {
+        type_id: PLAYER_TYPE_ID,
+        name: "Player",
+        properties: &[
+            Property {
+                name: "position",
+                property_type: PropertyType::FixedPointVec2(FixedPoint::new(-1024.0, 8192.0, 0.1)),
+                interpolation: Interpolation::Linear,
+            },
+            PLAYER_SCHEMA.properties[1],
+            PLAYER_SCHEMA.properties[2],
+        ],
+    };
+    const SNAPPED: NodeSchema = NodeSchema {
+        properties: &[
+            Property {
+                interpolation: Interpolation::None,
+                ..PLAYER_SCHEMA.properties[0]
+            },
+            PLAYER_SCHEMA.properties[1],
+            PLAYER_SCHEMA.properties[2],
+        ],
+        ..PLAYER_SCHEMA
+    };
+
+    let hash = node_schemas_hash(0, &[PLAYER_SCHEMA]);
+    assert_eq!(hash, node_schemas_hash(0, NODE_SCHEMAS));
+    assert_ne!(hash, node_schemas_hash(0, &[COARSER]));
+    assert_ne!(hash, node_schemas_hash(0, &[SNAPPED]));
+    assert_ne!(hash, node_schemas_hash(0, &[PLAYER_SCHEMA, EVERY_TYPE]));
+}
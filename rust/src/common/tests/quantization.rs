@@ -0,0 +1,127 @@
+﻿use common::bit_reader::BitReader;
+use common::bit_writer::BitWriter;
+use common::player_state::{POSITION_FORMAT, PlayerState};
+use common::quantization::{
+    FixedPoint, angle_to_u16, compress_unit_vec2, decompress_unit_vec2, f16_to_f32, f32_to_f16,
+    u16_to_angle,
+};
+use common::stream_reader::StreamReader;
+use common::stream_writer::StreamWriter;
+use glm::Vec2;
+use std::f32::consts::{PI, TAU};
+
+fn samples(min: f32, max: f32, count: u32) -> impl Iterator<Item = f32> {
+    (0..=count).map(move |i| min + (max - min) * i as f32 / count as f32)
+}
+
+#[test]
+fn fixed_point_round_trip_within_half_precision() {
+    let format = FixedPoint::new(-100.0, 300.0, 0.01);
+
+    for value in samples(-100.0, 300.0, 10_000) {
+        let decoded = format.dequantize(format.quantize(value));
+        assert!(
+            (decoded - value).abs() <= format.precision / 2.0 + 1e-4,
+            "{value} decoded as {decoded}"
+        );
+    }
+}
+
+#[test]
+fn fixed_point_clamps_out_of_range_values() {
+    let format = FixedPoint::new(0.0, 10.0, 0.5);
+
+    assert_eq!(format.dequantize(format.quantize(-5.0)), 0.0);
+    assert_eq!(format.dequantize(format.quantize(50.0)), 10.0);
+    assert_eq!(format.bits(), 5);
+}
+
+#[test]
+fn fixed_point_through_byte_and_bit_streams() {
+    let mut stream_writer = StreamWriter::new();
+    let mut bit_writer = BitWriter::new();
+    for value in samples(-1024.0, 8192.0, 500) {
+        stream_writer.write_fixed_point(value, &POSITION_FORMAT);
+        bit_writer.write_fixed_point(value, &POSITION_FORMAT);
+    }
+
+    let mut stream_reader = StreamReader::new(stream_writer.get_data());
+    let bytes = bit_writer.into_bytes();
+    let mut bit_reader = BitReader::new(&bytes);
+    for value in samples(-1024.0, 8192.0, 500) {
+        let from_bytes = stream_reader
+            .try_read_fixed_point(&POSITION_FORMAT)
+            .unwrap();
+        let from_bits = bit_reader.try_read_fixed_point(&POSITION_FORMAT).unwrap();
+        assert!((from_bytes - value).abs() <= POSITION_FORMAT.precision / 2.0 + 1e-3);
+        assert_eq!(from_bytes, from_bits);
+    }
+}
+
+#[test]
+fn half_float_relative_error_is_bounded() {
+    for value in samples(-60000.0, 60000.0, 20_000) {
+        let decoded = f16_to_f32(f32_to_f16(value));
+        // 10 mantissa bits: rounding error is at most 2^-11 relative.
+        let bound = value.abs() / 2048.0 + 6.0e-8;
+        assert!(
+            (decoded - value).abs() <= bound,
+            "{value} decoded as {decoded}"
+        );
+    }
+}
+
+#[test]
+fn half_float_special_values() {
+    assert_eq!(f16_to_f32(f32_to_f16(0.0)), 0.0);
+    assert_eq!(f16_to_f32(f32_to_f16(1.0)), 1.0);
+    assert_eq!(f16_to_f32(f32_to_f16(-2.5)), -2.5);
+    assert_eq!(f16_to_f32(f32_to_f16(70000.0)), f32::INFINITY);
+    assert_eq!(f16_to_f32(f32_to_f16(f32::NEG_INFINITY)), f32::NEG_INFINITY);
+    assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+    assert_eq!(f32_to_f16(65504.0), 0x7bff);
+    assert_eq!(f16_to_f32(0x0001), 2.0f32.powi(-24));
+}
+
+#[test]
+fn angle_round_trip_error_is_bounded() {
+    let bound = TAU / 65536.0 / 2.0 + 1e-5;
+
+    for angle in samples(-3.0 * PI, 3.0 * PI, 10_000) {
+        let decoded = u16_to_angle(angle_to_u16(angle));
+        let diff = (decoded - angle).rem_euclid(TAU);
+        let error = diff.min(TAU - diff);
+        assert!(error <= bound, "{angle} decoded as {decoded}");
+    }
+}
+
+#[test]
+fn unit_vec2_round_trip_error_is_bounded() {
+    for angle in samples(0.0, TAU, 5_000) {
+        let vec = Vec2::new(angle.cos(), angle.sin());
+        let decoded = decompress_unit_vec2(compress_unit_vec2(vec));
+        let error = ((decoded.x - vec.x).powi(2) + (decoded.y - vec.y).powi(2)).sqrt();
+        assert!(error <= 1e-4, "{angle} decoded with error {error}");
+    }
+}
+
+#[test]
+fn player_state_is_smaller_than_raw_floats() {
+    let state = PlayerState {
+        position: Vec2::new(1234.56, 789.01),
+        velocity: Vec2::new(-353.5, 353.5),
+        owner_id: 42,
+    };
+
+    let mut bit_writer = BitWriter::new();
+    bit_writer.write_serializable_ref(&state);
+    let bytes = bit_writer.into_bytes();
+    assert!(bytes.len() < 4 * 4 + 4);
+
+    let decoded: PlayerState = BitReader::new(&bytes).try_read_serializable().unwrap();
+    assert!((decoded.position.x - state.position.x).abs() <= 0.025 + 1e-3);
+    assert!((decoded.position.y - state.position.y).abs() <= 0.025 + 1e-3);
+    assert!((decoded.velocity.x - state.velocity.x).abs() <= 0.25);
+    assert!((decoded.velocity.y - state.velocity.y).abs() <= 0.25);
+    assert_eq!(decoded.owner_id, 42);
+}
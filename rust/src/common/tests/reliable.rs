@@ -0,0 +1,169 @@
+﻿use common::ack::AckTracker;
+use common::message_header::{DataType, MessageHeader, MessageType};
+use common::reliable::{
+    CHANNEL_COUNT, MAX_MESSAGE_SIZE, RESEND_INTERVAL, ReliableEndpoint, ReliableError, WINDOW_SIZE,
+};
+use common::stream_reader::StreamReader;
+use common::stream_writer::StreamWriter;
+use std::time::Instant;
+
+/// One side of a connection, with the packets it sent not delivered yet.
+struct Peer {
+    acks: AckTracker,
+    reliable: ReliableEndpoint,
+    sent: Vec<Vec<u8>>,
+}
+
+impl Peer {
+    fn new() -> Self {
+        Self {
+            acks: AckTracker::new(),
+            reliable: ReliableEndpoint::new(),
+            sent: Vec::new(),
+        }
+    }
+
+    fn flush(&mut self, now: Instant) {
+        let sent = &mut self.sent;
+        self.reliable
+            .write_packets(&mut self.acks, now, |packet| sent.push(packet.to_vec()));
+    }
+
+    /// A packet with nothing but acks, like the snapshots and inputs would carry.
+    fn send_acks(&mut self) -> Vec<u8> {
+        let mut stream_writer = StreamWriter::new();
+        stream_writer.write_serializable(MessageHeader::init(MessageType::Ping, DataType::None));
+        MessageHeader::write_acks(stream_writer.get_data_mut(), self.acks.send());
+        stream_writer.get_data().to_vec()
+    }
+
+    fn receive(&mut self, packet: &[u8]) {
+        let mut stream_reader = StreamReader::new(packet);
+        let header: MessageHeader = stream_reader.try_read_serializable().unwrap();
+        let reliable = &mut self.reliable;
+        let fresh = self.acks.receive(&header.acks, |sequence, status| {
+            reliable.on_packet_status(sequence, status)
+        });
+        if fresh && header.message_type == MessageType::Reliable {
+            self.reliable.receive(&mut stream_reader).unwrap();
+        }
+    }
+
+    fn received(&mut self) -> Vec<(u8, Vec<u8>)> {
+        std::iter::from_fn(|| self.reliable.pop_received()).collect()
+    }
+}
+
+#[test]
+fn messages_arrive_in_order_per_channel() {
+    let mut client = Peer::new();
+    let mut server = Peer::new();
+    let now = Instant::now();
+
+    client.reliable.send(0, b"spawn").unwrap();
+    client.reliable.send(1, b"hello").unwrap();
+    client.reliable.send(0, b"despawn").unwrap();
+    client.flush(now);
+    assert_eq!(client.sent.len(), 2);
+
+    for packet in client.sent.drain(..).rev() {
+        server.receive(&packet);
+    }
+    assert_eq!(
+        server.received(),
+        vec![
+            (0, b"spawn".to_vec()),
+            (0, b"despawn".to_vec()),
+            (1, b"hello".to_vec())
+        ]
+    );
+}
+
+#[test]
+fn lost_messages_are_resent_until_acknowledged() {
+    let mut client = Peer::new();
+    let mut server = Peer::new();
+    let now = Instant::now();
+
+    client.reliable.send(0, b"kick").unwrap();
+    client.flush(now);
+    client.sent.clear();
+
+    // Nothing is resent before the interval.
+    client.flush(now);
+    assert!(client.sent.is_empty());
+
+    client.flush(now + RESEND_INTERVAL);
+    assert_eq!(client.sent.len(), 1);
+    let packet = client.sent.pop().unwrap();
+    server.receive(&packet);
+    server.receive(&packet);
+    assert_eq!(server.received(), vec![(0, b"kick".to_vec())]);
+
+    // Once acknowledged, the message is forgotten.
+    client.receive(&server.send_acks());
+    assert_eq!(client.reliable.pending_messages(), 0);
+    client.flush(now + RESEND_INTERVAL * 3);
+    assert!(client.sent.is_empty());
+}
+
+#[test]
+fn out_of_order_messages_wait_for_the_missing_one() {
+    let mut client = Peer::new();
+    let mut server = Peer::new();
+    let now = Instant::now();
+
+    client.reliable.send(2, &[1; 1000]).unwrap();
+    client.reliable.send(2, &[2; 1000]).unwrap();
+    client.reliable.send(2, &[3; 1000]).unwrap();
+    client.flush(now);
+    assert_eq!(client.sent.len(), 3);
+
+    let first = client.sent.remove(0);
+    for packet in client.sent.drain(..) {
+        server.receive(&packet);
+    }
+    assert!(server.received().is_empty());
+
+    server.receive(&first);
+    let received: Vec<u8> = server.received().iter().map(|(_, data)| data[0]).collect();
+    assert_eq!(received, vec![1, 2, 3]);
+}
+
+#[test]
+fn only_the_window_is_in_flight() {
+    let mut client = Peer::new();
+    let mut server = Peer::new();
+    let now = Instant::now();
+
+    for i in 0..WINDOW_SIZE + 10 {
+        client.reliable.send(0, &[i as u8]).unwrap();
+    }
+    client.flush(now);
+    for packet in client.sent.drain(..) {
+        server.receive(&packet);
+    }
+    assert_eq!(server.received().len(), WINDOW_SIZE);
+
+    client.receive(&server.send_acks());
+    client.flush(now);
+    for packet in client.sent.drain(..) {
+        server.receive(&packet);
+    }
+    assert_eq!(server.received().len(), 10);
+}
+
+#[test]
+fn invalid_messages_are_refused() {
+    let mut reliable = ReliableEndpoint::new();
+
+    assert_eq!(
+        reliable.send(CHANNEL_COUNT as u8, b"chat"),
+        Err(ReliableError::UnknownChannel(CHANNEL_COUNT as u8))
+    );
+    assert_eq!(
+        reliable.send(0, &vec![0; MAX_MESSAGE_SIZE + 1]),
+        Err(ReliableError::TooLarge(MAX_MESSAGE_SIZE + 1))
+    );
+    assert_eq!(reliable.send(0, &vec![0; MAX_MESSAGE_SIZE]), Ok(()));
+}
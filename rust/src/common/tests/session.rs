@@ -0,0 +1,286 @@
+﻿use common::connect_token::{CONNECT_TOKEN_EXPIRY, ConnectToken, TokenError, TokenKey, UsedTokens};
+use common::decode_error::DecodeErrorKind;
+use common::handshake::{Handshake, KeyShare};
+use common::message_header::{DataType, MessageHeader, MessageType};
+use common::session::{ENCRYPTION_OVERHEAD, KeyExchange, Session};
+use common::stream_reader::StreamReader;
+use common::stream_writer::StreamWriter;
+
+const NOW: u64 = 1_700_000_000;
+
+fn message(message_type: MessageType, body: &[u8]) -> Vec<u8> {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable(MessageHeader::init(message_type, DataType::Input));
+    stream_writer.write_bytes(body);
+    stream_writer.get_data().to_vec()
+}
+
+/// Sessions of both sides after a handshake with a token from an in-process
+/// issuer, the client's first.
+fn connect(token_key: &TokenKey) -> (Session, Session) {
+    let token = token_key.issue(7, NOW);
+
+    let client = KeyExchange::new();
+    let private = token_key.open(&token.sealed, NOW).unwrap();
+    assert_eq!(private.client_id, 7);
+    assert_eq!(private.key, token.key);
+
+    let server = KeyExchange::new();
+    let server_session = server
+        .server_session(&client.public_key(), &private.key)
+        .unwrap();
+    let client_session = client
+        .client_session(&server.public_key(), &token.key)
+        .unwrap();
+    (client_session, server_session)
+}
+
+#[test]
+fn token_round_trips_through_the_wire() {
+    let token = TokenKey::new(&[3; 32]).issue(42, NOW);
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable(token.clone());
+
+    let read: ConnectToken = StreamReader::new(stream_writer.get_data())
+        .try_read_serializable()
+        .unwrap();
+    assert_eq!(read, token);
+    assert!(!read.is_expired(NOW));
+    assert!(read.is_expired(NOW + CONNECT_TOKEN_EXPIRY.as_secs()));
+}
+
+#[test]
+fn expired_token_is_refused() {
+    let token_key = TokenKey::new(&[3; 32]);
+    let token = token_key.issue(42, NOW);
+    let expiry = NOW + CONNECT_TOKEN_EXPIRY.as_secs();
+    assert!(token_key.open(&token.sealed, expiry - 1).is_ok());
+    assert_eq!(
+        token_key.open(&token.sealed, expiry),
+        Err(TokenError::Expired)
+    );
+}
+
+#[test]
+fn forged_tokens_are_refused() {
+    let token_key = TokenKey::new(&[3; 32]);
+    let token = token_key.issue(42, NOW);
+
+    let other_service = TokenKey::new(&[4; 32]);
+    assert_eq!(
+        other_service.open(&token.sealed, NOW),
+        Err(TokenError::Forged)
+    );
+
+    let mut tampered = token.sealed.clone();
+    tampered.data[0] ^= 1;
+    assert_eq!(token_key.open(&tampered, NOW), Err(TokenError::Forged));
+
+    // The expiry is authenticated with the data.
+    let mut extended = token.sealed.clone();
+    extended.expire_timestamp += 60;
+    assert_eq!(token_key.open(&extended, NOW), Err(TokenError::Forged));
+}
+
+#[test]
+fn tokens_are_bound_to_the_first_address_using_them() {
+    let token_key = TokenKey::new(&[3; 32]);
+    let token = token_key.issue(42, NOW);
+    let other = token_key.issue(43, NOW);
+    let mut used_tokens = UsedTokens::new();
+
+    assert_eq!(used_tokens.register(&token.sealed, "a", NOW), Ok(()));
+    // The client retries its `Hsk` or reconnects from the same address.
+    assert_eq!(used_tokens.register(&token.sealed, "a", NOW + 1), Ok(()));
+    assert_eq!(
+        used_tokens.register(&token.sealed, "b", NOW + 2),
+        Err(TokenError::Replayed)
+    );
+    assert_eq!(used_tokens.register(&other.sealed, "b", NOW + 2), Ok(()));
+    assert_eq!(used_tokens.len(), 2);
+}
+
+#[test]
+fn used_tokens_are_forgotten_once_expired() {
+    let token_key = TokenKey::new(&[3; 32]);
+    let token = token_key.issue(42, NOW);
+    let expiry = NOW + CONNECT_TOKEN_EXPIRY.as_secs();
+    let mut used_tokens = UsedTokens::new();
+
+    used_tokens.register(&token.sealed, "a", NOW).unwrap();
+    assert_eq!(
+        used_tokens.register(&token.sealed, "b", expiry - 1),
+        Err(TokenError::Replayed)
+    );
+
+    // Nothing else is kept around, the token itself is refused by then.
+    let later = token_key.issue(44, expiry);
+    used_tokens.register(&later.sealed, "c", expiry).unwrap();
+    assert_eq!(used_tokens.len(), 1);
+    assert_eq!(
+        token_key.open(&token.sealed, expiry),
+        Err(TokenError::Expired)
+    );
+}
+
+#[test]
+fn sessions_agree_in_both_directions() {
+    let (mut client, mut server) = connect(&TokenKey::new(&[3; 32]));
+
+    let input = message(MessageType::Data, b"inputs");
+    let encrypted = client.encrypt(&input).to_vec();
+    assert_eq!(encrypted.len(), input.len() + ENCRYPTION_OVERHEAD);
+    assert_ne!(
+        &encrypted[MessageHeader::SIZE..],
+        &input[MessageHeader::SIZE..]
+    );
+    assert_eq!(server.decrypt(&encrypted).unwrap(), input);
+
+    let snapshot = message(MessageType::Data, b"snapshot");
+    let encrypted = server.encrypt(&snapshot).to_vec();
+    assert_eq!(client.decrypt(&encrypted).unwrap(), snapshot);
+}
+
+#[test]
+fn sealed_packets_decrypt() {
+    let (mut client, mut server) = connect(&TokenKey::new(&[3; 32]));
+    let mut packet = client
+        .encrypt(&message(MessageType::Ping, b"ping"))
+        .to_vec();
+    MessageHeader::seal(&mut packet);
+    assert!(MessageHeader::verify(&packet).is_ok());
+    assert!(server.decrypt(&packet).is_ok());
+}
+
+#[test]
+fn tampered_messages_are_refused() {
+    let (mut client, mut server) = connect(&TokenKey::new(&[3; 32]));
+    let encrypted = client
+        .encrypt(&message(MessageType::Data, b"inputs"))
+        .to_vec();
+
+    let mut body = encrypted.clone();
+    *body.last_mut().unwrap() ^= 1;
+    let e = server.decrypt(&body).unwrap_err();
+    assert_eq!(e.kind, DecodeErrorKind::Unauthenticated);
+
+    // The header is authenticated too.
+    let mut header = encrypted.clone();
+    header[8] = MessageType::Bye as u8;
+    let e = server.decrypt(&header).unwrap_err();
+    assert_eq!(e.kind, DecodeErrorKind::Unauthenticated);
+
+    let plain = message(MessageType::Data, b"inputs");
+    let e = server.decrypt(&plain).unwrap_err();
+    assert_eq!(e.kind, DecodeErrorKind::Unauthenticated);
+
+    assert!(server.decrypt(&encrypted).is_ok());
+}
+
+#[test]
+fn replayed_messages_are_refused() {
+    let (mut client, mut server) = connect(&TokenKey::new(&[3; 32]));
+    let packets: Vec<Vec<u8>> = (0..4)
+        .map(|i| client.encrypt(&message(MessageType::Data, &[i])).to_vec())
+        .collect();
+
+    // Out of order is fine, twice is not.
+    assert!(server.decrypt(&packets[2]).is_ok());
+    assert!(server.decrypt(&packets[0]).is_ok());
+    assert!(server.decrypt(&packets[3]).is_ok());
+    assert!(server.decrypt(&packets[1]).is_ok());
+    for packet in &packets {
+        let e = server.decrypt(packet).unwrap_err();
+        assert_eq!(e.kind, DecodeErrorKind::Replayed);
+    }
+}
+
+#[test]
+fn packets_older_than_the_window_are_refused() {
+    let (mut client, mut server) = connect(&TokenKey::new(&[3; 32]));
+    let old = client.encrypt(&message(MessageType::Data, &[])).to_vec();
+    for _ in 0..100 {
+        let packet = client.encrypt(&message(MessageType::Data, &[])).to_vec();
+        server.decrypt(&packet).unwrap();
+    }
+    let e = server.decrypt(&old).unwrap_err();
+    assert_eq!(e.kind, DecodeErrorKind::Replayed);
+}
+
+#[test]
+fn sessions_of_another_token_do_not_match() {
+    let token_key = TokenKey::new(&[3; 32]);
+    let token = token_key.issue(7, NOW);
+    let stolen = token_key.issue(8, NOW);
+
+    let client = KeyExchange::new();
+    let server = KeyExchange::new();
+    let private = token_key.open(&token.sealed, NOW).unwrap();
+    let mut server_session = server
+        .server_session(&client.public_key(), &private.key)
+        .unwrap();
+    let mut client_session = client
+        .client_session(&server.public_key(), &stolen.key)
+        .unwrap();
+
+    let encrypted = client_session
+        .encrypt(&message(MessageType::Data, b"inputs"))
+        .to_vec();
+    let e = server_session.decrypt(&encrypted).unwrap_err();
+    assert_eq!(e.kind, DecodeErrorKind::Unauthenticated);
+}
+
+#[test]
+fn low_order_public_keys_are_refused() {
+    let exchange = KeyExchange::new();
+    assert!(exchange.server_session(&[0; 32], &[3; 32]).is_none());
+    assert!(exchange.client_session(&[0; 32], &[3; 32]).is_none());
+}
+
+#[test]
+fn server_handshake_decrypts_after_its_key_share() {
+    let token_key = TokenKey::new(&[3; 32]);
+    let token = token_key.issue(7, NOW);
+    let client = KeyExchange::new();
+
+    // Server side, as it answers the client's `Hsk`.
+    let private = token_key.open(&token.sealed, NOW).unwrap();
+    let server = KeyExchange::new();
+    let mut server_session = server
+        .server_session(&client.public_key(), &private.key)
+        .unwrap();
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable(MessageHeader::init(MessageType::Hsk, DataType::None));
+    stream_writer.write_serializable(KeyShare {
+        public_key: server.public_key(),
+    });
+    let offset = stream_writer.get_data().len();
+    stream_writer.write_serializable(Handshake {
+        client_id: private.client_id,
+        server_frequency: 30.0,
+        compression: true,
+    });
+    let mut reply = server_session
+        .encrypt_from(stream_writer.get_data(), offset)
+        .to_vec();
+    MessageHeader::seal(&mut reply);
+
+    // Client side.
+    let mut stream_reader = StreamReader::new(&reply);
+    let _: MessageHeader = stream_reader.try_read_serializable().unwrap();
+    let key_share: KeyShare = stream_reader.try_read_serializable().unwrap();
+    assert_eq!(stream_reader.get_cursor(), offset);
+    let mut client_session = client
+        .client_session(&key_share.public_key, &token.key)
+        .unwrap();
+    let decrypted = client_session.decrypt_from(&reply, offset).unwrap();
+    let handshake: Handshake = StreamReader::new(&decrypted[offset..])
+        .try_read_serializable()
+        .unwrap();
+    assert_eq!(handshake.client_id, 7);
+
+    // Both go on with the same session.
+    let input = message(MessageType::Data, b"inputs");
+    let encrypted = client_session.encrypt(&input).to_vec();
+    assert_eq!(server_session.decrypt(&encrypted).unwrap(), input);
+}
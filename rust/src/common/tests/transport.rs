@@ -0,0 +1,142 @@
+﻿use common::fragment::{Fragmenter, MAX_PACKET_SIZE, Reassembler};
+use common::message_header::{DataType, MessageHeader, MessageType};
+use common::stream_writer::StreamWriter;
+use common::transport::{MemoryNetwork, Transport, UdpTransport};
+use std::time::{Duration, Instant};
+
+fn sealed_message(len: usize) -> Vec<u8> {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_serializable(MessageHeader::init(
+        MessageType::Data,
+        DataType::Replication,
+    ));
+    for i in 0..len {
+        stream_writer.write_u8(i as u8);
+    }
+    MessageHeader::seal(stream_writer.get_data_mut());
+    stream_writer.get_data().to_vec()
+}
+
+#[test]
+fn memory_packets_arrive_in_order_with_their_sender() {
+    let network = MemoryNetwork::new();
+    let server = network.bind("127.0.0.1:3630");
+    let client = network.bind("127.0.0.1:50000");
+
+    let mut buf = [0; MAX_PACKET_SIZE];
+    assert_eq!(server.recv_from(&mut buf), None);
+
+    client.send_to("127.0.0.1:3630", b"first").unwrap();
+    client.send_to("127.0.0.1:3630", b"second").unwrap();
+    assert_eq!(
+        server.recv_from(&mut buf),
+        Some((5, "127.0.0.1:50000".to_string()))
+    );
+    assert_eq!(&buf[..5], b"first");
+    assert_eq!(
+        server.recv_from(&mut buf),
+        Some((6, "127.0.0.1:50000".to_string()))
+    );
+    assert_eq!(&buf[..6], b"second");
+    assert_eq!(server.recv_from(&mut buf), None);
+
+    let reply_to = client.local_addr().unwrap();
+    server.send_to(&reply_to, b"reply").unwrap();
+    assert_eq!(
+        client.recv_from(&mut buf),
+        Some((5, "127.0.0.1:3630".to_string()))
+    );
+}
+
+#[test]
+fn memory_packets_to_nobody_are_lost() {
+    let network = MemoryNetwork::new();
+    let client = network.bind("127.0.0.1:50000");
+    assert!(client.send_to("127.0.0.1:3630", b"lost").is_ok());
+
+    // Nor delivered to an endpoint bound later.
+    let server = network.bind("127.0.0.1:3630");
+    let mut buf = [0; MAX_PACKET_SIZE];
+    assert_eq!(server.recv_from(&mut buf), None);
+
+    drop(server);
+    client.send_to("127.0.0.1:3630", b"lost").unwrap();
+    let server = network.bind("127.0.0.1:3630");
+    assert_eq!(server.recv_from(&mut buf), None);
+}
+
+#[test]
+fn rebinding_an_address_replaces_its_endpoint() {
+    let network = MemoryNetwork::new();
+    let client = network.bind("127.0.0.1:50000");
+    let old = network.bind("127.0.0.1:3630");
+    let new = network.bind("127.0.0.1:3630");
+
+    // The old endpoint going away leaves the new one bound.
+    drop(old);
+    client.send_to("127.0.0.1:3630", b"hello").unwrap();
+    let mut buf = [0; MAX_PACKET_SIZE];
+    assert_eq!(new.recv_from(&mut buf).map(|(size, _)| size), Some(5));
+}
+
+#[test]
+fn memory_packets_are_truncated_to_the_buffer() {
+    let network = MemoryNetwork::new();
+    let server = network.bind("server");
+    let client = network.bind("client");
+
+    client.send_to("server", &[7; 16]).unwrap();
+    let mut buf = [0; 4];
+    assert_eq!(server.recv_from(&mut buf), Some((4, "client".to_string())));
+    assert_eq!(buf, [7; 4]);
+}
+
+#[test]
+fn fragmented_messages_cross_a_memory_network() {
+    let network = MemoryNetwork::new();
+    let server = network.bind("server");
+    let client = network.bind("client");
+
+    let message = sealed_message(3 * MAX_PACKET_SIZE);
+    Fragmenter::default()
+        .send(&message, |packet| client.send_to("server", packet).unwrap())
+        .unwrap();
+
+    let mut reassembler = Reassembler::new(4);
+    let mut buf = [0; MAX_PACKET_SIZE];
+    let mut received = None;
+    while let Some((size, from)) = server.recv_from(&mut buf) {
+        MessageHeader::verify(&buf[..size]).unwrap();
+        if let Some(message) = reassembler
+            .receive(from, &buf[..size], Instant::now())
+            .unwrap()
+        {
+            received = Some(message.into_owned());
+        }
+    }
+    assert_eq!(received, Some(message));
+}
+
+#[test]
+fn udp_transport_exchanges_on_loopback() {
+    let server = UdpTransport::bind("127.0.0.1:0").unwrap();
+    let client = UdpTransport::bind("127.0.0.1:0").unwrap();
+    let server_addr = server.local_addr().unwrap();
+    let client_addr = client.local_addr().unwrap();
+
+    let mut buf = [0; MAX_PACKET_SIZE];
+    // Non-blocking, nothing is waiting yet.
+    assert_eq!(server.recv_from(&mut buf), None);
+
+    client.send_to(&server_addr, b"hello").unwrap();
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let received = loop {
+        if let Some(received) = server.recv_from(&mut buf) {
+            break received;
+        }
+        assert!(Instant::now() < deadline, "Packet not received on loopback");
+        std::thread::yield_now();
+    };
+    assert_eq!(received, (5, client_addr));
+    assert_eq!(&buf[..5], b"hello");
+}
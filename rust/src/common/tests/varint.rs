@@ -0,0 +1,152 @@
+﻿use common::decode_error::DecodeErrorKind;
+use common::stream_reader::StreamReader;
+use common::stream_writer::StreamWriter;
+use common::varint::{zigzag_decode, zigzag_encode};
+
+fn var_u64(value: u64) -> Vec<u8> {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_var_u64(value);
+    stream_writer.get_data().to_vec()
+}
+
+fn var_i32(value: i32) -> Vec<u8> {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_var_i32(value);
+    stream_writer.get_data().to_vec()
+}
+
+fn assert_invalid(bytes: &[u8]) {
+    let error = StreamReader::new(bytes).try_read_var_u64().unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::InvalidValue, "{bytes:02x?}");
+    assert_eq!(error.offset, 0);
+}
+
+#[test]
+fn unsigned_values_round_trip_at_the_byte_boundaries() {
+    let cases: [(u64, usize); 9] = [
+        (0, 1),
+        (127, 1),
+        (128, 2),
+        (16_383, 2),
+        (16_384, 3),
+        (u32::MAX as u64, 5),
+        (u32::MAX as u64 + 1, 5),
+        (1 << 63, 10),
+        (u64::MAX, 10),
+    ];
+
+    for (value, len) in cases {
+        let bytes = var_u64(value);
+        assert_eq!(bytes.len(), len, "{value}");
+
+        let mut stream_reader = StreamReader::new(&bytes);
+        assert_eq!(stream_reader.try_read_var_u64().unwrap(), value);
+        assert!(!stream_reader.remain_data());
+    }
+
+    assert_eq!(var_u64(0), [0x00]);
+    assert_eq!(var_u64(127), [0x7f]);
+    assert_eq!(var_u64(128), [0x80, 0x01]);
+    assert_eq!(var_u64(u32::MAX as u64), [0xff, 0xff, 0xff, 0xff, 0x0f]);
+}
+
+#[test]
+fn u32_round_trips_and_rejects_larger_values() {
+    for value in [0, 127, 128, u32::MAX] {
+        let mut stream_writer = StreamWriter::new();
+        stream_writer.write_var_u32(value);
+        let mut stream_reader = StreamReader::new(stream_writer.get_data());
+        assert_eq!(stream_reader.try_read_var_u32().unwrap(), value);
+    }
+
+    let bytes = var_u64(u32::MAX as u64 + 1);
+    let error = StreamReader::new(&bytes).try_read_var_u32().unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::InvalidValue);
+}
+
+#[test]
+fn zigzag_keeps_small_magnitudes_small() {
+    let cases: [(i64, u64); 7] = [
+        (0, 0),
+        (-1, 1),
+        (1, 2),
+        (-2, 3),
+        (i32::MAX as i64, u32::MAX as u64 - 1),
+        (i32::MIN as i64, u32::MAX as u64),
+        (i64::MIN, u64::MAX),
+    ];
+
+    for (value, encoded) in cases {
+        assert_eq!(zigzag_encode(value), encoded, "{value}");
+        assert_eq!(zigzag_decode(encoded), value);
+    }
+    assert_eq!(zigzag_decode(zigzag_encode(i64::MAX)), i64::MAX);
+}
+
+#[test]
+fn signed_values_round_trip_at_the_boundaries() {
+    for value in [0, -1, 1, -64, 63, -65, 64, i32::MAX, i32::MIN] {
+        let bytes = var_i32(value);
+        let mut stream_reader = StreamReader::new(&bytes);
+        assert_eq!(stream_reader.try_read_var_i32().unwrap(), value);
+        assert!(!stream_reader.remain_data());
+    }
+
+    assert_eq!(var_i32(-64).len(), 1);
+    assert_eq!(var_i32(-65).len(), 2);
+    assert_eq!(var_i32(i32::MIN).len(), 5);
+
+    for value in [i64::MIN, i64::MAX] {
+        let mut stream_writer = StreamWriter::new();
+        stream_writer.write_var_i64(value);
+        let mut stream_reader = StreamReader::new(stream_writer.get_data());
+        assert_eq!(stream_reader.try_read_var_i64().unwrap(), value);
+    }
+}
+
+#[test]
+fn i32_rejects_values_outside_its_range() {
+    let mut stream_writer = StreamWriter::new();
+    stream_writer.write_var_i64(i32::MIN as i64 - 1);
+    let error = StreamReader::new(stream_writer.get_data())
+        .try_read_var_i32()
+        .unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::InvalidValue);
+}
+
+#[test]
+fn overflowing_encodings_are_rejected() {
+    // The tenth byte only has room for the 64th bit.
+    let mut bytes = vec![0xff; 9];
+    bytes.push(0x01);
+    assert_eq!(
+        StreamReader::new(&bytes).try_read_var_u64().unwrap(),
+        u64::MAX
+    );
+
+    for last in [0x02, 0x40, 0x7f] {
+        bytes[9] = last;
+        assert_invalid(&bytes);
+    }
+}
+
+#[test]
+fn overlong_encodings_are_rejected() {
+    // Eleven bytes never fit a u64, even when the extra bits are zero.
+    let mut bytes = vec![0x80; 10];
+    bytes.push(0x00);
+    assert_invalid(&bytes);
+
+    // Zero padding after the last significant group.
+    assert_invalid(&[0x80, 0x00]);
+    assert_invalid(&[0xff, 0x80, 0x00]);
+    assert_invalid(&[0x81, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x00]);
+}
+
+#[test]
+fn truncated_encodings_are_rejected() {
+    for bytes in [&[][..], &[0x80], &[0xff, 0xff, 0xff, 0xff]] {
+        let error = StreamReader::new(bytes).try_read_var_u64().unwrap_err();
+        assert_eq!(error.kind, DecodeErrorKind::UnexpectedEnd);
+    }
+}
@@ -0,0 +1,342 @@
+﻿//! Derive macros for the `common` wire traits.
+//!
+//! `#[derive(Serializable)]` implements `common::stream_writer::Serializable` and
+//! `#[derive(Deserializable)]` implements `common::stream_reader::TryDeserializable`
+//! (and therefore `Deserializable` for types with a `Default`). Fields are written in declaration order.
+//! A type with a lifetime parameter may borrow from the buffer being read
+//! through its first lifetime.
+//!
+//! `#[derive(Serializable)]` also implements `common::protocol::WireSchema` with a
+//! description of the fields and their encoding, which feeds `SCHEMA_HASH`.
+//!
+//! Field attributes:
+//! - `#[stream(skip)]`: the field is not sent and is rebuilt with `Default::default()`.
+//! - `#[stream(varint)]`: integer fields are written as LEB128 varints (zigzag for
+//!   signed types), see `common::varint::VarInt`.
+//! - `#[stream(with = "path")]`: the field is encoded with `path::serialize(&value, stream)`
+//!   and decoded with `path::try_deserialize(stream)`.
+//!
+//! Any field type implementing the traits can be used, including fixed-size
+//! arrays, which are written without a length prefix. Enums are prefixed with a
+//! `u8` tag: the explicit discriminant when one is given, the variant index otherwise.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+use syn::{
+    Data, DeriveInput, Expr, ExprLit, Field, Fields, GenericParam, Index, Lifetime, LifetimeParam,
+    Lit, LitStr, Path, parse_macro_input,
+};
+
+#[proc_macro_derive(Serializable, attributes(stream))]
+pub fn derive_serializable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_serializable(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(Deserializable, attributes(stream))]
+pub fn derive_deserializable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_deserializable(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+enum FieldCodec {
+    Default,
+    Skip,
+    VarInt,
+    With(Path),
+}
+
+fn field_codec(field: &Field) -> syn::Result<FieldCodec> {
+    let mut codec = FieldCodec::Default;
+
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("stream")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                codec = FieldCodec::Skip;
+                Ok(())
+            } else if meta.path.is_ident("varint") {
+                codec = FieldCodec::VarInt;
+                Ok(())
+            } else if meta.path.is_ident("with") {
+                let path: LitStr = meta.value()?.parse()?;
+                codec = FieldCodec::With(path.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("unsupported stream attribute"))
+            }
+        })?;
+    }
+
+    Ok(codec)
+}
+
+fn write_field(field: &Field, value: TokenStream2) -> syn::Result<TokenStream2> {
+    Ok(match field_codec(field)? {
+        FieldCodec::Default => quote! { stream.write_serializable_ref(#value); },
+        FieldCodec::Skip => quote! {},
+        FieldCodec::VarInt => quote! { ::common::varint::VarInt::write_var(#value, stream); },
+        FieldCodec::With(path) => quote! { #path::serialize(#value, stream); },
+    })
+}
+
+fn read_field(field: &Field) -> syn::Result<TokenStream2> {
+    Ok(match field_codec(field)? {
+        FieldCodec::Default => {
+            let ty = &field.ty;
+            quote! { stream_reader.try_read_serializable::<#ty>()? }
+        }
+        FieldCodec::Skip => quote! { ::std::default::Default::default() },
+        FieldCodec::VarInt => {
+            let ty = &field.ty;
+            quote! { <#ty as ::common::varint::VarInt>::try_read_var(stream_reader)? }
+        }
+        FieldCodec::With(path) => quote! { #path::try_deserialize(stream_reader)? },
+    })
+}
+
+fn field_bindings(fields: &Fields) -> Vec<proc_macro2::Ident> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match &field.ident {
+            Some(ident) => ident.clone(),
+            None => format_ident!("field_{}", i),
+        })
+        .collect()
+}
+
+fn construct(path: TokenStream2, fields: &Fields) -> syn::Result<TokenStream2> {
+    let reads = fields
+        .iter()
+        .map(read_field)
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    Ok(match fields {
+        Fields::Named(named) => {
+            let names = named.named.iter().map(|f| &f.ident);
+            quote! { #path { #(#names: #reads,)* } }
+        }
+        Fields::Unnamed(_) => quote! { #path ( #(#reads,)* ) },
+        Fields::Unit => quote! { #path },
+    })
+}
+
+fn enum_tags(data: &syn::DataEnum) -> syn::Result<Vec<u8>> {
+    data.variants
+        .iter()
+        .enumerate()
+        .map(|(i, variant)| match &variant.discriminant {
+            Some((
+                _,
+                Expr::Lit(ExprLit {
+                    lit: Lit::Int(int), ..
+                }),
+            )) => int.base10_parse::<u8>(),
+            Some((_, expr)) => Err(syn::Error::new(
+                expr.span(),
+                "discriminant must be an integer literal",
+            )),
+            None => u8::try_from(i)
+                .map_err(|_| syn::Error::new(variant.span(), "too many variants for a u8 tag")),
+        })
+        .collect()
+}
+
+fn field_schema(field: &Field) -> syn::Result<Option<String>> {
+    let ty = &field.ty;
+    let ty = quote!(#ty).to_string();
+    let codec = match field_codec(field)? {
+        FieldCodec::Default => ty,
+        FieldCodec::Skip => return Ok(None),
+        FieldCodec::VarInt => format!("varint {ty}"),
+        FieldCodec::With(path) => format!("{ty} with {}", quote!(#path)),
+    };
+
+    Ok(Some(match &field.ident {
+        Some(ident) => format!("{ident}: {codec}"),
+        None => codec,
+    }))
+}
+
+fn fields_schema(fields: &Fields) -> syn::Result<String> {
+    let fields = fields
+        .iter()
+        .filter_map(|field| field_schema(field).transpose())
+        .collect::<syn::Result<Vec<_>>>()?;
+    Ok(fields.join(", "))
+}
+
+fn schema(input: &DeriveInput) -> syn::Result<String> {
+    let name = &input.ident;
+
+    Ok(match &input.data {
+        Data::Struct(data) => format!("{name} {{ {} }}", fields_schema(&data.fields)?),
+        Data::Enum(data) => {
+            let variants = data
+                .variants
+                .iter()
+                .zip(enum_tags(data)?)
+                .map(|(variant, tag)| {
+                    let fields = fields_schema(&variant.fields)?;
+                    Ok(format!("{} = {tag} ({fields})", variant.ident))
+                })
+                .collect::<syn::Result<Vec<_>>>()?;
+            format!("{name} {{ {} }}", variants.join(", "))
+        }
+        Data::Union(_) => String::new(),
+    })
+}
+
+fn expand_serializable(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let writes = data
+                .fields
+                .iter()
+                .enumerate()
+                .map(|(i, field)| {
+                    let member = match &field.ident {
+                        Some(ident) => quote!(#ident),
+                        None => {
+                            let index = Index::from(i);
+                            quote!(#index)
+                        }
+                    };
+                    write_field(field, quote!(&self.#member))
+                })
+                .collect::<syn::Result<Vec<_>>>()?;
+            quote! { #(#writes)* }
+        }
+        Data::Enum(data) => {
+            let tags = enum_tags(data)?;
+            let arms = data
+                .variants
+                .iter()
+                .zip(tags)
+                .map(|(variant, tag)| {
+                    let ident = &variant.ident;
+                    let bindings = field_bindings(&variant.fields);
+                    let writes = variant
+                        .fields
+                        .iter()
+                        .zip(&bindings)
+                        .map(|(field, binding)| write_field(field, quote!(#binding)))
+                        .collect::<syn::Result<Vec<_>>>()?;
+                    let pattern = match &variant.fields {
+                        Fields::Named(_) => quote! { Self::#ident { #(#bindings,)* } },
+                        Fields::Unnamed(_) => quote! { Self::#ident ( #(#bindings,)* ) },
+                        Fields::Unit => quote! { Self::#ident },
+                    };
+                    Ok(quote! {
+                        #[allow(unused_variables)]
+                        #pattern => {
+                            stream.write_u8(#tag);
+                            #(#writes)*
+                        }
+                    })
+                })
+                .collect::<syn::Result<Vec<_>>>()?;
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new(
+                input.span(),
+                "Serializable cannot be derived for unions",
+            ));
+        }
+    };
+
+    let schema = schema(input)?;
+
+    Ok(quote! {
+        impl #impl_generics ::common::stream_writer::Serializable for #name #ty_generics #where_clause {
+            fn serialize(&self, stream: &mut ::common::stream_writer::StreamWriter) {
+                #body
+            }
+        }
+
+        impl #impl_generics ::common::protocol::WireSchema for #name #ty_generics #where_clause {
+            const SCHEMA: &'static str = #schema;
+        }
+    })
+}
+
+fn expand_deserializable(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    // Types with a lifetime borrow from the reader's buffer through it.
+    let mut generics = input.generics.clone();
+    let lifetime = match generics.lifetimes().next() {
+        Some(param) => param.lifetime.clone(),
+        None => {
+            let lifetime = Lifetime::new("'de", Span::call_site());
+            generics.params.insert(
+                0,
+                GenericParam::Lifetime(LifetimeParam::new(lifetime.clone())),
+            );
+            lifetime
+        }
+    };
+    let (impl_generics, _, _) = generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => construct(quote!(Self), &data.fields)?,
+        Data::Enum(data) => {
+            let tags = enum_tags(data)?;
+            let arms = data
+                .variants
+                .iter()
+                .zip(tags)
+                .map(|(variant, tag)| {
+                    let ident = &variant.ident;
+                    let value = construct(quote!(Self::#ident), &variant.fields)?;
+                    Ok(quote! { #tag => #value, })
+                })
+                .collect::<syn::Result<Vec<_>>>()?;
+            quote! {
+                {
+                    let offset = stream_reader.get_cursor();
+                    match stream_reader.try_read_u8()? {
+                        #(#arms)*
+                        tag => {
+                            return Err(::common::decode_error::DecodeError::invalid_value(
+                                offset,
+                                tag as usize,
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new(
+                input.span(),
+                "Deserializable cannot be derived for unions",
+            ));
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::common::stream_reader::TryDeserializable<#lifetime> for #name #ty_generics #where_clause {
+            fn try_deserialize(
+                stream_reader: &mut ::common::stream_reader::StreamReader<#lifetime>,
+            ) -> ::std::result::Result<Self, ::common::decode_error::DecodeError> {
+                Ok(#body)
+            }
+        }
+    })
+}
@@ -1,8 +1,10 @@
 ﻿use crate::network_manager::GDNetworkManager;
 use crate::replicated_node::GDReplicatedNode;
-use common::input_packet::{Input, InputBuffer, InputPacket};
+use common::input_clock::InputSampler;
+use common::input_packet::{InputBuffer, InputPacket};
 use common::message_header::MessageType;
 use common::stream_writer::StreamWriter;
+use glm::Vec2;
 use godot::builtin::Vector2;
 use godot::classes::Node;
 use godot::obj::{Base, Gd, WithBaseField};
@@ -16,7 +18,10 @@ pub struct GDInputManager {
 
     network_manager: Option<Gd<GDNetworkManager>>,
     input_packets: VecDeque<InputPacket>,
-    current_input: InputPacket,
+    /// Input of the frame being sampled, sent once the input clock moves on.
+    sampler: InputSampler,
+    /// Inputs sent and not predicted yet, see `GDPlayer::predict`.
+    unpredicted: Vec<InputPacket>,
     net_id: u32,
 }
 
@@ -27,36 +32,12 @@ impl INode for GDInputManager {
             base,
             network_manager: None,
             input_packets: VecDeque::new(),
-            current_input: InputPacket::new(),
+            sampler: InputSampler::new(),
+            unpredicted: Vec::new(),
             net_id: 0,
         }
     }
 
-    fn physics_process(&mut self, _delta: f64) {
-        if let Some(network_manager) = &mut self.network_manager {
-            self.current_input.sequence = network_manager.bind().get_server_frame();
-
-            self.input_packets.push_back(self.current_input.clone());
-
-            if self.input_packets.len() > 20 {
-                self.input_packets.pop_front();
-            }
-
-            let mut stream_writer = StreamWriter::new();
-            let input_buffer = InputBuffer {
-                client_id: network_manager.bind().client_id,
-                node_id: self.net_id,
-                packets: Vec::from(self.input_packets.clone()),
-            };
-            stream_writer.write_serializable(input_buffer);
-            network_manager
-                .bind()
-                .send_message(MessageType::Data, &mut stream_writer.get_data().to_vec());
-        }
-
-        self.current_input.reset();
-    }
-
     fn ready(&mut self) {
         self.network_manager = Some(
             self.base()
@@ -75,19 +56,52 @@ impl INode for GDInputManager {
 
 #[godot_api]
 impl GDInputManager {
+    /// Adds `direction` to the input of the frame the input clock is on,
+    /// sending the previous one first when the clock moved past it.
     #[func]
     pub fn add_direction_input(&mut self, direction: Vector2) {
-        if direction.y > 0.0 {
-            self.current_input.add_input(Input::Up)
-        }
-        if direction.y < 0.0 {
-            self.current_input.add_input(Input::Down)
-        }
-        if direction.x > 0.0 {
-            self.current_input.add_input(Input::Right)
+        let Some(network_manager) = &self.network_manager else {
+            return;
+        };
+        let Some(sequence) = network_manager.bind().input_sequence() else {
+            return;
+        };
+
+        if let Some(input) = self
+            .sampler
+            .sample(sequence, Vec2::new(direction.x, direction.y))
+        {
+            self.send_input(input);
         }
-        if direction.x < 0.0 {
-            self.current_input.add_input(Input::Left)
+    }
+
+    /// Inputs sent since the last call, each to be predicted once.
+    pub fn take_unpredicted(&mut self) -> Vec<InputPacket> {
+        std::mem::take(&mut self.unpredicted)
+    }
+
+    /// Sends the input of the frame sampled with the ones before it, in case
+    /// their packets were lost.
+    fn send_input(&mut self, input: InputPacket) {
+        let Some(network_manager) = &mut self.network_manager else {
+            return;
+        };
+        self.unpredicted.push(input.clone());
+        self.input_packets.push_back(input);
+
+        if self.input_packets.len() > 20 {
+            self.input_packets.pop_front();
         }
+
+        let mut stream_writer = StreamWriter::new();
+        let input_buffer = InputBuffer {
+            client_id: network_manager.bind().client_id,
+            node_id: self.net_id,
+            packets: Vec::from(self.input_packets.clone()),
+        };
+        stream_writer.write_serializable(input_buffer);
+        network_manager
+            .bind_mut()
+            .send_message(MessageType::Data, stream_writer.get_data());
     }
 }
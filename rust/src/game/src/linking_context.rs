@@ -1,9 +1,19 @@
 ﻿use crate::replicated_node::GDReplicatedNode;
-use common::snapshot::Snapshot;
+use common::bit_reader::BitReader;
+use common::decode_error::DecodeError;
+use common::property::{node_schema, NodeSchema, PropertyValue};
+use common::replicated_node::ReplicatedNode;
+use common::snapshot::{DeltaSnapshot, Snapshot, BASELINE_HISTORY};
+use godot::builtin::{VarDictionary, Variant, Vector2};
 use godot::classes::{INode, Node, PackedScene};
+use godot::global::godot_print;
 use godot::obj::{Base, Gd, WithBaseField};
-use godot::prelude::{godot_api, Array, GodotClass};
-use std::collections::HashMap;
+use godot::prelude::{godot_api, Array, GodotClass, ToGodot};
+use std::collections::{HashMap, VecDeque};
+
+/// Reconstructed snapshots kept as baselines, more than the server may refer to
+/// so a late packet still finds its baseline.
+const MAX_BASELINES: usize = 2 * BASELINE_HISTORY;
 
 #[derive(GodotClass)]
 #[class(base=Node)]
@@ -12,6 +22,7 @@ pub struct GDLinkingContext {
     pub scenes_links: Array<Gd<PackedScene>>,
 
     replicated_nodes: HashMap<u32, Gd<GDReplicatedNode>>,
+    baselines: VecDeque<Snapshot<'static>>,
 
     base: Base<Node>,
 }
@@ -23,6 +34,7 @@ impl INode for GDLinkingContext {
             base,
             scenes_links: Array::new(),
             replicated_nodes: HashMap::new(),
+            baselines: VecDeque::new(),
         }
     }
 
@@ -33,8 +45,35 @@ impl INode for GDLinkingContext {
 
 #[godot_api]
 impl GDLinkingContext {
-    pub fn handle_snapshot(&mut self, snap1: Snapshot, snap2: Snapshot, alpha: f32) {
-        for node in snap1.nodes {
+    /// Rebuilds the full snapshot from the baseline `delta` refers to, and keeps
+    /// it as a baseline for the next ones. Returns `None` when that baseline is
+    /// not known.
+    pub fn reconstruct(
+        &mut self,
+        delta: &DeltaSnapshot,
+    ) -> Result<Option<Snapshot<'static>>, DecodeError> {
+        let baseline = match delta.baseline {
+            Some(frame) => match self
+                .baselines
+                .iter()
+                .find(|baseline| baseline.frame == frame)
+            {
+                Some(baseline) => Some(baseline),
+                None => return Ok(None),
+            },
+            None => None,
+        };
+        let snapshot = delta.apply(baseline)?;
+
+        if self.baselines.len() >= MAX_BASELINES {
+            self.baselines.pop_front();
+        }
+        self.baselines.push_back(snapshot.clone());
+        Ok(Some(snapshot))
+    }
+
+    pub fn handle_snapshot(&mut self, snap1: &Snapshot, snap2: &Snapshot, alpha: f32) {
+        for node in &snap1.nodes {
             let next_frame_node = snap2
                 .nodes
                 .iter()
@@ -44,11 +83,11 @@ impl GDLinkingContext {
                 let replicated_node = self.get_replicated_node(node.net_id);
 
                 if let Some(replicated_node) = replicated_node {
-                    replicated_node.signals().deserialize().emit(
-                        node.data,
-                        next_frame_node.data.clone(),
-                        alpha,
-                    );
+                    let Some(state) = decode_state(node, next_frame_node, alpha) else {
+                        godot_print!("Node {} state could not be decoded", node.net_id);
+                        continue;
+                    };
+                    replicated_node.signals().deserialize().emit(&state);
                 } else {
                     self.spawn(next_frame_node.net_id, next_frame_node.type_id);
                 }
@@ -85,3 +124,31 @@ impl GDLinkingContext {
         None
     }
 }
+
+/// Decodes both states of a node with the schema of its type, and returns them
+/// blended by `alpha`, keyed by property name.
+fn decode_state(
+    node: &ReplicatedNode,
+    next_node: &ReplicatedNode,
+    alpha: f32,
+) -> Option<VarDictionary> {
+    let schema = node_schema(next_node.type_id)?;
+    let from = schema.try_read(&mut BitReader::new(&node.data)).ok()?;
+    let to = schema.try_read(&mut BitReader::new(&next_node.data)).ok()?;
+    let state = schema.interpolate(&from, &to, alpha);
+    Some(to_dictionary(schema, &state))
+}
+
+fn to_dictionary(schema: &NodeSchema, values: &[PropertyValue]) -> VarDictionary {
+    let mut dictionary = VarDictionary::new();
+    for (property, value) in schema.properties.iter().zip(values) {
+        let value: Variant = match *value {
+            PropertyValue::Bool(value) => value.to_variant(),
+            PropertyValue::U32(value) => value.to_variant(),
+            PropertyValue::F32(value) => value.to_variant(),
+            PropertyValue::Vec2(value) => Vector2::new(value.x, value.y).to_variant(),
+        };
+        dictionary.set(property.name, &value);
+    }
+    dictionary
+}
@@ -1,19 +1,46 @@
 ﻿use crate::linking_context::GDLinkingContext;
-use common::handshake::Handshake;
-use common::message_header::{DataType, MessageHeader, MessageType};
+use common::ack::{AckTracker, PacketStatus};
+use common::backoff::Backoff;
+use common::bit_reader::BitReader;
+use common::challenge::{Cookie, HANDSHAKE_PACKET_SIZE};
+use common::clock_sync::ClockSync;
+use common::compression::{decompress, Compressor};
+use common::conditioner::{ConditionedTransport, NetworkConditions};
+use common::connect_token::ConnectToken;
+use common::decode_error::DecodeError;
+use common::fragment::{Fragmenter, Reassembler, MAX_PACKET_SIZE};
+use common::handshake::{Handshake, HandshakeRequest, KeyShare, ProtocolInfo, Rejection};
+use common::input_clock::InputClock;
+use common::message_header::{DataType, MessageHeader, MessageType, PacketError};
 use common::ping_request::{PingRequest, PingResponse};
-use common::snapshot::Snapshot;
+use common::player_state::PlayerState;
+use common::reliable::{ReliableEndpoint, ReliableError};
+use common::session::{KeyExchange, Session};
+use common::snapshot::{DeltaSnapshot, Snapshot};
 use common::stream_reader::StreamReader;
 use common::stream_writer::StreamWriter;
+use common::transport::{Transport, UdpTransport};
 use godot::classes::{INode, Label, Node};
 use godot::global::{godot_print, godot_str};
-use godot::obj::{Base, Gd, WithBaseField};
-use godot::prelude::{godot_api, GodotClass};
-use snl::GameSocket;
+use godot::obj::{Base, Gd, WithBaseField, WithUserSignals};
+use godot::prelude::{godot_api, GString, GodotClass, PackedByteArray};
 use std::collections::VecDeque;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 const SERVER_IP: &str = "127.0.0.1:3630";
+const TOKEN_SERVICE_TIMEOUT: Duration = Duration::from_millis(200);
+/// Wait before asking the token service again after it failed, doubled with each failure.
+const TOKEN_RETRY_DELAY: Duration = Duration::from_millis(200);
+const MAX_TOKEN_RETRY_DELAY: Duration = Duration::from_secs(10);
+/// Fragmented messages being rebuilt at once.
+const MAX_REASSEMBLY_GROUPS: usize = 16;
+/// Seconds between pings, shorter until the clock is synchronized.
+const PING_INTERVAL: f64 = 1.0;
+const SYNC_PING_INTERVAL: f64 = 0.1;
 
 #[derive(Debug, Clone)]
 pub enum ConnectionState {
@@ -21,20 +48,63 @@ pub enum ConnectionState {
     Connecting,
     Connected,
     Spurious,
+    Rejected,
 }
 
 #[derive(GodotClass)]
 #[class(base=Node)]
 pub struct GDNetworkManager {
-    socket: Option<GameSocket>,
+    transport: Option<Box<dyn Transport>>,
     connection_state: ConnectionState,
     connection_timeout: f64,
     ping_sent: u32,
     last_snapshot_handled: f64,
-    snapshots: VecDeque<Snapshot>,
-    server_frame: u32,
+    snapshots: VecDeque<Snapshot<'static>>,
+    /// Newest snapshot received, with the sequence of the last input of this
+    /// client it includes.
+    latest_snapshot: Option<(Snapshot<'static>, Option<u32>)>,
+    /// Server time when the newest snapshot was received.
+    newest_snapshot_time: Option<f64>,
+    /// Set once the handshake completes, fed by the answers to pings.
+    clock: Option<ClockSync>,
+    /// Frames inputs are sampled for, set with `clock`.
+    input_clock: Option<InputClock>,
+    /// Origin of the times in pings, steady unlike the wall clock.
+    clock_epoch: Instant,
     last_time_since_ping: f64,
     server_frequency: f64,
+    fragmenter: Fragmenter,
+    reassembler: Reassembler<()>,
+    /// Set once the handshake completes.
+    acks: Option<AckTracker>,
+    /// Messages queued before the handshake completes go out once it does.
+    reliable: ReliableEndpoint,
+    /// Asks the server to compress messages during the handshake.
+    #[export]
+    compression: bool,
+    /// Set once the handshake completes with compression accepted.
+    compressor: Option<Compressor>,
+    /// Challenge cookie from the server's `Helo`, echoed in each `Hsk`.
+    cookie: Option<Cookie>,
+    /// Address of the service handing out connect tokens.
+    #[export]
+    token_service: GString,
+    token: Option<ConnectToken>,
+    /// Answer of the token service, fetched on a worker thread.
+    token_request: Option<Receiver<io::Result<Vec<u8>>>>,
+    token_backoff: Backoff,
+    /// Set for each connection attempt, its public key goes in the `Hsk`.
+    key_exchange: Option<KeyExchange>,
+    /// Set once the handshake completes, everything else is encrypted by it.
+    session: Option<Session>,
+    /// Bad network the client's socket is put through, written as the flags
+    /// of the server, like `--latency 100 --jitter 20 --loss 5`.
+    #[export]
+    network_conditions: GString,
+    dropped_packets: u32,
+    foreign_packets: u32,
+    corrupted_packets: u32,
+    rejection_message: String,
 
     pub client_id: u32,
     base: Base<Node>,
@@ -44,41 +114,61 @@ pub struct GDNetworkManager {
 impl INode for GDNetworkManager {
     fn init(base: Base<Node>) -> Self {
         Self {
-            socket: None,
+            transport: None,
             connection_state: ConnectionState::NotConnected,
             connection_timeout: 0.0,
             ping_sent: 0,
             client_id: 0,
             base,
             snapshots: VecDeque::new(),
-            server_frame: 0,
+            latest_snapshot: None,
+            newest_snapshot_time: None,
+            clock: None,
+            input_clock: None,
+            clock_epoch: Instant::now(),
             last_time_since_ping: 0.0,
             last_snapshot_handled: 0.0,
             server_frequency: 1.0,
+            fragmenter: Fragmenter::default(),
+            reassembler: Reassembler::new(MAX_REASSEMBLY_GROUPS),
+            acks: None,
+            reliable: ReliableEndpoint::new(),
+            compression: true,
+            compressor: None,
+            cookie: None,
+            token_service: GString::from("127.0.0.1:3631"),
+            token: None,
+            token_request: None,
+            token_backoff: Backoff::new(TOKEN_RETRY_DELAY, MAX_TOKEN_RETRY_DELAY),
+            key_exchange: None,
+            session: None,
+            network_conditions: GString::new(),
+            dropped_packets: 0,
+            foreign_packets: 0,
+            corrupted_packets: 0,
+            rejection_message: String::new(),
         }
     }
 
     fn process(&mut self, delta: f64) {
         self.last_snapshot_handled += delta;
 
-        let mut buf = [0; 1200];
-        if let Some(socket) = self.socket.as_mut() {
-            match socket.poll(&mut buf) {
-                Some((size, _)) => {
-                    let buf = &mut buf[..size];
-                    let mut stream_reader = StreamReader::new(buf.to_vec());
-                    let message_header: MessageHeader = stream_reader.read_serializable();
-                    match message_header.message_type {
-                        MessageType::Helo => self.set_connection_state(ConnectionState::Connecting),
-                        MessageType::Hsk => self.handle_hsk(stream_reader),
-                        MessageType::Ping => self.handle_ping(stream_reader),
-                        MessageType::Data => self.handle_data(message_header, stream_reader),
-                        MessageType::Bye => self.disconnect_socket(false),
-                    }
-                }
-                None => {}
+        // A snapshot can arrive as several fragments, read everything pending.
+        let mut buf = [0; MAX_PACKET_SIZE];
+        while let Some((size, addr)) = self
+            .transport
+            .as_ref()
+            .and_then(|transport| transport.recv_from(&mut buf))
+        {
+            // Only the server is talked to, and nothing but its address makes
+            // a rejection sent before the session exists its own.
+            if addr != SERVER_IP {
+                self.foreign_packets += 1;
+                continue;
             }
+            self.handle_packet(&buf[..size]);
         }
+        self.reassembler.expire(Instant::now());
 
         self.connection_timeout += delta;
         if self.connection_timeout > 0.100 {
@@ -89,16 +179,22 @@ impl INode for GDNetworkManager {
             return;
         }
 
-        let alpha = (self.last_snapshot_handled / self.server_frequency) as f32;
+        // Measured on the synchronized clock when there is one, which does not
+        // rush or stall with the frame rate.
+        let elapsed = match (self.newest_snapshot_time, self.server_time()) {
+            (Some(received), Some(now)) => now - received,
+            _ => self.last_snapshot_handled,
+        };
+        let alpha = (elapsed / self.server_frequency) as f32;
+        let mut linking_context = self.get_linking_context();
 
         if let Some(s1) = self.snapshots.get(0) {
-            let snap1 = s1.clone();
-            let mut snap2: Option<Snapshot> = None;
+            let mut snap2: Option<&Snapshot> = None;
 
             let mut i = 1;
             while snap2.is_none() {
                 if let Some(s) = self.snapshots.get(i) {
-                    snap2 = Some(s.clone());
+                    snap2 = Some(s);
                 }
                 i += 1;
                 if i >= self.snapshots.len() {
@@ -107,9 +203,9 @@ impl INode for GDNetworkManager {
             }
 
             if let Some(snap2) = snap2 {
-                self.get_linking_context()
+                linking_context
                     .bind_mut()
-                    .handle_snapshot(snap1, snap2, alpha / i as f32);
+                    .handle_snapshot(s1, snap2, alpha / i as f32);
             } else {
                 godot_print!("Snapshot not found");
             }
@@ -119,20 +215,30 @@ impl INode for GDNetworkManager {
     fn physics_process(&mut self, delta: f64) {
         self.last_time_since_ping += delta;
 
-        if self.last_time_since_ping > 1.0 {
+        let now = self.client_time();
+        if let (Some(clock), Some(input_clock)) = (self.clock.as_mut(), self.input_clock.as_mut()) {
+            if let Some(server_time) = clock.server_time(now) {
+                input_clock.advance(delta, server_time, clock.rtt());
+            }
+        }
+
+        let interval = match &self.clock {
+            Some(clock) if !clock.is_synchronized() => SYNC_PING_INTERVAL,
+            _ => PING_INTERVAL,
+        };
+        if self.last_time_since_ping > interval {
             let mut stream_writer = StreamWriter::new();
             let ping_request = PingRequest {
-                time_client_request: SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as u64,
+                time_client_request: self.client_time(),
             };
             stream_writer.write_serializable(ping_request);
 
-            self.send_message(MessageType::Ping, &mut stream_writer.get_data().to_vec());
+            self.send_message(MessageType::Ping, stream_writer.get_data());
 
             self.last_time_since_ping = 0.0;
         }
+
+        self.flush_reliable();
     }
 
     fn exit_tree(&mut self) {
@@ -142,28 +248,200 @@ impl INode for GDNetworkManager {
     fn ready(&mut self) {
         self.base_mut().add_to_group("Network");
 
-        let socket = GameSocket::new("127.0.0.1:0");
-
-        match socket {
-            Ok(socket) => {
-                self.socket = Some(socket);
-                self.handle_timeout();
+        let transport = match UdpTransport::bind("127.0.0.1:0") {
+            Ok(transport) => transport,
+            Err(e) => {
+                godot_print!("Error connecting to server: {}", e);
+                return;
             }
-            Err(e) => godot_print!("Error connecting to server: {}", e),
+        };
+
+        let conditions = match self
+            .network_conditions
+            .to_string()
+            .parse::<NetworkConditions>()
+        {
+            Ok(conditions) => conditions,
+            Err(e) => {
+                godot_print!("Network conditions ignored: {}", e);
+                NetworkConditions::default()
+            }
+        };
+        if conditions.is_ideal() {
+            self.set_transport(Box::new(transport));
+        } else {
+            godot_print!("Network conditions: {:?}", conditions);
+            self.set_transport(Box::new(ConditionedTransport::new(transport, conditions)));
         }
     }
 }
 
+#[godot_api]
 impl GDNetworkManager {
-    pub fn send_message(&self, message_type: MessageType, buffer: &mut Vec<u8>) {
+    #[signal]
+    pub fn connection_rejected(message: GString);
+
+    #[signal]
+    pub fn packet_delivered(sequence: u32);
+
+    #[signal]
+    pub fn packet_lost(sequence: u32);
+
+    #[signal]
+    pub fn reliable_received(channel: u8, bytes: PackedByteArray);
+
+    /// Queues `bytes` on a reliable ordered channel, returns false when the
+    /// channel refuses it.
+    #[func(rename = send_reliable)]
+    pub fn send_reliable_bytes(&mut self, channel: u8, bytes: PackedByteArray) -> bool {
+        match self.send_reliable(channel, bytes.as_slice()) {
+            Ok(()) => true,
+            Err(e) => {
+                godot_print!("Reliable message not sent: {}", e);
+                false
+            }
+        }
+    }
+
+    #[func]
+    pub fn get_dropped_packets(&self) -> u32 {
+        self.dropped_packets
+    }
+
+    #[func]
+    pub fn get_foreign_packets(&self) -> u32 {
+        self.foreign_packets
+    }
+
+    #[func]
+    pub fn get_corrupted_packets(&self) -> u32 {
+        self.corrupted_packets
+    }
+
+    #[func]
+    pub fn get_discarded_fragment_groups(&self) -> u32 {
+        self.reassembler.discarded_groups
+    }
+
+    #[func]
+    pub fn get_duplicate_packets(&self) -> u32 {
+        self.acks.as_ref().map_or(0, |acks| acks.duplicate_packets)
+    }
+
+    /// Share of the packets sent to the server known to be lost.
+    #[func]
+    pub fn get_packet_loss(&self) -> f32 {
+        self.acks.as_ref().map_or(0.0, |acks| acks.packet_loss())
+    }
+
+    /// Bytes sent per byte of the messages of `message_type` given to the
+    /// compressor, 1 when none were.
+    #[func]
+    pub fn get_compression_ratio(&self, message_type: u8) -> f32 {
+        let Some(compressor) = &self.compressor else {
+            return 1.0;
+        };
+        compressor
+            .stats()
+            .iter()
+            .find(|(stats_type, _)| *stats_type as u8 == message_type)
+            .map_or(1.0, |(_, stats)| stats.ratio())
+    }
+
+    /// Frames the inputs of this client arrive ahead of the server, smoothed.
+    #[func]
+    pub fn get_input_lead(&self) -> f64 {
+        self.input_clock
+            .as_ref()
+            .and_then(|input_clock| input_clock.lead())
+            .unwrap_or(0.0)
+    }
+
+    /// Share the input clock runs faster to keep the inputs on time, negative
+    /// when slower.
+    #[func]
+    pub fn get_input_dilation(&self) -> f64 {
+        self.input_clock
+            .as_ref()
+            .map_or(0.0, |input_clock| input_clock.dilation())
+    }
+
+    #[func]
+    pub fn is_rejected(&self) -> bool {
+        matches!(self.connection_state, ConnectionState::Rejected)
+    }
+
+    #[func]
+    pub fn get_rejection_message(&self) -> GString {
+        GString::from(self.rejection_message.as_str())
+    }
+
+    pub fn send_message(&mut self, message_type: MessageType, buffer: &[u8]) {
         let mut stream_writer = StreamWriter::new();
         stream_writer.write_serializable(MessageHeader::init(message_type, DataType::Input));
         stream_writer.write_bytes(buffer);
+        if let Some(acks) = self.acks.as_mut().filter(|_| message_type.is_sequenced()) {
+            MessageHeader::write_acks(stream_writer.get_data_mut(), acks.send());
+        }
 
-        if let Some(socket) = self.socket.as_ref() {
-            match socket.send(SERVER_IP, stream_writer.get_data()) {
-                Ok(_) => {}
-                Err(e) => godot_print!("Error sending message: {}", e),
+        let packet = match self.compressor.as_mut() {
+            Some(compressor) => compressor.compress(stream_writer.get_data_mut()),
+            None => stream_writer.get_data_mut(),
+        };
+        if matches!(message_type, MessageType::Helo | MessageType::Hsk) {
+            Self::send_packet(self.transport.as_deref(), &self.fragmenter, packet);
+            return;
+        }
+        // Nothing but the handshake goes out before the session exists.
+        if let Some(session) = self.session.as_mut() {
+            Self::send_packet(
+                self.transport.as_deref(),
+                &self.fragmenter,
+                session.encrypt(packet),
+            );
+        }
+    }
+
+    /// Queues `bytes` on a reliable ordered channel and sends what is due on
+    /// it once connected.
+    pub fn send_reliable(&mut self, channel: u8, bytes: &[u8]) -> Result<(), ReliableError> {
+        self.reliable.send(channel, bytes)?;
+        self.flush_reliable();
+        Ok(())
+    }
+
+    /// Sends the reliable messages not sent yet or due again.
+    fn flush_reliable(&mut self) {
+        let (Some(acks), Some(session)) = (self.acks.as_mut(), self.session.as_mut()) else {
+            return;
+        };
+
+        let transport = self.transport.as_deref();
+        let fragmenter = &self.fragmenter;
+        let compressor = &mut self.compressor;
+        self.reliable.write_packets(acks, Instant::now(), |packet| {
+            let packet = match compressor {
+                Some(compressor) => compressor.compress(packet),
+                None => packet,
+            };
+            Self::send_packet(transport, fragmenter, session.encrypt(packet))
+        });
+    }
+
+    /// Seals `packet`, a message starting with a `MessageHeader`, and sends it
+    /// to the server.
+    fn send_packet(transport: Option<&dyn Transport>, fragmenter: &Fragmenter, packet: &mut [u8]) {
+        MessageHeader::seal(packet);
+
+        if let Some(transport) = transport {
+            let result = fragmenter.send(packet, |packet| {
+                match transport.send_to(SERVER_IP, packet) {
+                    Ok(_) => {}
+                    Err(e) => godot_print!("Error sending message: {}", e),
+                }
+            });
+            if let Err(e) = result {
+                godot_print!("Message not sent: {}", e);
             }
         }
     }
@@ -177,12 +455,24 @@ impl GDNetworkManager {
     fn handle_timeout(&mut self) {
         match self.connection_state {
             ConnectionState::NotConnected => {
-                let mut message: Vec<u8> = vec![];
-                self.send_message(MessageType::Helo, &mut message);
+                if self.fetch_token() {
+                    self.send_protocol(MessageType::Helo);
+                }
             }
             ConnectionState::Connecting => {
-                let mut message: Vec<u8> = vec![];
-                self.send_message(MessageType::Hsk, &mut message);
+                let now = unix_time();
+                if self
+                    .token
+                    .as_ref()
+                    .is_none_or(|token| token.is_expired(now))
+                {
+                    // Start over with a new token.
+                    self.disconnect_socket(false);
+                } else if self.cookie.is_some() {
+                    self.send_protocol(MessageType::Hsk);
+                } else {
+                    self.send_protocol(MessageType::Helo);
+                }
             }
             ConnectionState::Connected => {
                 //if self.last_snapshot_handled > 1.0 {
@@ -191,25 +481,147 @@ impl GDNetworkManager {
             }
             ConnectionState::Spurious => {
                 if self.ping_sent < 3 {
-                    let mut message: Vec<u8> = vec![];
-                    self.send_message(MessageType::Ping, &mut message);
+                    self.send_message(MessageType::Ping, &[]);
                     self.ping_sent += 1;
                 } else {
                     self.disconnect_socket(false)
                 }
             }
+            ConnectionState::Rejected => {}
         }
 
         self.connection_timeout = 0.0;
     }
 
+    fn send_protocol(&mut self, message_type: MessageType) {
+        let mut stream_writer = StreamWriter::new();
+        stream_writer.write_serializable(ProtocolInfo::current());
+        if message_type == MessageType::Hsk {
+            let (Some(cookie), Some(token), Some(key_exchange)) =
+                (self.cookie, self.token.as_ref(), self.key_exchange.as_ref())
+            else {
+                return;
+            };
+            stream_writer.write_serializable(cookie);
+            stream_writer.write_serializable(token.sealed.clone());
+            stream_writer.write_serializable(KeyShare {
+                public_key: key_exchange.public_key(),
+            });
+            stream_writer.write_serializable(HandshakeRequest {
+                compression: self.compression,
+            });
+        }
+        // The server only answers requests at least as large as its reply.
+        let padding = HANDSHAKE_PACKET_SIZE
+            .saturating_sub(MessageHeader::SIZE + stream_writer.get_data().len());
+        stream_writer.write_bytes(&vec![0; padding]);
+        self.send_message(message_type, stream_writer.get_data());
+    }
+
+    /// Asks the token service for a connect token unless the current one is
+    /// still valid. Returns false when there is none to connect with yet.
+    ///
+    /// The request runs on a worker thread so the game does not stall while
+    /// the service answers, it is polled by the next calls.
+    fn fetch_token(&mut self) -> bool {
+        if self
+            .token
+            .as_ref()
+            .is_some_and(|token| !token.is_expired(unix_time()))
+        {
+            return true;
+        }
+        self.token = None;
+
+        if let Some(token_request) = &self.token_request {
+            let result = match token_request.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => return false,
+                Err(TryRecvError::Disconnected) => Err(io::Error::other("token request aborted")),
+            };
+            self.token_request = None;
+            return self.receive_token(result);
+        }
+
+        if !self.token_backoff.is_ready(Instant::now()) {
+            return false;
+        }
+        let Ok(addr) = self.token_service.to_string().parse::<SocketAddr>() else {
+            godot_print!("Invalid token service address: {}", self.token_service);
+            self.token_backoff.fail(Instant::now());
+            return false;
+        };
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // Nobody is waiting anymore when the manager was freed.
+            let _ = sender.send(request_token(addr));
+        });
+        self.token_request = Some(receiver);
+        false
+    }
+
+    /// Reads the answer of the token service, backing off when there is no token in it.
+    fn receive_token(&mut self, result: io::Result<Vec<u8>>) -> bool {
+        let token = match result {
+            Ok(data) => StreamReader::new(&data).try_read_serializable::<ConnectToken>(),
+            Err(e) => {
+                let delay = self.token_backoff.fail(Instant::now());
+                godot_print!("Token service unreachable, retrying in {:?}: {}", delay, e);
+                return false;
+            }
+        };
+
+        match token {
+            Ok(token) => {
+                godot_print!("Got connect token for client {}", token.client_id);
+                self.token_backoff.reset();
+                self.token = Some(token);
+                true
+            }
+            Err(e) => {
+                let delay = self.token_backoff.fail(Instant::now());
+                godot_print!("Malformed connect token, retrying in {:?}: {}", delay, e);
+                false
+            }
+        }
+    }
+
+    /// Stops connection attempts until the game is restarted with a matching build.
+    fn reject(&mut self, message: String) {
+        godot_print!("Connection rejected: {}", message);
+        self.rejection_message = message;
+        self.set_connection_state(ConnectionState::Rejected);
+
+        let message = self.get_rejection_message();
+        self.signals().connection_rejected().emit(&message);
+    }
+
+    /// Exchanges packets with the server through `transport` from now on,
+    /// starting a new connection.
+    pub fn set_transport(&mut self, transport: Box<dyn Transport>) {
+        if self.transport.is_some() {
+            self.disconnect_socket(true);
+        }
+        self.transport = Some(transport);
+        self.handle_timeout();
+    }
+
     pub fn disconnect_socket(&mut self, send_bye: bool) {
         self.ping_sent = 0;
         if send_bye {
             let mut stream_writer = StreamWriter::new();
             stream_writer.write_u32(self.client_id);
-            self.send_message(MessageType::Bye, &mut stream_writer.get_data().to_vec());
+            self.send_message(MessageType::Bye, stream_writer.get_data());
         }
+        self.acks = None;
+        self.reliable = ReliableEndpoint::new();
+        self.compressor = None;
+        self.cookie = None;
+        self.key_exchange = None;
+        self.session = None;
+        self.clock = None;
+        self.input_clock = None;
+        self.newest_snapshot_time = None;
         self.set_connection_state(ConnectionState::NotConnected);
     }
 
@@ -218,40 +630,239 @@ impl GDNetworkManager {
             .get_node_as::<GDLinkingContext>("%GDLinkingContext")
     }
 
-    fn handle_ping(&mut self, mut stream_reader: StreamReader) {
-        let ping_response: PingResponse = stream_reader.read_serializable();
-        let current_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
+    fn handle_packet(&mut self, packet: &[u8]) {
+        if let Err(e) = MessageHeader::verify(packet) {
+            match e {
+                PacketError::UnknownProtocol => self.foreign_packets += 1,
+                PacketError::BadChecksum => self.corrupted_packets += 1,
+            }
+            godot_print!("Dropped packet: {}", e);
+            return;
+        }
+
+        let result = match self.reassembler.receive((), packet, Instant::now()) {
+            Ok(Some(message)) => self.open_message(&message),
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = result {
+            self.dropped_packets += 1;
+            godot_print!(
+                "Dropped malformed packet ({} dropped): {}",
+                self.dropped_packets,
+                e
+            );
+        }
+    }
+
+    /// Decrypts a message of the session, only the handshake comes in the
+    /// clear and is ignored once the session exists.
+    fn open_message(&mut self, message: &[u8]) -> Result<(), DecodeError> {
+        let message_header: MessageHeader = StreamReader::new(message).try_read_serializable()?;
+        match message_header.message_type {
+            MessageType::Helo | MessageType::Hsk | MessageType::Reject
+                if self.session.is_some() =>
+            {
+                Ok(())
+            }
+            MessageType::Hsk => self.handle_hsk(message),
+            MessageType::Helo | MessageType::Reject => {
+                self.handle_message(StreamReader::new(message))
+            }
+            _ => {
+                let Some(session) = self.session.as_mut() else {
+                    return Err(DecodeError::unauthenticated(0));
+                };
+                let message = session.decrypt(message)?;
+                let message = decompress(&message)?;
+                self.handle_message(StreamReader::new(&message))
+            }
+        }
+    }
+
+    fn handle_message(&mut self, mut stream_reader: StreamReader) -> Result<(), DecodeError> {
+        let message_header: MessageHeader = stream_reader.try_read_serializable()?;
+        if !self.receive_acks(&message_header) {
+            return Ok(());
+        }
+        let sequence = message_header
+            .message_type
+            .is_sequenced()
+            .then_some(message_header.acks.sequence);
+
+        match message_header.message_type {
+            MessageType::Helo => self.handle_helo(stream_reader)?,
+            // Read by `open_message`, which decrypts its end.
+            MessageType::Hsk => {}
+            MessageType::Ping => self.handle_ping(stream_reader)?,
+            MessageType::Data => {
+                if !self.handle_data(message_header, stream_reader)? {
+                    return Ok(());
+                }
+            }
+            MessageType::Bye => self.disconnect_socket(false),
+            MessageType::Reject => self.handle_reject(stream_reader)?,
+            MessageType::Reliable => self.handle_reliable(stream_reader)?,
+            // Consumed by the reassembler.
+            MessageType::Fragment => {}
+        }
+
+        if let (Some(acks), Some(sequence)) = (self.acks.as_mut(), sequence) {
+            acks.acknowledge(sequence);
+        }
+        Ok(())
+    }
+
+    /// Feeds the acks of a sequenced message to the connection and reports the
+    /// packets it resolves. Returns false when the message is a duplicate.
+    /// The message itself is acknowledged once handled.
+    fn receive_acks(&mut self, message_header: &MessageHeader) -> bool {
+        if !message_header.message_type.is_sequenced() {
+            return true;
+        }
+        let Some(acks) = self.acks.as_mut() else {
+            return true;
+        };
+
+        let mut resolved = Vec::new();
+        let fresh = acks.receive_unacknowledged(&message_header.acks, |sequence, status| {
+            resolved.push((sequence, status))
+        });
+
+        for (sequence, status) in resolved {
+            self.reliable.on_packet_status(sequence, status);
+            match status {
+                PacketStatus::Delivered => self.signals().packet_delivered().emit(sequence as u32),
+                PacketStatus::Lost => self.signals().packet_lost().emit(sequence as u32),
+            }
+        }
+        fresh
+    }
 
-        self.server_frame = ping_response.server_frame;
+    fn handle_ping(&mut self, mut stream_reader: StreamReader) -> Result<(), DecodeError> {
+        let ping_response: PingResponse = stream_reader.try_read_serializable()?;
+        let current_time = self.client_time();
+        let Some(rtt) = current_time.checked_sub(ping_response.time_client_request) else {
+            return Ok(());
+        };
+        if let Some(clock) = self.clock.as_mut() {
+            clock.add_sample(&ping_response, current_time);
+        }
 
-        let rtt =
-            Duration::from_millis(current_time - ping_response.time_client_request).as_millis();
         let mut label = self.base_mut().get_node_as::<Label>("%LatencyLabel");
         label.set_text(&godot_str!("{rtt} ms"));
+        Ok(())
     }
 
-    fn handle_hsk(&mut self, mut stream_reader: StreamReader) {
-        let handshake: Handshake = stream_reader.read_serializable();
+    fn handle_helo(&mut self, mut stream_reader: StreamReader) -> Result<(), DecodeError> {
+        let cookie: Cookie = stream_reader.try_read_serializable()?;
+        match self.connection_state {
+            ConnectionState::NotConnected => {
+                self.cookie = Some(cookie);
+                self.key_exchange = Some(KeyExchange::new());
+                self.set_connection_state(ConnectionState::Connecting);
+            }
+            // Sent again when the previous cookie expired.
+            ConnectionState::Connecting => self.cookie = Some(cookie),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_hsk(&mut self, message: &[u8]) -> Result<(), DecodeError> {
+        let mut stream_reader = StreamReader::new(message);
+        let _: MessageHeader = stream_reader.try_read_serializable()?;
+        let protocol: ProtocolInfo = stream_reader.try_read_serializable()?;
+        let key_share: KeyShare = stream_reader.try_read_serializable()?;
+        let offset = stream_reader.get_cursor();
+        let (Some(token), Some(key_exchange)) = (&self.token, &self.key_exchange) else {
+            return Ok(());
+        };
+        let Some(mut session) = key_exchange.client_session(&key_share.public_key, &token.key)
+        else {
+            return Err(DecodeError::unauthenticated(offset));
+        };
+        let message = session.decrypt_from(message, offset)?;
+
+        // Checked once decrypted, which authenticates everything before `offset`.
+        if protocol.check().is_err() {
+            self.reject(format!(
+                "Server protocol {} build {:016x} is not supported by this client",
+                protocol.version, protocol.schema_hash
+            ));
+            return Ok(());
+        }
+
+        let handshake: Handshake = StreamReader::new(&message[offset..]).try_read_serializable()?;
         self.set_connection_state(ConnectionState::Connected);
+        self.session = Some(session);
+        self.acks = Some(AckTracker::new());
+        self.compressor = handshake.compression.then(Compressor::new);
         self.client_id = handshake.client_id;
         self.server_frequency = 1.0 / handshake.server_frequency;
+        self.clock = Some(ClockSync::new(handshake.server_frequency));
+        self.input_clock = Some(InputClock::new(handshake.server_frequency));
         godot_print!("ClientID : {:?}", self.client_id);
+        Ok(())
+    }
+
+    fn handle_reliable(&mut self, mut stream_reader: StreamReader) -> Result<(), DecodeError> {
+        if self.acks.is_none() {
+            return Ok(());
+        }
+
+        self.reliable.receive(&mut stream_reader)?;
+        while let Some((channel, data)) = self.reliable.pop_received() {
+            let bytes = PackedByteArray::from(data.as_slice());
+            self.signals().reliable_received().emit(channel, &bytes);
+        }
+        Ok(())
     }
 
-    fn handle_data(&mut self, message_header: MessageHeader, mut stream_reader: StreamReader) {
+    fn handle_reject(&mut self, mut stream_reader: StreamReader) -> Result<(), DecodeError> {
+        let rejection: Rejection = stream_reader.try_read_serializable()?;
+        // Either reason takes another build of the game to connect.
+        self.reject(rejection.message);
+        Ok(())
+    }
+
+    /// Returns false when the snapshot it carries could not be applied, for it
+    /// to stay unacknowledged and the server to keep the baseline it has.
+    fn handle_data(
+        &mut self,
+        message_header: MessageHeader,
+        mut stream_reader: StreamReader,
+    ) -> Result<bool, DecodeError> {
         self.last_snapshot_handled = 0.0;
         self.connection_timeout = 0.0;
         match message_header.data_type {
             DataType::None => {}
             DataType::Input => {}
             DataType::Replication => {
-                self.snapshots.push_back(stream_reader.read_serializable());
+                let delta: DeltaSnapshot = stream_reader.try_read_serializable()?;
+                let Some(snapshot) = self.get_linking_context().bind_mut().reconstruct(&delta)?
+                else {
+                    godot_print!("Snapshot {} dropped, baseline not found", delta.frame);
+                    return Ok(false);
+                };
+                if self
+                    .latest_snapshot
+                    .as_ref()
+                    .is_none_or(|(latest, _)| snapshot.frame > latest.frame)
+                {
+                    self.latest_snapshot = Some((snapshot.clone(), delta.last_input));
+                    if let (Some(input_clock), Some(lead)) =
+                        (self.input_clock.as_mut(), delta.input_lead)
+                    {
+                        input_clock.report_lead(lead);
+                    }
+                }
+                self.snapshots.push_back(snapshot);
+                self.newest_snapshot_time = self.server_time();
 
                 if self.snapshots.len() < 3 {
-                    return;
+                    return Ok(true);
                 }
 
                 if self.snapshots.len() > 3 {
@@ -259,8 +870,54 @@ impl GDNetworkManager {
                 }
             }
         }
+        Ok(true)
+    }
+    /// State of the boat `net_id` in the newest snapshot, with the frame of the
+    /// snapshot and the sequence of the last input of this client it includes.
+    pub fn latest_player_state(&self, net_id: u32) -> Option<(u32, PlayerState, Option<u32>)> {
+        let (snapshot, last_input) = self.latest_snapshot.as_ref()?;
+        let node = snapshot.nodes.iter().find(|node| node.net_id == net_id)?;
+        let state = BitReader::new(&node.data).try_read_serializable().ok()?;
+        Some((snapshot.frame, state, *last_input))
+    }
+
+    /// Sequence of the inputs sampled now, None until the clock is known.
+    pub fn input_sequence(&self) -> Option<u32> {
+        self.input_clock.as_ref()?.sequence()
+    }
+
+    /// Seconds the server steps each frame by, in the precision of its
+    /// movement.
+    pub fn frame_duration(&self) -> f32 {
+        self.server_frequency as f32
+    }
+
+    /// Seconds simulated by the server by now, None until the first ping
+    /// answered.
+    pub fn server_time(&mut self) -> Option<f64> {
+        let now = self.client_time();
+        self.clock.as_mut()?.server_time(now)
     }
-    pub fn get_server_frame(&self) -> u32 {
-        self.server_frame + (self.last_time_since_ping / self.server_frequency) as u32
+
+    /// Milliseconds since `clock_epoch`, the clock of pings.
+    fn client_time(&self) -> u64 {
+        self.clock_epoch.elapsed().as_millis() as u64
     }
 }
+
+/// Reads everything the token service at `addr` sends, blocking until it is done.
+fn request_token(addr: SocketAddr) -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect_timeout(&addr, TOKEN_SERVICE_TIMEOUT)?;
+    stream.set_read_timeout(Some(TOKEN_SERVICE_TIMEOUT))?;
+    let mut data = Vec::new();
+    stream.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Seconds since the Unix epoch, the clock of connect tokens.
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
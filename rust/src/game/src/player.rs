@@ -1,13 +1,21 @@
-﻿use crate::network_manager::GDNetworkManager;
-use common::stream_reader::StreamReader;
-use godot::builtin::Vector2;
+﻿use crate::input_manager::GDInputManager;
+use crate::network_manager::GDNetworkManager;
+use crate::replicated_node::GDReplicatedNode;
+use common::movement::{BoatState, BOAT_CONFIG};
+use common::player_state::POSITION_FORMAT;
+use common::prediction::Prediction;
+use glm::Vec2;
+use godot::builtin::{VarDictionary, Vector2};
 use godot::classes::{CharacterBody2D, ICharacterBody2D};
-use godot::global::{exp};
 use godot::obj::{Base, Gd, WithBaseField};
-use godot::prelude::{godot_api, GodotClass};
+use godot::prelude::{godot_api, FromGodot, GodotClass};
 
-const MAX_HARD_SNAP: f64 = 150.0;
-const ERROR_DISTANCE: f64 = 10.0;
+/// Distance in pixels between the replayed and the predicted position past
+/// which the prediction is taken as wrong. Both sides step the boat by the
+/// same inputs and frame duration, so the gap only comes from the position of
+/// the snapshot being quantized, and the rounding of the steps replayed from
+/// it.
+const MISPREDICTION_DISTANCE: f32 = 2.0 * POSITION_FORMAT.precision;
 
 #[derive(GodotClass)]
 #[class(base=CharacterBody2D)]
@@ -19,6 +27,9 @@ pub struct GDPlayer {
 
     #[var]
     replicated_velocity: Vector2,
+    /// Inputs of the locally owned boat the server has not applied yet.
+    prediction: Prediction,
+    mispredictions: u32,
 }
 
 #[godot_api]
@@ -29,6 +40,8 @@ impl ICharacterBody2D for GDPlayer {
             owner_id: 0,
             network_manager: None,
             replicated_velocity: Vector2::new(0.0, 0.0),
+            prediction: Prediction::new(),
+            mispredictions: 0,
         }
     }
 
@@ -54,59 +67,111 @@ impl GDPlayer {
         false
     }
 
+    /// Takes the properties of `PLAYER_SCHEMA` decoded by the linking context.
+    /// The locally owned boat is predicted instead, see `predict`.
     #[func]
-    pub fn deserialize_bytes(&mut self, snap1: Vec<u8>, snap2: Vec<u8>, alpha: f32) {
-        let mut sr1 = StreamReader::new(snap1);
-        let mut sr2 = StreamReader::new(snap2);
-
-        let position1 = sr1.read_vec2();
-
-        let position2 = sr2.read_vec2();
-        let velocity = sr2.read_vec2();
-        self.owner_id = sr2.read_u32();
-
-        let old_position = Vector2::new(position1.x, position1.y);
-        let mut next_position = Vector2::new(position2.x, position2.y);
-
-        if !self.is_locally_owned() {
-            next_position = old_position.lerp(next_position, alpha);
-            self.replicated_velocity = Vector2::new(velocity.x, velocity.y);
-            self.base_mut().set_position(next_position);
+    pub fn apply_state(&mut self, state: VarDictionary) {
+        let (Some(position), Some(velocity), Some(owner_id)) = (
+            property::<Vector2>(&state, "position"),
+            property::<Vector2>(&state, "velocity"),
+            property::<u32>(&state, "owner_id"),
+        ) else {
             return;
-        }
-
-        let current_pos = self.base().get_position();
-        let error_vec = next_position - current_pos;
-        let dist_error = error_vec.length() as f64;
+        };
+        self.owner_id = owner_id;
 
-        if dist_error <= ERROR_DISTANCE {
-            return;
+        if !self.is_locally_owned() {
+            self.replicated_velocity = velocity;
+            self.base_mut().set_position(position);
         }
+    }
 
-        if dist_error >= MAX_HARD_SNAP {
-            self.base_mut().set_position(next_position);
+    /// Moves the locally owned boat by one server frame for each input
+    /// `input_manager` sent since, the way the server does once it gets them,
+    /// after correcting it from the newest snapshot.
+    #[func]
+    pub fn predict(&mut self, mut input_manager: Gd<GDInputManager>) {
+        self.reconcile();
+
+        let Some(frame_duration) = self
+            .network_manager
+            .as_ref()
+            .map(|network_manager| network_manager.bind().frame_duration())
+        else {
             return;
+        };
+        let mut state = self.boat_state();
+        for input in input_manager.bind_mut().take_unpredicted() {
+            state = self
+                .prediction
+                .apply(&BOAT_CONFIG, state, input, frame_duration);
         }
+        self.set_boat_state(state);
+    }
 
-        let delta_time = self.base().get_process_delta_time();
-
-        // Bon bas là ça applique la méthode donné en cours, j'ai rien compris mais ça à l'air de marcher
-        // Par contre j'ai du baisser les paramètres de fou par apport aux exemples donnés, aucune idée de pourquoi
-
-        let g = 5.0;
-        let k = 15.0;
-        let d = 0.5;
+    /// Times the replayed position of the boat was too far from the predicted
+    /// one and it was moved there.
+    #[func]
+    pub fn get_mispredictions(&self) -> u32 {
+        self.mispredictions
+    }
 
-        let urgency = g * (1.0 / (MAX_HARD_SNAP - ERROR_DISTANCE));
+    /// Rewinds to the newest snapshot not reconciled yet and replays the inputs
+    /// the server had not applied, moving the boat only when it ends up
+    /// elsewhere.
+    fn reconcile(&mut self) {
+        let (Some(network_manager), Some(net_id)) = (&self.network_manager, self.net_id()) else {
+            return;
+        };
+        let Some((frame, state, last_input)) = network_manager.bind().latest_player_state(net_id)
+        else {
+            return;
+        };
+        let state = BoatState {
+            position: state.position,
+            velocity: state.velocity,
+        };
+        let Some(replayed) = self
+            .prediction
+            .reconcile(&BOAT_CONFIG, frame, state, last_input)
+        else {
+            return;
+        };
 
-        let exponent = exp(d * dist_error * delta_time);
-        let correction_mag = urgency * (k * dist_error * delta_time / exponent);
+        let position = Vector2::new(replayed.position.x, replayed.position.y);
+        if self.base().get_position().distance_to(position) > MISPREDICTION_DISTANCE {
+            self.mispredictions += 1;
+            self.set_boat_state(replayed);
+        }
+    }
 
-        let correction_vector = error_vec.normalized() * (correction_mag as f32);
+    fn boat_state(&self) -> BoatState {
+        let position = self.base().get_position();
+        let velocity = self.base().get_velocity();
+        BoatState {
+            position: Vec2::new(position.x, position.y),
+            velocity: Vec2::new(velocity.x, velocity.y),
+        }
+    }
 
-        let new_pos = current_pos + correction_vector;
-        self.base_mut().set_position(new_pos);
+    fn set_boat_state(&mut self, state: BoatState) {
+        self.base_mut()
+            .set_position(Vector2::new(state.position.x, state.position.y));
+        self.base_mut()
+            .set_velocity(Vector2::new(state.velocity.x, state.velocity.y));
+    }
 
-        // Bon en vrai j'ai compris l'idée générale, mais les math et la physique j'ai trop de lacunne pour l'expliquer
+    fn net_id(&self) -> Option<u32> {
+        let replicated_node = self
+            .base()
+            .get_parent()?
+            .try_cast::<GDReplicatedNode>()
+            .ok()?;
+        let net_id = replicated_node.bind().net_id;
+        Some(net_id)
     }
 }
+
+fn property<T: FromGodot>(state: &VarDictionary, name: &str) -> Option<T> {
+    state.get(name)?.try_to().ok()
+}
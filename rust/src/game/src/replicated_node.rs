@@ -1,4 +1,5 @@
-﻿use godot::classes::Node;
+﻿use godot::builtin::VarDictionary;
+use godot::classes::Node;
 use godot::obj::Base;
 use godot::prelude::{godot_api, GodotClass, INode};
 
@@ -20,6 +21,8 @@ impl INode for GDReplicatedNode {
 
 #[godot_api]
 impl GDReplicatedNode {
+    /// Replicated properties by name, blended between the two snapshots being
+    /// played.
     #[signal]
-    pub fn deserialize(snap1: Vec<u8>, snap2: Vec<u8>, alpha: f32);
+    pub fn deserialize(state: VarDictionary);
 }
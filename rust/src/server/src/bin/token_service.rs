@@ -0,0 +1,39 @@
+﻿//! Hands out connect tokens for the game server, one per TCP connection.
+//!
+//! Shares its key with the server through `RUSTY_TOKEN_KEY`, both fall back
+//! to the development key when it is not set.
+use common::connect_token::{DEV_TOKEN_KEY, TOKEN_KEY_VAR, TokenKey};
+use common::stream_writer::StreamWriter;
+use std::io::Write;
+use std::net::TcpListener;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const TOKEN_SERVICE_IP: &str = "127.0.0.1:3631";
+
+fn main() {
+    let token_key = TokenKey::from_env().unwrap_or_else(|| {
+        println!("{} not set, using the development token key", TOKEN_KEY_VAR);
+        TokenKey::new(&DEV_TOKEN_KEY)
+    });
+    let listener = TcpListener::bind(TOKEN_SERVICE_IP).expect("Token service address in use");
+    println!("Token service ready on address: {}", TOKEN_SERVICE_IP);
+
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let token = token_key.issue(rand::random(), now);
+        let client_id = token.client_id;
+
+        let mut stream_writer = StreamWriter::new();
+        stream_writer.write_serializable(token);
+        match stream.write_all(stream_writer.get_data()) {
+            Ok(()) => println!("Issued token for client {}", client_id),
+            Err(e) => println!("Token for client {} not sent: {}", client_id, e),
+        }
+    }
+}
@@ -1,20 +1,24 @@
-﻿use crate::network::connected_client::ConnectedClient;
+﻿use crate::SERVER_FREQUENCY;
+use crate::network::connected_client::ConnectedClient;
 use crate::replication::replicated_nodes::player::Player;
-use bevy::prelude::{Query, Resource};
-use bevy_rapier2d::prelude::Velocity;
+use bevy::prelude::{Query, Resource, Transform, Vec2};
 use common::input_packet::InputBuffer;
+use common::input_queue::MissingInput;
+use common::movement::{BOAT_CONFIG, BoatState};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Resource)]
 pub struct InputManager {
     pub server_frame: u32,
+    /// What boats do on the frames their input is missing for.
+    pub missing_input: MissingInput,
 }
 
 impl InputManager {
     pub fn handle_input(
         &mut self,
         buffers: Vec<InputBuffer>,
-        mut players: Query<(&mut Player, &mut Velocity)>,
+        mut players: Query<&mut Player>,
         mut clients: Query<&mut ConnectedClient>,
     ) {
         let server_time = SystemTime::now()
@@ -23,26 +27,50 @@ impl InputManager {
             .as_millis() as u64;
 
         for buffer in buffers {
-            if let Some((mut player, mut velocity)) = players
+            let Some(mut client) = clients
                 .iter_mut()
-                .find(|(player, _velocity)| player.net_id == buffer.node_id)
-            {
+                .find(|client| client.net_id == buffer.client_id)
+            else {
+                continue;
+            };
+            client.latest_data_received = server_time;
+
+            if players.iter().any(|player| {
+                player.net_id == buffer.node_id && player.owner_id == buffer.client_id
+            }) {
                 for input_packet in buffer.packets {
-                    if input_packet.sequence == self.server_frame {
-                        velocity.linvel = player.handle_input(input_packet);
-                        println!("{}", velocity.linvel);
-                    }
+                    client.inputs.push(input_packet, self.server_frame);
                 }
             }
+        }
 
+        for mut player in players.iter_mut() {
             if let Some(mut client) = clients
                 .iter_mut()
-                .find(|client| client.net_id == buffer.client_id)
+                .find(|client| client.net_id == player.owner_id)
             {
-                client.latest_data_received = server_time;
+                let input = client.inputs.pop(self.server_frame, self.missing_input);
+                player.handle_input(input);
             }
         }
 
         self.server_frame += 1;
     }
 }
+
+/// Moves each boat by one frame of its input, the way the client predicts it.
+/// The boats are kinematic, so nothing but this step moves them, and they go
+/// through each other.
+pub fn move_players(mut players: Query<(&mut Player, &mut Transform)>) {
+    let dt = (1.0 / SERVER_FREQUENCY) as f32;
+    for (mut player, mut transform) in players.iter_mut() {
+        let state = BoatState {
+            position: glm::Vec2::new(transform.translation.x, transform.translation.y),
+            velocity: glm::Vec2::new(player.velocity.x, player.velocity.y),
+        };
+        let state = BOAT_CONFIG.step(state, &player.input, dt);
+        transform.translation.x = state.position.x;
+        transform.translation.y = state.position.y;
+        player.velocity = Vec2::new(state.velocity.x, state.velocity.y);
+    }
+}
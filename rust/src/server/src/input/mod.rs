@@ -1,5 +1,12 @@
-﻿use crate::input::input_manager::InputManager;
-use bevy::app::{App, Plugin};
+﻿use crate::SERVER_FREQUENCY;
+use crate::input::input_manager::{InputManager, move_players};
+use crate::network::connected_client::ConnectedClient;
+use crate::network::poll;
+use bevy::app::{App, FixedUpdate, Plugin, Update};
+use bevy::prelude::{Fixed, IntoScheduleConfigs, Query, Time};
+use bevy::time::common_conditions::on_timer;
+use common::input_queue::MissingInput;
+use std::time::Duration;
 
 pub mod input_manager;
 
@@ -7,6 +14,30 @@ pub struct InputPlugin;
 
 impl Plugin for InputPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(InputManager { server_frame: 0 });
+        app.insert_resource(InputManager {
+            server_frame: 0,
+            missing_input: MissingInput::default(),
+        })
+        .insert_resource(Time::<Fixed>::from_hz(SERVER_FREQUENCY))
+        .add_systems(FixedUpdate, move_players.after(poll))
+        .add_systems(
+            Update,
+            report_inputs.run_if(on_timer(Duration::from_secs(10))),
+        );
+    }
+}
+
+fn report_inputs(connected_clients: Query<&ConnectedClient>) {
+    for client in connected_clients.iter() {
+        let stats = client.inputs.stats;
+        println!(
+            "Client {} inputs: {} applied, {} late, {} missing, {} duplicated, {} buffered",
+            client.net_id,
+            stats.applied,
+            stats.late,
+            stats.missing,
+            stats.duplicated,
+            client.inputs.buffered()
+        );
     }
 }
@@ -8,15 +8,24 @@ use crate::replication::ReplicationPlugin;
 use bevy::DefaultPlugins;
 use bevy::app::App;
 use bevy_rapier2d::prelude::*;
+use common::conditioner::{CONDITIONS_USAGE, NetworkConditions};
 
 const SERVER_IP: &str = "127.0.0.1:3630";
 const SERVER_FREQUENCY: f64 = 30.0;
 
 fn main() {
+    let conditions = match NetworkConditions::from_args(std::env::args().skip(1)) {
+        Ok(conditions) => conditions,
+        Err(e) => {
+            println!("{}\n{}", e, CONDITIONS_USAGE);
+            std::process::exit(2);
+        }
+    };
+
     App::new()
         .add_plugins(DefaultPlugins)
         .add_plugins(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(100.0))
-        .add_plugins(NetworkPlugin)
+        .add_plugins(NetworkPlugin { conditions })
         .add_plugins(ReplicationPlugin)
         .add_plugins(InputPlugin)
         .run();
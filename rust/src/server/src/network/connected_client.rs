@@ -1,8 +1,28 @@
-﻿use bevy::prelude::Component;
+﻿use crate::replication::snapshot_history::SentSnapshots;
+use bevy::prelude::Component;
+use common::ack::AckTracker;
+use common::compression::Compressor;
+use common::input_queue::InputQueue;
+use common::reliable::ReliableEndpoint;
+use common::session::{PUBLIC_KEY_SIZE, Session};
 
 #[derive(Component)]
 pub struct ConnectedClient {
     pub net_id: u32,
     pub address: String,
     pub latest_data_received: u64,
+    pub acks: AckTracker,
+    pub reliable: ReliableEndpoint,
+    pub snapshots: SentSnapshots,
+    /// Inputs received for the client's boat. The sequence of the newest one
+    /// applied goes with each snapshot for the client to replay the ones after.
+    pub inputs: InputQueue,
+    /// Set when compression was negotiated during the handshake.
+    pub compressor: Option<Compressor>,
+    /// Encrypts everything exchanged once the handshake completes.
+    pub session: Session,
+    /// Public keys of the key exchange, to answer the `Hsk` again when the
+    /// client repeats it.
+    pub client_key: [u8; PUBLIC_KEY_SIZE],
+    pub server_key: [u8; PUBLIC_KEY_SIZE],
 }
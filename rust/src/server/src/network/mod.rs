@@ -8,28 +8,41 @@ use crate::{SERVER_FREQUENCY, SERVER_IP};
 use bevy::app::{App, Plugin};
 use bevy::prelude::*;
 use bevy::time::common_conditions::on_timer;
-use bevy_rapier2d::dynamics::Velocity;
+use common::ack::PacketStatus;
+use common::conditioner::NetworkConditions;
 use common::message_header::{DataType, MessageHeader, MessageType};
 use common::ping_request::{PingRequest, PingResponse};
+use common::reliable::CHAT_CHANNEL;
 use common::stream_writer::StreamWriter;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 pub mod connected_client;
 pub mod network_manager;
 
-pub struct NetworkPlugin;
+pub struct NetworkPlugin {
+    /// Bad network the server's socket is put through, to try the game on it.
+    pub conditions: NetworkConditions,
+}
 
 impl Plugin for NetworkPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(NetworkManager::new(SERVER_IP))
+        app.insert_resource(NetworkManager::new(SERVER_IP, self.conditions))
             .add_message::<PingReceived>()
-            .add_systems(Update, on_ping_received)
+            .add_message::<PacketResolved>()
+            .add_message::<ReliableReceived>()
+            .add_systems(
+                Update,
+                (on_ping_received, on_packet_resolved, on_reliable_received),
+            )
             .insert_resource(Time::<Fixed>::from_hz(SERVER_FREQUENCY))
             .add_systems(
                 FixedUpdate,
                 (
                     poll,
+                    receive_reliable_messages.after(poll),
+                    resend_reliable_messages,
                     handle_timeout.run_if(on_timer(Duration::from_secs(1))),
+                    report_compression.run_if(on_timer(Duration::from_secs(10))),
                 ),
             );
     }
@@ -55,21 +68,43 @@ fn handle_timeout(
     }
 }
 
-fn poll(
+fn report_compression(connected_clients: Query<&ConnectedClient>) {
+    for client in connected_clients.iter() {
+        let Some(compressor) = &client.compressor else {
+            continue;
+        };
+        for (message_type, stats) in compressor.stats() {
+            println!(
+                "Client {} {:?}: {}/{} compressed, {:.0}% of {} bytes",
+                client.net_id,
+                message_type,
+                stats.compressed_messages,
+                stats.messages,
+                stats.ratio() * 100.0,
+                stats.original_bytes
+            );
+        }
+    }
+}
+
+pub fn poll(
     commands: Commands,
-    network_manager: ResMut<NetworkManager>,
-    players: Query<(&mut Player, &mut Velocity)>,
-    clients: Query<&mut ConnectedClient>,
+    mut network_manager: ResMut<NetworkManager>,
+    players: Query<&mut Player>,
+    mut clients: Query<&mut ConnectedClient>,
     mut input_manager: ResMut<InputManager>,
     ev_ping_received: MessageWriter<PingReceived>,
     ev_client_connected: MessageWriter<ClientConnected>,
     ev_client_disconnected: MessageWriter<ClientDisconnected>,
+    ev_packet_resolved: MessageWriter<PacketResolved>,
 ) {
     let poll_events = network_manager.poll(
         commands,
         ev_ping_received,
         ev_client_connected,
         ev_client_disconnected,
+        ev_packet_resolved,
+        &mut clients,
     );
     input_manager.handle_input(poll_events, players, clients);
 }
@@ -80,6 +115,69 @@ pub struct PingReceived {
     address: String,
 }
 
+/// A sequenced packet sent to a client was acknowledged or given up on.
+#[derive(Message, Debug)]
+pub struct PacketResolved {
+    pub client_net_id: u32,
+    pub sequence: u16,
+    pub status: PacketStatus,
+}
+
+/// A message arrived in order on a reliable channel of a client.
+#[derive(Message, Debug)]
+pub struct ReliableReceived {
+    pub client_net_id: u32,
+    pub channel: u8,
+    pub data: Vec<u8>,
+}
+
+fn receive_reliable_messages(
+    mut clients: Query<&mut ConnectedClient>,
+    mut ev_reliable_received: MessageWriter<ReliableReceived>,
+) {
+    for mut client in clients.iter_mut() {
+        while let Some((channel, data)) = client.reliable.pop_received() {
+            ev_reliable_received.write(ReliableReceived {
+                client_net_id: client.net_id,
+                channel,
+                data,
+            });
+        }
+    }
+}
+
+fn resend_reliable_messages(
+    network_manager: Res<NetworkManager>,
+    mut clients: Query<&mut ConnectedClient>,
+) {
+    for mut client in clients.iter_mut() {
+        network_manager.flush_reliable(&mut client);
+    }
+}
+
+fn on_reliable_received(
+    mut messages: MessageReader<ReliableReceived>,
+    network_manager: Res<NetworkManager>,
+    mut connected_clients: Query<&mut ConnectedClient>,
+) {
+    for reliable_received in messages.read() {
+        if reliable_received.channel != CHAT_CHANNEL {
+            continue;
+        }
+
+        for mut client in connected_clients.iter_mut() {
+            if client.net_id == reliable_received.client_net_id {
+                continue;
+            }
+            if let Err(e) =
+                network_manager.send_reliable(&mut client, CHAT_CHANNEL, &reliable_received.data)
+            {
+                println!("Chat message to client {} not sent: {}", client.net_id, e);
+            }
+        }
+    }
+}
+
 fn on_ping_received(
     mut messages: MessageReader<PingReceived>,
     network_manager: Res<NetworkManager>,
@@ -92,13 +190,6 @@ fn on_ping_received(
             .unwrap()
             .as_millis() as u64;
 
-        if let Some(mut connected_client) = connected_clients
-            .iter_mut()
-            .find(|client| client.address == ping_received.address)
-        {
-            connected_client.latest_data_received = server_time;
-        }
-
         let mut stream_writer = StreamWriter::new();
         stream_writer.write_serializable(MessageHeader::init(MessageType::Ping, DataType::None));
         let ping_response = PingResponse {
@@ -108,6 +199,35 @@ fn on_ping_received(
         };
         stream_writer.write_serializable(ping_response);
 
-        network_manager.send_data(&ping_received.address, stream_writer.get_data())
+        if let Some(mut connected_client) = connected_clients
+            .iter_mut()
+            .find(|client| client.address == ping_received.address)
+        {
+            connected_client.latest_data_received = server_time;
+            network_manager.send_to_client(&mut connected_client, stream_writer.get_data_mut());
+        }
+    }
+}
+
+fn on_packet_resolved(
+    mut messages: MessageReader<PacketResolved>,
+    connected_clients: Query<&ConnectedClient>,
+) {
+    for packet_resolved in messages.read() {
+        if packet_resolved.status != PacketStatus::Lost {
+            continue;
+        }
+
+        if let Some(client) = connected_clients
+            .iter()
+            .find(|client| client.net_id == packet_resolved.client_net_id)
+        {
+            println!(
+                "Packet {} to client {} lost ({:.1}% loss)",
+                packet_resolved.sequence,
+                client.net_id,
+                client.acks.packet_loss() * 100.0
+            );
+        }
     }
 }
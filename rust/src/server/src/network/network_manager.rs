@@ -1,73 +1,273 @@
 ﻿use crate::SERVER_FREQUENCY;
-use crate::network::PingReceived;
 use crate::network::connected_client::ConnectedClient;
+use crate::network::{PacketResolved, PingReceived};
 use crate::replication::events::on_client_connected::ClientConnected;
 use crate::replication::events::on_client_disconnected::ClientDisconnected;
-use bevy::prelude::{Commands, MessageWriter, Resource};
-use common::handshake::Handshake;
+use crate::replication::snapshot_history::SentSnapshots;
+use bevy::prelude::{Commands, MessageWriter, Query, Resource};
+use common::ack::AckTracker;
+use common::challenge::{Cookie, CookieIssuer, HANDSHAKE_PACKET_SIZE};
+use common::compression::{Compressor, decompress};
+use common::conditioner::{ConditionedTransport, NetworkConditions};
+use common::connect_token::{
+    DEV_TOKEN_KEY, SealedConnectToken, TOKEN_KEY_VAR, TokenKey, UsedTokens,
+};
+use common::decode_error::DecodeError;
+use common::fragment::{Fragmenter, Reassembler};
+use common::handshake::{Handshake, HandshakeRequest, KeyShare, ProtocolInfo, Rejection};
 use common::input_packet::InputBuffer;
-use common::message_header::{DataType, MessageHeader, MessageType};
+use common::input_queue::InputQueue;
+use common::message_header::{DataType, MessageHeader, MessageType, PacketError};
 use common::ping_request::PingRequest;
+use common::reliable::{ReliableEndpoint, ReliableError};
+use common::session::KeyExchange;
 use common::stream_reader::StreamReader;
 use common::stream_writer::StreamWriter;
-use snl::GameSocket;
+use common::transport::{Transport, UdpTransport};
+use std::borrow::Cow;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Fragmented messages being rebuilt at once, across all clients.
+const MAX_REASSEMBLY_GROUPS: usize = 256;
+/// Fragmented messages being rebuilt at once for a single client.
+const MAX_REASSEMBLY_GROUPS_PER_CLIENT: usize = 8;
 
 #[derive(Resource)]
 pub struct NetworkManager {
-    socket: Option<GameSocket>,
+    transport: Option<Box<dyn Transport>>,
+    fragmenter: Fragmenter,
+    pub reassembler: Reassembler<String>,
+    pub dropped_packets: u32,
+    pub foreign_packets: u32,
+    pub corrupted_packets: u32,
+    /// Packets other than `Helo` and `Hsk` from an address without a session,
+    /// fragments included.
+    pub unauthenticated_packets: u32,
+    /// Whether clients asking for compression get it.
+    pub compression: bool,
+    cookies: CookieIssuer,
+    token_key: TokenKey,
+    /// Keeps the connect tokens accepted from being replayed from another address.
+    used_tokens: UsedTokens<String>,
+    /// `Helo` and `Hsk` ignored for being too small or carrying a bad cookie.
+    pub refused_handshakes: u32,
 }
 
 impl NetworkManager {
-    pub fn new(addr: &str) -> Self {
-        let socket = GameSocket::new(addr);
-
-        match socket {
-            Ok(socket) => {
+    pub fn new(addr: &str, conditions: NetworkConditions) -> Self {
+        let transport = match UdpTransport::bind(addr) {
+            Ok(transport) if conditions.is_ideal() => {
                 println!("Server ready on address: {}", addr);
-                Self {
-                    socket: Some(socket),
-                }
+                Some(Box::new(transport) as Box<dyn Transport>)
+            }
+            Ok(transport) => {
+                println!("Server ready on address: {} under {:?}", addr, conditions);
+                Some(Box::new(ConditionedTransport::new(transport, conditions))
+                    as Box<dyn Transport>)
             }
-            Err(_) => Self { socket: None },
+            Err(e) => {
+                println!("Server not bound to {}: {}", addr, e);
+                None
+            }
+        };
+        Self::with_transport(transport)
+    }
+
+    /// Server exchanging its packets through `transport`, a `MemoryTransport`
+    /// when driven without sockets.
+    pub fn with_transport(transport: Option<Box<dyn Transport>>) -> Self {
+        Self {
+            transport,
+            fragmenter: Fragmenter::default(),
+            reassembler: Reassembler::with_sender_limit(
+                MAX_REASSEMBLY_GROUPS,
+                MAX_REASSEMBLY_GROUPS_PER_CLIENT,
+            ),
+            dropped_packets: 0,
+            foreign_packets: 0,
+            corrupted_packets: 0,
+            unauthenticated_packets: 0,
+            compression: true,
+            cookies: CookieIssuer::new(rand::random()),
+            token_key: TokenKey::from_env().unwrap_or_else(|| {
+                println!("{} not set, using the development token key", TOKEN_KEY_VAR);
+                TokenKey::new(&DEV_TOKEN_KEY)
+            }),
+            used_tokens: UsedTokens::new(),
+            refused_handshakes: 0,
+        }
+    }
+
+    /// Whether `size`, the size of a `Helo` or `Hsk`, is enough to answer it.
+    fn check_request_size(&mut self, addr: &str, size: usize) -> bool {
+        if size >= HANDSHAKE_PACKET_SIZE {
+            return true;
         }
+        self.refuse_handshake(addr, "request not padded");
+        false
+    }
+
+    fn refuse_handshake(&mut self, addr: &str, reason: &str) {
+        self.refused_handshakes += 1;
+        println!(
+            "Refused handshake from {} ({} refused): {}",
+            addr, self.refused_handshakes, reason
+        );
     }
 
-    fn handle_helo(&self, addr: String) {
+    /// Answers with a `Rejection` and returns false when the client's build
+    /// cannot talk to this one.
+    fn check_protocol(&self, addr: &String, request_size: usize, protocol: &ProtocolInfo) -> bool {
+        let Err(reason) = protocol.check() else {
+            return true;
+        };
+
+        let rejection = Rejection::new(reason, protocol);
+        println!("Rejected {}: {}", addr, rejection.message);
+
+        let mut stream_writer = StreamWriter::new();
+        stream_writer.write_serializable(MessageHeader::init(MessageType::Reject, DataType::None));
+        stream_writer.write_serializable(rejection);
+        self.send_reply(addr, request_size, stream_writer.get_data_mut());
+        false
+    }
+
+    /// Answers with a `Helo` carrying a fresh cookie for the client to echo in
+    /// its `Hsk`.
+    fn send_challenge(&self, addr: &String, request_size: usize) {
+        let cookie = self.cookies.issue(addr, unix_time());
+
         let mut stream_writer = StreamWriter::new();
         stream_writer.write_serializable(MessageHeader::init(MessageType::Helo, DataType::None));
-        self.send_data(&addr, stream_writer.get_data());
+        stream_writer.write_serializable(cookie);
+        self.send_reply(addr, request_size, stream_writer.get_data_mut());
+    }
+
+    fn handle_helo(
+        &mut self,
+        addr: String,
+        request_size: usize,
+        mut stream_reader: StreamReader,
+    ) -> Result<(), DecodeError> {
+        if !self.check_request_size(&addr, request_size) {
+            return Ok(());
+        }
+        let protocol: ProtocolInfo = stream_reader.try_read_serializable()?;
+        if !self.check_protocol(&addr, request_size, &protocol) {
+            return Ok(());
+        }
+
+        self.send_challenge(&addr, request_size);
+        Ok(())
     }
 
     fn handle_hsk(
-        &self,
+        &mut self,
         addr: String,
+        request_size: usize,
+        mut stream_reader: StreamReader,
         commands: &mut Commands,
         ev_client_connected: &mut MessageWriter<ClientConnected>,
-    ) {
-        let client_net_id = rand::random();
-        let connected_client = commands
-            .spawn(ConnectedClient {
-                net_id: client_net_id,
-                address: addr.clone(),
-                latest_data_received: 0,
-            })
-            .id();
-
-        let client_connected = ClientConnected {
+        clients: &mut Query<&mut ConnectedClient>,
+    ) -> Result<(), DecodeError> {
+        if !self.check_request_size(&addr, request_size) {
+            return Ok(());
+        }
+        let protocol: ProtocolInfo = stream_reader.try_read_serializable()?;
+        if !self.check_protocol(&addr, request_size, &protocol) {
+            return Ok(());
+        }
+        let cookie: Cookie = stream_reader.try_read_serializable()?;
+        if !self.cookies.verify(&addr, &cookie, unix_time()) {
+            // Likely expired while the client retried, it gets another one.
+            self.refuse_handshake(&addr, "invalid cookie");
+            self.send_challenge(&addr, request_size);
+            return Ok(());
+        }
+        let sealed_token: SealedConnectToken = stream_reader.try_read_serializable()?;
+        let key_share: KeyShare = stream_reader.try_read_serializable()?;
+        let request: HandshakeRequest = stream_reader.try_read_serializable()?;
+
+        // The client sends `Hsk` until it hears back, answer the copies
+        // without allocating another client.
+        if let Some(mut client) = clients.iter_mut().find(|client| client.address == addr) {
+            if client.client_key == key_share.public_key {
+                self.send_handshake(&mut client, request_size);
+            }
+            return Ok(());
+        }
+
+        let now = unix_time();
+        let token = match self.token_key.open(&sealed_token, now).and_then(|token| {
+            self.used_tokens
+                .register(&sealed_token, addr.clone(), now)
+                .map(|()| token)
+        }) {
+            Ok(token) => token,
+            Err(e) => {
+                self.refuse_handshake(&addr, &e.to_string());
+                return Ok(());
+            }
+        };
+        if clients
+            .iter()
+            .any(|client| client.net_id == token.client_id)
+        {
+            self.refuse_handshake(&addr, "client already connected");
+            return Ok(());
+        }
+        let key_exchange = KeyExchange::new();
+        let Some(session) = key_exchange.server_session(&key_share.public_key, &token.key) else {
+            self.refuse_handshake(&addr, "invalid public key");
+            return Ok(());
+        };
+        let compression = request.compression && self.compression;
+
+        let mut client = ConnectedClient {
+            net_id: token.client_id,
+            address: addr.clone(),
+            latest_data_received: 0,
+            acks: AckTracker::new(),
+            reliable: ReliableEndpoint::new(),
+            snapshots: SentSnapshots::default(),
+            inputs: InputQueue::new(),
+            compressor: compression.then(Compressor::new),
+            session,
+            client_key: key_share.public_key,
+            server_key: key_exchange.public_key(),
+        };
+        self.send_handshake(&mut client, request_size);
+
+        let client_net_id = client.net_id;
+        let connected_client = commands.spawn(client).id();
+        ev_client_connected.write(ClientConnected {
             entity: connected_client,
             client_net_id,
-        };
+        });
+        Ok(())
+    }
 
+    /// Answers the client's `Hsk` with the server half of the key exchange,
+    /// followed by the `Handshake` encrypted by the new session.
+    fn send_handshake(&self, client: &mut ConnectedClient, request_size: usize) {
         let mut stream_writer = StreamWriter::new();
         stream_writer.write_serializable(MessageHeader::init(MessageType::Hsk, DataType::None));
+        stream_writer.write_serializable(ProtocolInfo::current());
+        stream_writer.write_serializable(KeyShare {
+            public_key: client.server_key,
+        });
+        let offset = stream_writer.get_data().len();
         stream_writer.write_serializable(Handshake {
-            client_id: client_net_id,
+            client_id: client.net_id,
             server_frequency: SERVER_FREQUENCY,
+            compression: client.compressor.is_some(),
         });
 
-        println!("Send hsk to {}", addr);
-        self.send_data(&addr, stream_writer.get_data());
-        ev_client_connected.write(client_connected);
+        println!("Send hsk to {}", client.address);
+        let packet = client
+            .session
+            .encrypt_from(stream_writer.get_data(), offset);
+        self.send_reply(&client.address, request_size, packet);
     }
 
     fn handle_ping(
@@ -75,68 +275,332 @@ impl NetworkManager {
         address: String,
         mut stream_reader: StreamReader,
         ev_ping_received: &mut MessageWriter<PingReceived>,
-    ) {
-        let ping_request: PingRequest = stream_reader.read_serializable();
+    ) -> Result<(), DecodeError> {
+        let ping_request: PingRequest = stream_reader.try_read_serializable()?;
 
         ev_ping_received.write(PingReceived {
             ping_request,
             address,
         });
+        Ok(())
     }
 
-    fn handle_bye(
+    fn handle_reliable(
         &self,
+        addr: &str,
         mut stream_reader: StreamReader,
-        ev_client_disconnected: &mut MessageWriter<ClientDisconnected>,
-    ) {
-        let net_id = stream_reader.read_u32();
+        clients: &mut Query<&mut ConnectedClient>,
+    ) -> Result<(), DecodeError> {
+        match clients.iter_mut().find(|client| client.address == addr) {
+            Some(mut client) => client.reliable.receive(&mut stream_reader),
+            None => Ok(()),
+        }
+    }
 
-        ev_client_disconnected.write(ClientDisconnected {
-            client_net_id: net_id,
-        });
+    fn handle_bye(
+        &self,
+        addr: &str,
+        clients: &Query<&mut ConnectedClient>,
+        ev_client_disconnected: &mut MessageWriter<ClientDisconnected>,
+    ) -> Result<(), DecodeError> {
+        // The id in the packet is not trusted, the session tells who sent it.
+        if let Some(client) = clients.iter().find(|client| client.address == addr) {
+            ev_client_disconnected.write(ClientDisconnected {
+                client_net_id: client.net_id,
+            });
+        }
+        Ok(())
     }
 
     pub fn poll(
-        &self,
+        &mut self,
         mut commands: Commands,
         mut ev_ping_received: MessageWriter<PingReceived>,
         mut ev_client_connected: MessageWriter<ClientConnected>,
         mut ev_client_disconnected: MessageWriter<ClientDisconnected>,
+        mut ev_packet_resolved: MessageWriter<PacketResolved>,
+        clients: &mut Query<&mut ConnectedClient>,
     ) -> Vec<InputBuffer> {
         let mut input_buffers = Vec::new();
+        self.reassembler.expire(Instant::now());
 
         loop {
             let mut buf = [0; 1500];
-            if let Some(socket) = self.socket.as_ref() {
-                if let Some((size, socket_addr)) = socket.poll(&mut buf) {
-                    let buf = &mut buf[..size];
-                    let mut stream_reader = StreamReader::new(buf.to_vec());
-                    let message_header: MessageHeader = stream_reader.read_serializable();
-                    match message_header.message_type {
-                        MessageType::Helo => self.handle_helo(socket_addr),
-                        MessageType::Hsk => {
-                            self.handle_hsk(socket_addr, &mut commands, &mut ev_client_connected)
-                        }
-                        MessageType::Ping => {
-                            self.handle_ping(socket_addr, stream_reader, &mut ev_ping_received)
-                        }
-                        MessageType::Data => input_buffers.push(stream_reader.read_serializable()),
-                        MessageType::Bye => {
-                            self.handle_bye(stream_reader, &mut ev_client_disconnected)
-                        }
-                    };
-                } else {
-                    break;
+            let Some(transport) = self.transport.as_ref() else {
+                break;
+            };
+            let Some((size, socket_addr)) = transport.recv_from(&mut buf) else {
+                break;
+            };
+
+            if let Err(e) = MessageHeader::verify(&buf[..size]) {
+                match e {
+                    PacketError::UnknownProtocol => self.foreign_packets += 1,
+                    PacketError::BadChecksum => self.corrupted_packets += 1,
+                }
+                println!("Dropped packet from {}: {}", socket_addr, e);
+                continue;
+            }
+
+            // Handshakes are never fragmented, so only clients with a session
+            // get to hold reassembly buffers.
+            let is_fragment = StreamReader::new(&buf[..size])
+                .try_read_serializable::<MessageHeader>()
+                .is_ok_and(|message_header| message_header.message_type == MessageType::Fragment);
+            if is_fragment && !clients.iter().any(|client| client.address == socket_addr) {
+                self.unauthenticated_packets += 1;
+                println!(
+                    "Dropped Fragment from {} without a session ({} dropped)",
+                    socket_addr, self.unauthenticated_packets
+                );
+                continue;
+            }
+
+            let message =
+                match self
+                    .reassembler
+                    .receive(socket_addr.clone(), &buf[..size], Instant::now())
+                {
+                    Ok(Some(message)) => message,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        self.drop_malformed(&socket_addr, e);
+                        continue;
+                    }
+                };
+            let Some(message) = self.open_message(&socket_addr, message, clients) else {
+                continue;
+            };
+            let message = match decompress(&message) {
+                Ok(message) => message,
+                Err(e) => {
+                    self.drop_malformed(&socket_addr, e);
+                    continue;
+                }
+            };
+
+            let mut stream_reader = StreamReader::new(&message);
+            let message_header = match stream_reader.try_read_serializable::<MessageHeader>() {
+                Ok(message_header) => message_header,
+                Err(e) => {
+                    self.drop_malformed(&socket_addr, e);
+                    continue;
                 }
+            };
+            if !receive_acks(
+                &socket_addr,
+                &message_header,
+                clients,
+                &mut ev_packet_resolved,
+            ) {
+                continue;
+            }
+
+            let result = match message_header.message_type {
+                MessageType::Helo => {
+                    self.handle_helo(socket_addr.clone(), message.len(), stream_reader)
+                }
+                MessageType::Hsk => self.handle_hsk(
+                    socket_addr.clone(),
+                    message.len(),
+                    stream_reader,
+                    &mut commands,
+                    &mut ev_client_connected,
+                    clients,
+                ),
+                MessageType::Ping => {
+                    self.handle_ping(socket_addr.clone(), stream_reader, &mut ev_ping_received)
+                }
+                MessageType::Data => {
+                    stream_reader
+                        .try_read_serializable()
+                        .map(|mut input_buffer: InputBuffer| {
+                            // Inputs only ever move the boats of the client that sent them.
+                            if let Some(client) =
+                                clients.iter().find(|client| client.address == socket_addr)
+                            {
+                                input_buffer.client_id = client.net_id;
+                                input_buffers.push(input_buffer);
+                            }
+                        })
+                }
+                MessageType::Bye => {
+                    self.handle_bye(&socket_addr, clients, &mut ev_client_disconnected)
+                }
+                MessageType::Reliable => self.handle_reliable(&socket_addr, stream_reader, clients),
+                // Only ever sent by the server.
+                MessageType::Reject => Ok(()),
+                // Consumed by the reassembler.
+                MessageType::Fragment => Ok(()),
+            };
+
+            if let Err(e) = result {
+                self.drop_malformed(&socket_addr, e);
             }
         }
 
         input_buffers
     }
 
-    pub fn send_data(&self, addr: &String, buffer: &[u8]) {
-        if let Some(socket) = self.socket.as_ref() {
-            socket.send(&addr, &buffer).expect("Error Message sending");
+    /// Decrypts `message` with the session of its sender. Only `Helo` and
+    /// `Hsk` are read without one.
+    fn open_message<'m>(
+        &mut self,
+        addr: &str,
+        message: Cow<'m, [u8]>,
+        clients: &mut Query<&mut ConnectedClient>,
+    ) -> Option<Cow<'m, [u8]>> {
+        let message_header =
+            match StreamReader::new(&message).try_read_serializable::<MessageHeader>() {
+                Ok(message_header) => message_header,
+                Err(e) => {
+                    self.drop_malformed(addr, e);
+                    return None;
+                }
+            };
+        if matches!(
+            message_header.message_type,
+            MessageType::Helo | MessageType::Hsk
+        ) {
+            return Some(message);
+        }
+
+        let Some(mut client) = clients.iter_mut().find(|client| client.address == addr) else {
+            self.unauthenticated_packets += 1;
+            println!(
+                "Dropped {:?} from {} without a session ({} dropped)",
+                message_header.message_type, addr, self.unauthenticated_packets
+            );
+            return None;
+        };
+        match client.session.decrypt(&message) {
+            Ok(message) => Some(Cow::Owned(message)),
+            Err(e) => {
+                self.drop_malformed(addr, e);
+                None
+            }
         }
     }
+
+    fn drop_malformed(&mut self, addr: &str, e: DecodeError) {
+        self.dropped_packets += 1;
+        println!(
+            "Dropped malformed packet from {} ({} dropped): {}",
+            addr, self.dropped_packets, e
+        );
+    }
+
+    /// Queues `bytes` on a reliable ordered channel of the client and sends
+    /// what is due on it.
+    pub fn send_reliable(
+        &self,
+        client: &mut ConnectedClient,
+        channel: u8,
+        bytes: &[u8],
+    ) -> Result<(), ReliableError> {
+        client.reliable.send(channel, bytes)?;
+        self.flush_reliable(client);
+        Ok(())
+    }
+
+    /// Sends the reliable messages of the client not sent yet or due again.
+    pub fn flush_reliable(&self, client: &mut ConnectedClient) {
+        let ConnectedClient {
+            address,
+            acks,
+            reliable,
+            compressor,
+            session,
+            ..
+        } = client;
+        reliable.write_packets(acks, Instant::now(), |packet| {
+            let packet = match compressor {
+                Some(compressor) => compressor.compress(packet),
+                None => packet,
+            };
+            self.send_data(address, session.encrypt(packet))
+        });
+    }
+
+    /// Numbers `buffer` on the client's connection and sends it, compressed
+    /// when negotiated and encrypted, returning the sequence it was given.
+    pub fn send_to_client(&self, client: &mut ConnectedClient, buffer: &mut [u8]) -> u16 {
+        let acks = client.acks.send();
+        MessageHeader::write_acks(buffer, acks);
+        let packet = match client.compressor.as_mut() {
+            Some(compressor) => compressor.compress(buffer),
+            None => buffer,
+        };
+        self.send_data(&client.address, client.session.encrypt(packet));
+        acks.sequence
+    }
+
+    /// Sends `buffer` in answer to a request of `request_size` bytes from a
+    /// client not connected yet, unless it is larger.
+    fn send_reply(&self, addr: &String, request_size: usize, buffer: &mut [u8]) {
+        if buffer.len() > request_size {
+            println!(
+                "Reply of {} bytes to {} not sent, the request had {}",
+                buffer.len(),
+                addr,
+                request_size
+            );
+            return;
+        }
+        self.send_data(addr, buffer);
+    }
+
+    /// Seals `buffer`, a packet starting with a `MessageHeader`, and sends it,
+    /// split into fragments when it does not fit in one datagram.
+    pub fn send_data(&self, addr: &String, buffer: &mut [u8]) {
+        MessageHeader::seal(buffer);
+        if let Some(transport) = self.transport.as_ref() {
+            let result = self.fragmenter.send(buffer, |packet| {
+                if let Err(e) = transport.send_to(addr, packet) {
+                    println!("Packet to {} not sent: {}", addr, e);
+                }
+            });
+            if let Err(e) = result {
+                println!("Message to {} not sent: {}", addr, e);
+            }
+        }
+    }
+}
+
+/// Seconds since the Unix epoch, the clock of the cookies.
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Feeds the acks of a sequenced message to its client's `AckTracker`.
+/// Returns false when the message is a duplicate and must be dropped.
+fn receive_acks(
+    addr: &str,
+    message_header: &MessageHeader,
+    clients: &mut Query<&mut ConnectedClient>,
+    ev_packet_resolved: &mut MessageWriter<PacketResolved>,
+) -> bool {
+    if !message_header.message_type.is_sequenced() {
+        return true;
+    }
+    let Some(client) = clients.iter_mut().find(|client| client.address == addr) else {
+        return true;
+    };
+
+    let client = client.into_inner();
+    let client_net_id = client.net_id;
+    let reliable = &mut client.reliable;
+    client
+        .acks
+        .receive(&message_header.acks, |sequence, status| {
+            reliable.on_packet_status(sequence, status);
+            ev_packet_resolved.write(PacketResolved {
+                client_net_id,
+                sequence,
+                status,
+            });
+        })
 }
@@ -1,7 +1,6 @@
 ﻿use crate::replication::replicated_nodes::player::Player;
 use crate::replication::replication_manager::{ClientEntityLink, ReplicationManager};
 use bevy::prelude::*;
-use bevy_rapier2d::dynamics::Velocity;
 use bevy_rapier2d::prelude::{Collider, GravityScale, RigidBody};
 
 #[derive(Message, Debug)]
@@ -25,15 +24,13 @@ pub fn on_client_connected(
         let player = Player::new(player_net_id, on_connected.client_net_id);
 
         let player_entity = commands
+            // Only moved by `move_players`, exactly like the client predicts
+            // it: the boats do not collide with anything, on neither side.
             .spawn((
                 player,
-                RigidBody::Dynamic,
+                RigidBody::KinematicPositionBased,
                 Collider::ball(15.0),
                 position,
-                Velocity {
-                    linvel: Vec2::new(0.0, 0.0),
-                    angvel: 0.0,
-                },
                 GravityScale(0.0),
             ))
             .id();
@@ -1,16 +1,21 @@
 ﻿use crate::SERVER_FREQUENCY;
+use crate::input::input_manager::move_players;
 use crate::replication::events::on_client_connected::{ClientConnected, on_client_connected};
 use crate::replication::events::on_client_disconnected::{
     ClientDisconnected, on_client_disconnected,
 };
-use crate::replication::replication_manager::{ReplicationManager, handle_snapshots};
+use crate::replication::replication_manager::{
+    ReplicationManager, acknowledge_snapshots, handle_snapshots,
+};
+use crate::replication::snapshot_history::SnapshotHistory;
 use bevy::app::{App, FixedUpdate, Plugin, Update};
-use bevy::prelude::{Fixed, Time};
+use bevy::prelude::{Fixed, IntoScheduleConfigs, Time};
 use std::collections::HashMap;
 
 pub mod events;
 pub mod replicated_nodes;
 pub mod replication_manager;
+pub mod snapshot_history;
 
 pub struct ReplicationPlugin;
 
@@ -19,10 +24,18 @@ impl Plugin for ReplicationPlugin {
         app.insert_resource(ReplicationManager {
             client_entities: HashMap::new(),
         })
+        .init_resource::<SnapshotHistory>()
         .add_message::<ClientConnected>()
         .add_message::<ClientDisconnected>()
         .add_systems(Update, (on_client_connected, on_client_disconnected))
         .insert_resource(Time::<Fixed>::from_hz(SERVER_FREQUENCY))
-        .add_systems(FixedUpdate, handle_snapshots);
+        .add_systems(
+            FixedUpdate,
+            // After the boats moved on the frame each client's
+            // `InputQueue::last_simulated` refers to.
+            (acknowledge_snapshots, handle_snapshots)
+                .chain()
+                .after(move_players),
+        );
     }
 }
@@ -1,13 +1,22 @@
-﻿use bevy::prelude::Component;
-use bevy::prelude::Vec2;
-use common::input_packet::{Input, InputPacket};
-use common::stream_writer::{Serializable, StreamWriter};
+﻿use bevy::prelude::{Component, Vec2};
+use common::input_packet::InputPacket;
+use common::player_state::PLAYER_TYPE_ID;
+use common::stream_reader::Deserializable;
+use common::stream_writer::Serializable;
 
-#[derive(Component)]
+#[derive(Component, Serializable, Deserializable)]
 pub struct Player {
+    #[stream(skip)]
     pub net_id: u32,
+    #[stream(skip)]
     pub type_id: u32,
     pub owner_id: u32,
+    /// Input steering the boat until the next one arrives.
+    #[stream(skip)]
+    pub input: InputPacket,
+    /// Velocity of the last step of `BoatConfig`, the one the client predicts.
+    #[stream(skip)]
+    pub velocity: Vec2,
 }
 
 impl Clone for Player {
@@ -16,6 +25,8 @@ impl Clone for Player {
             net_id: self.net_id,
             type_id: self.type_id,
             owner_id: self.owner_id,
+            input: self.input.clone(),
+            velocity: self.velocity,
         }
     }
 }
@@ -24,21 +35,14 @@ impl Player {
     pub fn new(net_id: u32, owner_id: u32) -> Self {
         Self {
             net_id,
-            type_id: 0,
+            type_id: PLAYER_TYPE_ID,
             owner_id,
+            input: InputPacket::new(),
+            velocity: Vec2::ZERO,
         }
     }
 
-    pub fn handle_input(&mut self, input_packet: InputPacket) -> Vec2 {
-        let input_direction =
-            input_packet.read_vector(Input::Right, Input::Left, Input::Up, Input::Down);
-        let direction = Vec2::new(input_direction.x, input_direction.y);
-        direction * 500.0
-    }
-}
-
-impl Serializable for Player {
-    fn serialize(&self, stream: &mut StreamWriter) {
-        stream.write_u32(self.owner_id);
+    pub fn handle_input(&mut self, input_packet: InputPacket) {
+        self.input = input_packet;
     }
 }
@@ -1,12 +1,14 @@
 ﻿use crate::input::input_manager::InputManager;
+use crate::network::PacketResolved;
 use crate::network::connected_client::ConnectedClient;
 use crate::network::network_manager::NetworkManager;
 use crate::replication::replicated_nodes::player::Player;
-use bevy::prelude::{Entity, Query, Res, Resource, Transform};
-use bevy_rapier2d::prelude::Velocity;
+use crate::replication::snapshot_history::SnapshotHistory;
+use bevy::prelude::{Entity, Local, MessageReader, Query, Res, ResMut, Resource, Transform};
+use common::ack::PacketStatus;
 use common::message_header::{DataType, MessageHeader, MessageType};
-use common::replicated_node::ReplicatedNode;
-use common::snapshot::Snapshot;
+use common::player_state::PlayerState;
+use common::snapshot::DeltaSnapshot;
 use common::stream_writer::StreamWriter;
 use glm::Vec2;
 use std::collections::HashMap;
@@ -30,35 +32,66 @@ impl ClientEntityLink {
     }
 }
 
+/// Moves each client's baseline to the newest snapshot it acknowledged.
+pub fn acknowledge_snapshots(
+    mut messages: MessageReader<PacketResolved>,
+    mut clients: Query<&mut ConnectedClient>,
+) {
+    for packet_resolved in messages.read() {
+        if packet_resolved.status != PacketStatus::Delivered {
+            continue;
+        }
+        if let Some(mut client) = clients
+            .iter_mut()
+            .find(|client| client.net_id == packet_resolved.client_net_id)
+        {
+            client.snapshots.on_delivered(packet_resolved.sequence);
+        }
+    }
+}
+
+/// Takes a snapshot of the replicated nodes and sends it to each client as a
+/// delta against the last one it acknowledged, whole when there is none.
 pub fn handle_snapshots(
     network_manager: Res<NetworkManager>,
-    clients: Query<&ConnectedClient>,
-    replicated_nodes: Query<(&Transform, &Player, &Velocity)>,
+    mut clients: Query<&mut ConnectedClient>,
+    replicated_nodes: Query<(&Transform, &Player)>,
     input_manager: Res<InputManager>,
+    mut history: ResMut<SnapshotHistory>,
+    mut stream_writer: Local<StreamWriter<'static>>,
 ) {
-    let mut stream_writer = StreamWriter::new();
-
-    let message_header = MessageHeader::init(MessageType::Data, DataType::Replication);
-    stream_writer.write_serializable(message_header);
-
-    let mut snapshot = Snapshot::new(input_manager.server_frame);
+    let frame = input_manager.server_frame;
+    let nodes = replicated_nodes.iter().map(|(transform, player)| {
+        let state = PlayerState {
+            position: Vec2::new(transform.translation.x, transform.translation.y),
+            velocity: Vec2::new(player.velocity.x, player.velocity.y),
+            owner_id: player.owner_id,
+        };
+        (player.net_id, player.type_id, state)
+    });
+    history.push_from(frame, nodes);
 
-    for (transform, player, velocity) in replicated_nodes.iter() {
-        let mut sw = StreamWriter::new();
-        sw.write_vec2(Vec2::new(transform.translation.x, transform.translation.y));
-        sw.write_vec2(Vec2::new(velocity.linvel.x, velocity.linvel.y));
-        sw.write_serializable_ref(player);
-
-        snapshot.nodes.push(ReplicatedNode {
-            net_id: player.net_id,
-            type_id: player.type_id,
-            data: sw.get_data().to_vec(),
-        });
-    }
+    let Some(snapshot) = history.latest() else {
+        return;
+    };
+    for mut client in clients.iter_mut() {
+        let baseline = client
+            .snapshots
+            .acked_frame()
+            .and_then(|acked_frame| history.get(acked_frame));
 
-    stream_writer.write_serializable(snapshot);
+        stream_writer.clear();
+        let message_header = MessageHeader::init(MessageType::Data, DataType::Replication);
+        stream_writer.write_serializable(message_header);
+        DeltaSnapshot::write_from(
+            &mut stream_writer,
+            snapshot,
+            baseline,
+            client.inputs.last_simulated(),
+            client.inputs.lead(),
+        );
 
-    for client in clients.iter() {
-        network_manager.send_data(&client.address, stream_writer.get_data());
+        let sequence = network_manager.send_to_client(&mut client, stream_writer.get_data_mut());
+        client.snapshots.on_sent(sequence, frame);
     }
 }
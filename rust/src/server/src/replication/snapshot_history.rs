@@ -0,0 +1,96 @@
+﻿use bevy::prelude::Resource;
+use common::bit_writer::{BitSerializable, BitWriter};
+use common::replicated_node::ReplicatedNode;
+use common::snapshot::{BASELINE_HISTORY, Snapshot};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+
+/// Latest snapshots taken, for encoding deltas against what clients have.
+///
+/// Their nodes are sorted by `net_id`, as `DeltaSnapshot::write_from` needs,
+/// and the buffers of the snapshots falling out of the history are reused.
+#[derive(Resource, Default)]
+pub struct SnapshotHistory {
+    snapshots: VecDeque<Snapshot<'static>>,
+    /// Packed state buffers of the nodes of dropped snapshots.
+    spare_buffers: Vec<Vec<u8>>,
+}
+
+impl SnapshotHistory {
+    /// Takes the snapshot of `frame` from `(net_id, type_id, state)` entries.
+    pub fn push_from<I, T>(&mut self, frame: u32, nodes: I)
+    where
+        I: IntoIterator<Item = (u32, u32, T)>,
+        T: BitSerializable,
+    {
+        let oldest = if self.snapshots.len() >= BASELINE_HISTORY {
+            self.snapshots.pop_front()
+        } else {
+            None
+        };
+        let mut snapshot = oldest.unwrap_or_else(|| Snapshot::new(frame));
+        snapshot.frame = frame;
+        for node in snapshot.nodes.drain(..) {
+            if let Cow::Owned(buffer) = node.data {
+                self.spare_buffers.push(buffer);
+            }
+        }
+
+        for (net_id, type_id, state) in nodes {
+            let buffer = self.spare_buffers.pop().unwrap_or_default();
+            let mut bit_writer = BitWriter::with_buffer(buffer);
+            bit_writer.write_serializable_ref(&state);
+            snapshot.nodes.push(ReplicatedNode {
+                net_id,
+                type_id,
+                data: Cow::Owned(bit_writer.into_bytes()),
+            });
+        }
+        snapshot.nodes.sort_unstable_by_key(|node| node.net_id);
+
+        self.snapshots.push_back(snapshot);
+    }
+
+    pub fn latest(&self) -> Option<&Snapshot<'static>> {
+        self.snapshots.back()
+    }
+
+    pub fn get(&self, frame: u32) -> Option<&Snapshot<'static>> {
+        self.snapshots
+            .iter()
+            .find(|snapshot| snapshot.frame == frame)
+    }
+}
+
+/// Frames of the snapshots sent to one client by packet sequence, and the
+/// newest one it acknowledged.
+#[derive(Default)]
+pub struct SentSnapshots {
+    sent: VecDeque<(u16, u32)>,
+    acked_frame: Option<u32>,
+}
+
+impl SentSnapshots {
+    pub fn on_sent(&mut self, sequence: u16, frame: u32) {
+        if self.sent.len() >= BASELINE_HISTORY {
+            self.sent.pop_front();
+        }
+        self.sent.push_back((sequence, frame));
+    }
+
+    pub fn on_delivered(&mut self, sequence: u16) {
+        let Some(&(_, frame)) = self.sent.iter().find(|(sent, _)| *sent == sequence) else {
+            return;
+        };
+        if self
+            .acked_frame
+            .is_none_or(|acked_frame| frame > acked_frame)
+        {
+            self.acked_frame = Some(frame);
+        }
+    }
+
+    pub fn acked_frame(&self) -> Option<u32> {
+        self.acked_frame
+    }
+}